@@ -91,10 +91,22 @@ event NftBurned(
     uint256 timestamp
 );
 
-   /// @dev Only this hardcoded address can call mint_nft_release
+    event RelayerChanged(
+        address indexed previousRelayer,
+        address indexed newRelayer,
+        uint256 previousRelayerValidUntil
+    );
+
+    /// @dev Canister-controlled address allowed to call mint_nft_release
+    address public relayer;
+    /// @dev The rotated-out relayer stays valid until previousRelayerValidUntil so in-flight releases land
+    address public previousRelayer;
+    uint256 public previousRelayerValidUntil;
+
     modifier onlyRelayer() {
         require(
-            msg.sender == 0x6aa3a45c64B45BF5eb031A4Ae18059e36e674E9c,
+            msg.sender == relayer ||
+                (msg.sender == previousRelayer && block.timestamp <= previousRelayerValidUntil),
             "Not authorized"
         );
         _;
@@ -102,8 +114,24 @@ event NftBurned(
 
 
     /// ✅ Constructor: now takes an explicit owner argument (for OpenZeppelin v5+)
-    constructor(address initialOwner) ERC721("OrigynNFT", "ONFT") Ownable() {
+    constructor(address initialOwner, address initialRelayer) ERC721("OrigynNFT", "ONFT") Ownable() {
+        require(initialRelayer != address(0), "Invalid relayer");
+        relayer = initialRelayer;
         emit ContractInitialized(initialOwner, block.timestamp);
+        emit RelayerChanged(address(0), initialRelayer, 0);
+    }
+
+    /// @notice Hand over the relayer role; called by the current relayer during key rotation, or by the owner for recovery
+    function setRelayer(address newRelayer, uint256 gracePeriod) external {
+        require(msg.sender == relayer || msg.sender == owner(), "Not authorized");
+        require(newRelayer != address(0), "Invalid relayer");
+        require(newRelayer != relayer, "Already relayer");
+
+        previousRelayer = relayer;
+        previousRelayerValidUntil = block.timestamp + gracePeriod;
+        relayer = newRelayer;
+
+        emit RelayerChanged(previousRelayer, newRelayer, previousRelayerValidUntil);
     }
 
       function mint_nft_release(
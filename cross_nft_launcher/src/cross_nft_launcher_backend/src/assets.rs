@@ -0,0 +1,273 @@
+use candid::{CandidType, Decode, Encode, Nat, Principal};
+use ic_cdk::api::{caller, id, time};
+use ic_cdk::{query, update};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{StableBTreeMap, Storable};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use crate::certification::sha256;
+use crate::http;
+use crate::log;
+use crate::memory::{get_memory, Memory, ASSETS_MEMORY_ID};
+use crate::{is_minter, Value, TOKENS};
+
+// Ingress messages are capped at 2 MiB, so chunks leave room for the rest of the call
+const MAX_CHUNK_BYTES: usize = 1_900_000;
+// http_request has no streaming callback, so an asset must fit in one query response
+const MAX_ASSET_BYTES: usize = 2_500_000;
+const UPLOAD_TTL_NANOS: u64 = 60 * 60 * 1_000_000_000;
+const CONTENT_TYPES: [&str; 9] = [
+    "image/png",
+    "image/jpeg",
+    "image/gif",
+    "image/webp",
+    "image/svg+xml",
+    "video/mp4",
+    "video/webm",
+    "audio/mpeg",
+    "audio/wav",
+];
+
+/// A committed asset, content-addressed by the hex sha256 of its bytes
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct Asset {
+    pub content_type: String,
+    #[serde(with = "serde_bytes")]
+    pub content: Vec<u8>,
+    pub uploaded_by: Principal,
+    pub created_at: u64,
+}
+
+impl Storable for Asset {
+    fn to_bytes(&self) -> Cow<[u8]> {
+        Cow::Owned(Encode!(self).expect("failed to encode Asset"))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("failed to decode Asset")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct AssetInfo {
+    pub sha256: String,
+    pub content_type: String,
+    pub size: u64,
+    pub url: String,
+    pub created_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct CommitAssetArgs {
+    pub upload_id: u64,
+    pub content_type: String,
+    // Hex sha256 the client computed; the commit fails if the chunks don't match it
+    pub sha256: Option<String>,
+    // Token whose `image` metadata should point at the asset
+    pub token_id: Option<Nat>,
+}
+
+struct PendingUpload {
+    owner: Principal,
+    content: Vec<u8>,
+    updated_at: u64,
+}
+
+thread_local! {
+    // sha256 hex -> asset
+    static ASSETS: RefCell<StableBTreeMap<String, Asset, Memory>> = RefCell::new(
+        StableBTreeMap::init(get_memory(ASSETS_MEMORY_ID))
+    );
+
+    // Uploads still receiving chunks; dropped after UPLOAD_TTL_NANOS without activity
+    static UPLOADS: RefCell<HashMap<u64, PendingUpload>> = RefCell::new(HashMap::new());
+    static NEXT_UPLOAD_ID: RefCell<u64> = RefCell::new(0);
+}
+
+fn asset_path(hash: &str) -> String {
+    format!("/asset/{}", hash)
+}
+
+fn asset_url(hash: &str) -> String {
+    format!("https://{}.icp0.io{}", id(), asset_path(hash))
+}
+
+fn asset_info(hash: &str, asset: &Asset) -> AssetInfo {
+    AssetInfo {
+        sha256: hash.to_string(),
+        content_type: asset.content_type.clone(),
+        size: asset.content.len() as u64,
+        url: asset_url(hash),
+        created_at: asset.created_at,
+    }
+}
+
+pub fn asset(hash: &str) -> Option<Asset> {
+    ASSETS.with(|assets| assets.borrow().get(&hash.to_ascii_lowercase()))
+}
+
+/// Re-certify every stored asset; certified data does not survive an upgrade
+pub fn certify_all() {
+    let hashes: Vec<String> =
+        ASSETS.with(|assets| assets.borrow().iter().map(|(hash, _)| hash).collect());
+    for hash in hashes {
+        http::certify_path(&asset_path(&hash));
+    }
+}
+
+fn set_token_image(token_id: &Nat, url: String) -> Result<(), String> {
+    TOKENS.with(|tokens| {
+        let mut tokens = tokens.borrow_mut();
+        let token = tokens
+            .get_mut(&token_id.to_string())
+            .ok_or_else(|| format!("Token {} does not exist", token_id))?;
+        token.metadata.retain(|(key, _)| key != "image");
+        token.metadata.push(("image".to_string(), Value::Text(url)));
+        Ok(())
+    })
+}
+
+/// Append a chunk to an upload; pass `upload_id: None` to start a new one. Returns the upload id.
+#[update]
+pub fn upload_asset_chunk(upload_id: Option<u64>, chunk: Vec<u8>) -> Result<u64, String> {
+    let uploader = caller();
+    if !is_minter(&uploader) {
+        return Err("Unauthorized: only minters can upload assets".to_string());
+    }
+    if chunk.len() > MAX_CHUNK_BYTES {
+        return Err(format!(
+            "Chunk of {} bytes exceeds {} bytes",
+            chunk.len(),
+            MAX_CHUNK_BYTES
+        ));
+    }
+    let now = time();
+
+    UPLOADS.with(|uploads| {
+        let mut uploads = uploads.borrow_mut();
+        uploads.retain(|_, upload| upload.updated_at + UPLOAD_TTL_NANOS > now);
+
+        let upload_id = match upload_id {
+            Some(upload_id) => upload_id,
+            None => {
+                let upload_id = NEXT_UPLOAD_ID.with(|next| {
+                    let mut next = next.borrow_mut();
+                    *next += 1;
+                    *next
+                });
+                uploads.insert(
+                    upload_id,
+                    PendingUpload {
+                        owner: uploader,
+                        content: Vec::new(),
+                        updated_at: now,
+                    },
+                );
+                upload_id
+            }
+        };
+        let upload = uploads
+            .get_mut(&upload_id)
+            .filter(|upload| upload.owner == uploader)
+            .ok_or_else(|| format!("Upload {} not found or expired", upload_id))?;
+        if upload.content.len() + chunk.len() > MAX_ASSET_BYTES {
+            return Err(format!("Asset exceeds {} bytes", MAX_ASSET_BYTES));
+        }
+        upload.content.extend_from_slice(&chunk);
+        upload.updated_at = now;
+        Ok(upload_id)
+    })
+}
+
+/// Store an upload as a certified asset under its sha256, optionally pointing a token's image at it
+#[update]
+pub fn commit_asset(args: CommitAssetArgs) -> Result<AssetInfo, String> {
+    let uploader = caller();
+    if !is_minter(&uploader) {
+        return Err("Unauthorized: only minters can commit assets".to_string());
+    }
+    let content_type = args.content_type.trim().to_ascii_lowercase();
+    if !CONTENT_TYPES.contains(&content_type.as_str()) {
+        return Err(format!(
+            "Unsupported content type '{}'; expected one of {}",
+            args.content_type,
+            CONTENT_TYPES.join(", ")
+        ));
+    }
+    if let Some(token_id) = &args.token_id {
+        if !TOKENS.with(|tokens| tokens.borrow().contains_key(&token_id.to_string())) {
+            return Err(format!("Token {} does not exist", token_id));
+        }
+    }
+
+    let upload = UPLOADS
+        .with(|uploads| {
+            let mut uploads = uploads.borrow_mut();
+            let owned = uploads
+                .get(&args.upload_id)
+                .map_or(false, |upload| upload.owner == uploader);
+            if owned {
+                uploads.remove(&args.upload_id)
+            } else {
+                None
+            }
+        })
+        .ok_or_else(|| format!("Upload {} not found or expired", args.upload_id))?;
+    if upload.content.is_empty() {
+        return Err(format!("Upload {} has no content", args.upload_id));
+    }
+
+    let hash = hex::encode(sha256(&upload.content));
+    if let Some(expected) = &args.sha256 {
+        if !expected
+            .trim_start_matches("0x")
+            .eq_ignore_ascii_case(&hash)
+        {
+            return Err(format!(
+                "Content hash {} does not match expected {}",
+                hash, expected
+            ));
+        }
+    }
+
+    // Identical bytes are stored once; the first upload's content type wins
+    let asset = match asset(&hash) {
+        Some(existing) => existing,
+        None => {
+            let asset = Asset {
+                content_type,
+                content: upload.content,
+                uploaded_by: uploader,
+                created_at: time(),
+            };
+            ASSETS.with(|assets| assets.borrow_mut().insert(hash.clone(), asset.clone()));
+            http::certify_path(&asset_path(&hash));
+            asset
+        }
+    };
+
+    if let Some(token_id) = &args.token_id {
+        set_token_image(token_id, asset_url(&hash))?;
+        http::certify_token(token_id);
+    }
+    log!(
+        Info,
+        Metadata,
+        "Stored asset {} ({}, {} bytes)",
+        hash,
+        asset.content_type,
+        asset.content.len()
+    );
+    Ok(asset_info(&hash, &asset))
+}
+
+#[query]
+pub fn get_asset_info(sha256: String) -> Option<AssetInfo> {
+    let hash = sha256.trim_start_matches("0x").to_ascii_lowercase();
+    asset(&hash).map(|asset| asset_info(&hash, &asset))
+}
@@ -0,0 +1,321 @@
+use candid::{CandidType, Principal};
+use ic_cdk::api::{caller, time};
+use ic_cdk::{query, update};
+use ic_stable_structures::{StableBTreeMap, StableCell};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use crate::ensure_admin;
+use crate::log;
+use crate::memory::{
+    candid_storable, get_memory, Memory, BRIDGE_PAUSES_MEMORY_ID, BURN_WINDOWS_MEMORY_ID,
+    CIRCUIT_BREAKER_CONFIG_MEMORY_ID, RELEASE_FAILURES_MEMORY_ID,
+};
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+
+#[derive(
+    CandidType, Deserialize, Clone, Copy, Debug, Serialize, PartialEq, Eq, PartialOrd, Ord,
+)]
+pub enum BridgeDirection {
+    EvmToIcp,
+    IcpToEvm,
+}
+
+#[derive(
+    CandidType, Deserialize, Clone, Copy, Debug, Serialize, PartialEq, Eq, PartialOrd, Ord,
+)]
+pub enum BridgeOperation {
+    Index,
+    Mint,
+    Release,
+}
+
+#[derive(
+    CandidType, Deserialize, Clone, Copy, Debug, Serialize, PartialEq, Eq, PartialOrd, Ord,
+)]
+pub enum PauseScope {
+    Global,
+    Chain(u64),
+    Direction(BridgeDirection),
+    Operation(BridgeOperation),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub enum PauseSource {
+    Admin(Principal),
+    CircuitBreaker,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct PauseRecord {
+    pub scope: PauseScope,
+    pub reason: String,
+    pub source: PauseSource,
+    pub paused_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct CircuitBreakerConfig {
+    pub enabled: bool,
+    pub burn_window_secs: u64,
+    pub max_burns_per_window: u32,
+    pub max_consecutive_release_failures: u32,
+}
+
+impl Default for CircuitBreakerConfig {
+    fn default() -> Self {
+        CircuitBreakerConfig {
+            enabled: true,
+            burn_window_secs: 3_600,
+            max_burns_per_window: 50,
+            max_consecutive_release_failures: 3,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct ChainCounters {
+    pub chain_id: u64,
+    pub burns_in_window: u32,
+    pub consecutive_release_failures: u32,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct BridgeStatus {
+    pub paused: Vec<PauseRecord>,
+    pub circuit_breaker: CircuitBreakerConfig,
+    pub counters: Vec<ChainCounters>,
+}
+
+// Burn timestamps (ns) of one source chain inside the current window, oldest first
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+struct BurnWindow(Vec<u64>);
+
+candid_storable!(PauseScope, PauseRecord, CircuitBreakerConfig, BurnWindow);
+
+thread_local! {
+    // Pauses and breaker state survive upgrades, so an upgrade never silently resumes the bridge
+    static PAUSES: RefCell<StableBTreeMap<PauseScope, PauseRecord, Memory>> = RefCell::new(
+        StableBTreeMap::init(get_memory(BRIDGE_PAUSES_MEMORY_ID))
+    );
+
+    static CIRCUIT_BREAKER_CONFIG: RefCell<StableCell<CircuitBreakerConfig, Memory>> = RefCell::new(
+        StableCell::init(get_memory(CIRCUIT_BREAKER_CONFIG_MEMORY_ID), CircuitBreakerConfig::default())
+            .expect("failed to init circuit breaker config")
+    );
+
+    static BURN_WINDOWS: RefCell<StableBTreeMap<u64, BurnWindow, Memory>> = RefCell::new(
+        StableBTreeMap::init(get_memory(BURN_WINDOWS_MEMORY_ID))
+    );
+
+    static RELEASE_FAILURES: RefCell<StableBTreeMap<u64, u32, Memory>> = RefCell::new(
+        StableBTreeMap::init(get_memory(RELEASE_FAILURES_MEMORY_ID))
+    );
+}
+
+fn circuit_breaker_config() -> CircuitBreakerConfig {
+    CIRCUIT_BREAKER_CONFIG.with(|c| c.borrow().get().clone())
+}
+
+/// Returns an error if any pause covering this chain, direction or operation is active
+pub fn ensure_active(
+    chain_id: u64,
+    direction: BridgeDirection,
+    operation: BridgeOperation,
+) -> Result<(), String> {
+    let scopes = [
+        PauseScope::Global,
+        PauseScope::Chain(chain_id),
+        PauseScope::Direction(direction),
+        PauseScope::Operation(operation),
+    ];
+
+    PAUSES.with(|pauses| {
+        let pauses = pauses.borrow();
+        for scope in scopes.iter() {
+            if let Some(record) = pauses.get(scope) {
+                return Err(format!(
+                    "Bridge paused ({:?}) for {:?} on chain {}: {}",
+                    record.scope, operation, chain_id, record.reason
+                ));
+            }
+        }
+        Ok(())
+    })
+}
+
+fn trip(scope: PauseScope, reason: String) {
+    log!(
+        Error,
+        Release,
+        "Circuit breaker tripped for {:?}: {}",
+        scope,
+        reason
+    );
+    PAUSES.with(|pauses| {
+        let mut pauses = pauses.borrow_mut();
+        if !pauses.contains_key(&scope) {
+            pauses.insert(
+                scope,
+                PauseRecord {
+                    scope,
+                    reason,
+                    source: PauseSource::CircuitBreaker,
+                    paused_at: time(),
+                },
+            );
+        }
+    });
+}
+
+/// Record a burn observed on `chain_id`; trips the chain breaker on unusual volume
+pub fn record_burn(chain_id: u64) -> Result<(), String> {
+    let config = circuit_breaker_config();
+    let now = time();
+    // A window longer than the clock saturates to counting every recorded burn
+    let window_start = now.saturating_sub(config.burn_window_secs.saturating_mul(NANOS_PER_SEC));
+
+    let burns_in_window = BURN_WINDOWS.with(|windows| {
+        let mut windows = windows.borrow_mut();
+        let mut window = windows.get(&chain_id).unwrap_or_default();
+        window.0.retain(|ts| *ts >= window_start);
+        window.0.push(now);
+        let burns = window.0.len() as u32;
+        windows.insert(chain_id, window);
+        burns
+    });
+
+    if config.enabled && burns_in_window > config.max_burns_per_window {
+        let reason = format!(
+            "{} burns within {}s exceeds limit of {}",
+            burns_in_window, config.burn_window_secs, config.max_burns_per_window
+        );
+        trip(PauseScope::Chain(chain_id), reason.clone());
+        return Err(reason);
+    }
+
+    Ok(())
+}
+
+/// Record the outcome of a release on `chain_id`; trips the chain breaker on repeated failures
+pub fn record_release_result(chain_id: u64, succeeded: bool) {
+    let config = circuit_breaker_config();
+
+    let failures = RELEASE_FAILURES.with(|failures| {
+        let mut failures = failures.borrow_mut();
+        let count = if succeeded {
+            0
+        } else {
+            failures.get(&chain_id).unwrap_or(0) + 1
+        };
+        failures.insert(chain_id, count);
+        count
+    });
+
+    if config.enabled && failures >= config.max_consecutive_release_failures {
+        trip(
+            PauseScope::Chain(chain_id),
+            format!("{} consecutive release failures", failures),
+        );
+    }
+}
+
+fn clear<V: ic_stable_structures::Storable>(map: &mut StableBTreeMap<u64, V, Memory>) {
+    let chain_ids: Vec<u64> = map.iter().map(|(chain_id, _)| chain_id).collect();
+    for chain_id in chain_ids {
+        map.remove(&chain_id);
+    }
+}
+
+fn reset_counters(scope: &PauseScope) {
+    match scope {
+        PauseScope::Chain(chain_id) => {
+            BURN_WINDOWS.with(|w| w.borrow_mut().remove(chain_id));
+            RELEASE_FAILURES.with(|f| f.borrow_mut().remove(chain_id));
+        }
+        _ => {
+            BURN_WINDOWS.with(|w| clear(&mut w.borrow_mut()));
+            RELEASE_FAILURES.with(|f| clear(&mut f.borrow_mut()));
+        }
+    }
+}
+
+#[update]
+pub fn pause_bridge(scope: PauseScope, reason: String) -> Result<(), String> {
+    ensure_admin()?;
+    log!(Warn, Release, "Pausing bridge for {:?}: {}", scope, reason);
+    PAUSES.with(|pauses| {
+        pauses.borrow_mut().insert(
+            scope,
+            PauseRecord {
+                scope,
+                reason,
+                source: PauseSource::Admin(caller()),
+                paused_at: time(),
+            },
+        );
+    });
+    Ok(())
+}
+
+#[update]
+pub fn unpause_bridge(scope: PauseScope) -> Result<(), String> {
+    ensure_admin()?;
+    let removed = PAUSES.with(|pauses| pauses.borrow_mut().remove(&scope));
+    if removed.is_none() {
+        return Err(format!("Bridge is not paused for {:?}", scope));
+    }
+    reset_counters(&scope);
+    log!(Info, Release, "Unpaused bridge for {:?}", scope);
+    Ok(())
+}
+
+#[update]
+pub fn set_circuit_breaker_config(config: CircuitBreakerConfig) -> Result<(), String> {
+    ensure_admin()?;
+    CIRCUIT_BREAKER_CONFIG
+        .with(|c| c.borrow_mut().set(config))
+        .map_err(|e| format!("Failed to store circuit breaker config: {:?}", e))?;
+    Ok(())
+}
+
+#[query]
+pub fn get_bridge_status() -> BridgeStatus {
+    let circuit_breaker = circuit_breaker_config();
+    let window_start = time().saturating_sub(
+        circuit_breaker
+            .burn_window_secs
+            .saturating_mul(NANOS_PER_SEC),
+    );
+
+    let mut counters: BTreeMap<u64, ChainCounters> = BTreeMap::new();
+    BURN_WINDOWS.with(|windows| {
+        for (chain_id, window) in windows.borrow().iter() {
+            let entry = counters.entry(chain_id).or_insert(ChainCounters {
+                chain_id,
+                burns_in_window: 0,
+                consecutive_release_failures: 0,
+            });
+            entry.burns_in_window =
+                window.0.iter().filter(|ts| **ts >= window_start).count() as u32;
+        }
+    });
+    RELEASE_FAILURES.with(|failures| {
+        for (chain_id, count) in failures.borrow().iter() {
+            let entry = counters.entry(chain_id).or_insert(ChainCounters {
+                chain_id,
+                burns_in_window: 0,
+                consecutive_release_failures: 0,
+            });
+            entry.consecutive_release_failures = count;
+        }
+    });
+
+    BridgeStatus {
+        paused: PAUSES.with(|pauses| pauses.borrow().iter().map(|(_, record)| record).collect()),
+        circuit_breaker,
+        counters: counters.into_values().collect(),
+    }
+}
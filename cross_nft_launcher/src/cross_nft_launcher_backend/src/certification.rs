@@ -0,0 +1,566 @@
+use base64::Engine;
+use ciborium::value::Value as Cbor;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::cmp::Ordering;
+use std::collections::BTreeMap;
+
+pub type Hash = [u8; 32];
+
+/// IC hash tree, as certified through `set_certified_data` and sent in certificates
+#[derive(Clone, Debug)]
+pub enum HashTree {
+    Empty,
+    Fork(Box<HashTree>, Box<HashTree>),
+    Labeled(Vec<u8>, Box<HashTree>),
+    Leaf(Vec<u8>),
+    Pruned(Hash),
+}
+
+fn domain_hash(domain: &str, parts: &[&[u8]]) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([domain.len() as u8]);
+    hasher.update(domain.as_bytes());
+    for part in parts {
+        hasher.update(part);
+    }
+    hasher.finalize().into()
+}
+
+pub fn sha256(bytes: &[u8]) -> Hash {
+    Sha256::digest(bytes).into()
+}
+
+impl HashTree {
+    pub fn labeled(label: &[u8], tree: HashTree) -> Self {
+        HashTree::Labeled(label.to_vec(), Box::new(tree))
+    }
+
+    pub fn digest(&self) -> Hash {
+        match self {
+            HashTree::Empty => domain_hash("ic-hashtree-empty", &[]),
+            HashTree::Fork(left, right) => {
+                domain_hash("ic-hashtree-fork", &[&left.digest(), &right.digest()])
+            }
+            HashTree::Labeled(label, tree) => {
+                domain_hash("ic-hashtree-labeled", &[label, &tree.digest()])
+            }
+            HashTree::Leaf(value) => domain_hash("ic-hashtree-leaf", &[value]),
+            HashTree::Pruned(hash) => *hash,
+        }
+    }
+
+    pub fn to_cbor(&self) -> Cbor {
+        let tag = |n: u8| Cbor::Integer(n.into());
+        match self {
+            HashTree::Empty => Cbor::Array(vec![tag(0)]),
+            HashTree::Fork(left, right) => {
+                Cbor::Array(vec![tag(1), left.to_cbor(), right.to_cbor()])
+            }
+            HashTree::Labeled(label, tree) => {
+                Cbor::Array(vec![tag(2), Cbor::Bytes(label.clone()), tree.to_cbor()])
+            }
+            HashTree::Leaf(value) => Cbor::Array(vec![tag(3), Cbor::Bytes(value.clone())]),
+            HashTree::Pruned(hash) => Cbor::Array(vec![tag(4), Cbor::Bytes(hash.to_vec())]),
+        }
+    }
+}
+
+/// Node of a label-ordered treap; each node caches the hash of its subtree, so inserting or
+/// removing a path only rehashes the nodes along it
+struct Node {
+    label: Vec<u8>,
+    // sha256 of the label: a deterministic priority that keeps the treap balanced
+    priority: Hash,
+    value: Subtree,
+    left: Map,
+    right: Map,
+    hash: Hash,
+}
+
+type Map = Option<Box<Node>>;
+
+enum Subtree {
+    Leaf(Vec<u8>),
+    Map(Map),
+}
+
+fn map_hash(map: &Map) -> Hash {
+    map.as_ref()
+        .map_or_else(|| HashTree::Empty.digest(), |node| node.hash)
+}
+
+fn pruned(map: &Map) -> Option<HashTree> {
+    map.as_ref().map(|node| HashTree::Pruned(node.hash))
+}
+
+/// Fork a node's entry in between its subtrees, leaving out empty ones
+fn node_tree(left: Option<HashTree>, entry: HashTree, right: Option<HashTree>) -> HashTree {
+    let with_left = match left {
+        Some(left) => HashTree::Fork(Box::new(left), Box::new(entry)),
+        None => entry,
+    };
+    match right {
+        Some(right) => HashTree::Fork(Box::new(with_left), Box::new(right)),
+        None => with_left,
+    }
+}
+
+impl Subtree {
+    fn digest(&self) -> Hash {
+        match self {
+            Subtree::Leaf(value) => HashTree::Leaf(value.clone()).digest(),
+            Subtree::Map(map) => map_hash(map),
+        }
+    }
+}
+
+impl Node {
+    fn new(label: &[u8], value: Subtree) -> Box<Node> {
+        let mut node = Box::new(Node {
+            label: label.to_vec(),
+            priority: sha256(label),
+            value,
+            left: None,
+            right: None,
+            hash: [0; 32],
+        });
+        node.rehash();
+        node
+    }
+
+    fn entry_hash(&self) -> Hash {
+        domain_hash("ic-hashtree-labeled", &[&self.label, &self.value.digest()])
+    }
+
+    fn rehash(&mut self) {
+        self.hash = node_tree(
+            pruned(&self.left),
+            HashTree::Pruned(self.entry_hash()),
+            pruned(&self.right),
+        )
+        .digest();
+    }
+}
+
+/// Split `map` into the labels below `label`, the node for `label` and the labels above it
+fn split(map: Map, label: &[u8]) -> (Map, Map, Map) {
+    let Some(mut node) = map else {
+        return (None, None, None);
+    };
+    match label.cmp(&node.label) {
+        Ordering::Equal => {
+            let (below, above) = (node.left.take(), node.right.take());
+            (below, Some(node), above)
+        }
+        Ordering::Less => {
+            let (below, found, above) = split(node.left.take(), label);
+            node.left = above;
+            node.rehash();
+            (below, found, Some(node))
+        }
+        Ordering::Greater => {
+            let (below, found, above) = split(node.right.take(), label);
+            node.right = below;
+            node.rehash();
+            (Some(node), found, above)
+        }
+    }
+}
+
+/// Join two maps where every label of `left` sorts before those of `right`
+fn merge(left: Map, right: Map) -> Map {
+    match (left, right) {
+        (None, map) | (map, None) => map,
+        (Some(mut left), Some(mut right)) => {
+            if left.priority >= right.priority {
+                left.right = merge(left.right.take(), Some(right));
+                left.rehash();
+                Some(left)
+            } else {
+                right.left = merge(Some(left), right.left.take());
+                right.rehash();
+                Some(right)
+            }
+        }
+    }
+}
+
+/// Replace the value under `label` with `f` of the current one; None removes the label
+fn update(map: Map, label: &[u8], f: impl FnOnce(Option<Subtree>) -> Option<Subtree>) -> Map {
+    let (below, found, above) = split(map, label);
+    let node = f(found.map(|node| node.value)).map(|value| Node::new(label, value));
+    merge(merge(below, node), above)
+}
+
+/// Add a path ending in an empty leaf
+fn insert_path(map: Map, path: &[Vec<u8>]) -> Map {
+    update(map, &path[0], |current| {
+        Some(if path.len() == 1 {
+            Subtree::Leaf(vec![])
+        } else {
+            let child = match current {
+                Some(Subtree::Map(child)) => child,
+                _ => None,
+            };
+            Subtree::Map(insert_path(child, &path[1..]))
+        })
+    })
+}
+
+/// Remove a path, dropping labels left without any leaf below them
+fn remove_path(map: Map, path: &[Vec<u8>]) -> Map {
+    update(map, &path[0], |current| match current {
+        Some(Subtree::Map(child)) if path.len() > 1 => {
+            remove_path(child, &path[1..]).map(|child| Subtree::Map(Some(child)))
+        }
+        Some(_) if path.len() == 1 => None,
+        current => current,
+    })
+}
+
+/// Tree revealing only the branch along `path`; every sibling is pruned to its hash
+fn witness(map: &Map, path: &[Vec<u8>]) -> HashTree {
+    let Some(node) = map else {
+        return HashTree::Empty;
+    };
+    let entry = || HashTree::Pruned(node.entry_hash());
+    match path[0].cmp(&node.label) {
+        Ordering::Less => node_tree(
+            Some(witness(&node.left, path)),
+            entry(),
+            pruned(&node.right),
+        ),
+        Ordering::Greater => node_tree(
+            pruned(&node.left),
+            entry(),
+            Some(witness(&node.right, path)),
+        ),
+        Ordering::Equal => {
+            let value = match &node.value {
+                Subtree::Leaf(value) => HashTree::Leaf(value.clone()),
+                Subtree::Map(child) if path.len() > 1 => witness(child, &path[1..]),
+                Subtree::Map(child) => HashTree::Pruned(map_hash(child)),
+            };
+            node_tree(
+                pruned(&node.left),
+                HashTree::labeled(&node.label, value),
+                pruned(&node.right),
+            )
+        }
+    }
+}
+
+// Response certification v2: every certified response shares this expression, which
+// skips the request and covers the status, body and Content-Type
+pub const HTTP_CERT_EXPRESSION: &str = "default_certification(ValidationArgs{certification:Certification{no_request_certification:Empty{},response_certification:ResponseCertification{certified_response_headers:ResponseHeaderList{headers:[\"content-type\"]}}}})";
+const CERTIFIED_HEADERS: [&str; 1] = ["content-type"];
+// For responses that change between certifications; gateways pass them through unverified
+pub const HTTP_SKIP_EXPRESSION: &str =
+    "default_certification(ValidationArgs{no_certification:Empty{}})";
+
+thread_local! {
+    // Canister signatures: sha256(seed) -> sha256(message) -> expiry
+    static SIGNATURES: RefCell<BTreeMap<Hash, BTreeMap<Hash, u64>>> = RefCell::new(BTreeMap::new());
+
+    // URL path -> hash of the response certified for it; None for paths that skip certification
+    static HTTP_RESPONSES: RefCell<BTreeMap<String, Option<Hash>>> = RefCell::new(BTreeMap::new());
+
+    // Every path above, kept hashed so certifying one response does not rehash the rest
+    static TREE: RefCell<Map> = RefCell::new(None);
+}
+
+fn signature_path(seed_hash: &Hash, message_hash: &Hash) -> Vec<Vec<u8>> {
+    vec![b"sig".to_vec(), seed_hash.to_vec(), message_hash.to_vec()]
+}
+
+/// `http_expr` labels for an exact URL path: "/asset/ab" -> ["http_expr", "asset", "ab", "<$>"]
+fn http_expr_path(url_path: &str) -> Vec<Vec<u8>> {
+    std::iter::once("http_expr")
+        .chain(url_path.strip_prefix('/').unwrap_or(url_path).split('/'))
+        .chain(std::iter::once("<$>"))
+        .map(|label| label.as_bytes().to_vec())
+        .collect()
+}
+
+fn http_response_path(url_path: &str, response_hash: Option<&Hash>) -> Vec<Vec<u8>> {
+    let mut path = http_expr_path(url_path);
+    match response_hash {
+        Some(response_hash) => {
+            path.push(sha256(HTTP_CERT_EXPRESSION.as_bytes()).to_vec());
+            // Empty request hash: requests are not certified
+            path.push(vec![]);
+            path.push(response_hash.to_vec());
+        }
+        None => path.push(sha256(HTTP_SKIP_EXPRESSION.as_bytes()).to_vec()),
+    }
+    path
+}
+
+/// Apply `f` to the certified tree and publish its new root hash
+fn update_tree(f: impl FnOnce(Map) -> Map) {
+    let root = TREE.with(|tree| {
+        let mut tree = tree.borrow_mut();
+        *tree = f(tree.take());
+        map_hash(&tree)
+    });
+    ic_cdk::api::set_certified_data(&root);
+}
+
+fn tree_witness(path: &[Vec<u8>]) -> HashTree {
+    TREE.with(|tree| witness(&tree.borrow(), path))
+}
+
+fn encode_cbor(value: &Cbor) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::new();
+    ciborium::ser::into_writer(&Cbor::Tag(55799, Box::new(value.clone())), &mut bytes)
+        .map_err(|e| format!("Failed to encode CBOR: {}", e))?;
+    Ok(bytes)
+}
+
+/// Certify `message` as signed by the canister signature key derived from `seed`
+pub fn add_signature(seed: &[u8], message: &[u8], expires_at: u64, now: u64) {
+    let seed_hash = sha256(seed);
+    let message_hash = sha256(message);
+    let expired: Vec<Vec<Vec<u8>>> = SIGNATURES.with(|signatures| {
+        let mut signatures = signatures.borrow_mut();
+        let mut expired = Vec::new();
+        for (seed_hash, messages) in signatures.iter_mut() {
+            messages.retain(|message_hash, expiry| {
+                let keep = *expiry > now;
+                if !keep {
+                    expired.push(signature_path(seed_hash, message_hash));
+                }
+                keep
+            });
+        }
+        signatures.retain(|_, messages| !messages.is_empty());
+        signatures
+            .entry(seed_hash)
+            .or_default()
+            .insert(message_hash, expires_at);
+        expired
+    });
+    update_tree(|mut tree| {
+        for path in &expired {
+            tree = remove_path(tree, path);
+        }
+        insert_path(tree, &signature_path(&seed_hash, &message_hash))
+    });
+}
+
+/// CBOR canister signature over `message`, valid only in query calls where a certificate exists
+pub fn signature(seed: &[u8], message: &[u8]) -> Result<Vec<u8>, String> {
+    let seed_hash = sha256(seed);
+    let message_hash = sha256(message);
+    let known = SIGNATURES.with(|signatures| {
+        signatures
+            .borrow()
+            .get(&seed_hash)
+            .map_or(false, |messages| messages.contains_key(&message_hash))
+    });
+    if !known {
+        return Err("No signature found for this delegation".to_string());
+    }
+    let certificate = ic_cdk::api::data_certificate()
+        .ok_or_else(|| "Signatures are only available in query calls".to_string())?;
+
+    let witness = tree_witness(&signature_path(&seed_hash, &message_hash));
+    encode_cbor(&Cbor::Map(vec![
+        (
+            Cbor::Text("certificate".to_string()),
+            Cbor::Bytes(certificate),
+        ),
+        (Cbor::Text("tree".to_string()), witness.to_cbor()),
+    ]))
+}
+
+pub fn leb128(mut n: u64) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    loop {
+        let byte = (n & 0x7f) as u8;
+        n >>= 7;
+        if n == 0 {
+            bytes.push(byte);
+            return bytes;
+        }
+        bytes.push(byte | 0x80);
+    }
+}
+
+/// Response hash of the v2 spec: representation-independent hash of the certified headers,
+/// the expression header and the status pseudo-header, followed by the body hash
+fn response_hash(status_code: u16, headers: &[(String, String)], body: &[u8]) -> Hash {
+    let mut fields: Vec<Vec<u8>> = headers
+        .iter()
+        .filter(|(name, _)| CERTIFIED_HEADERS.contains(&name.to_ascii_lowercase().as_str()))
+        .map(|(name, value)| {
+            [
+                sha256(name.to_ascii_lowercase().as_bytes()),
+                sha256(value.as_bytes()),
+            ]
+            .concat()
+        })
+        .collect();
+    fields.push(
+        [
+            sha256(b"ic-certificateexpression"),
+            sha256(HTTP_CERT_EXPRESSION.as_bytes()),
+        ]
+        .concat(),
+    );
+    fields.push(
+        [
+            sha256(b":ic-cert-status"),
+            sha256(&leb128(status_code as u64)),
+        ]
+        .concat(),
+    );
+    fields.sort();
+    let headers_hash = sha256(&fields.concat());
+    sha256(&[headers_hash, sha256(body)].concat())
+}
+
+/// Certify the response served for `url_path`, replacing any earlier one
+pub fn certify_response(
+    url_path: &str,
+    status_code: u16,
+    headers: &[(String, String)],
+    body: &[u8],
+) {
+    let hash = response_hash(status_code, headers, body);
+    set_response(url_path, Some(Some(hash)));
+}
+
+/// Serve `url_path` without certification, e.g. for content that changes on every update
+pub fn skip_certification(url_path: &str) {
+    set_response(url_path, Some(None));
+}
+
+pub fn remove_response(url_path: &str) {
+    set_response(url_path, None);
+}
+
+/// Swap the tree path of `url_path` for one certifying `response`; None removes it
+fn set_response(url_path: &str, response: Option<Option<Hash>>) {
+    let previous = HTTP_RESPONSES.with(|responses| {
+        let mut responses = responses.borrow_mut();
+        match response {
+            Some(hash) => responses.insert(url_path.to_string(), hash),
+            None => responses.remove(url_path),
+        }
+    });
+    if previous == response {
+        return;
+    }
+    update_tree(|mut tree| {
+        if let Some(hash) = previous {
+            tree = remove_path(tree, &http_response_path(url_path, hash.as_ref()));
+        }
+        match response {
+            Some(hash) => insert_path(tree, &http_response_path(url_path, hash.as_ref())),
+            None => tree,
+        }
+    });
+}
+
+pub fn expression_header() -> (String, String) {
+    (
+        "IC-CertificateExpression".to_string(),
+        HTTP_CERT_EXPRESSION.to_string(),
+    )
+}
+
+pub fn skip_expression_header() -> (String, String) {
+    (
+        "IC-CertificateExpression".to_string(),
+        HTTP_SKIP_EXPRESSION.to_string(),
+    )
+}
+
+/// IC-Certificate header for a certified `url_path`; None outside query calls or for
+/// paths that were never certified
+pub fn certificate_header(url_path: &str) -> Option<(String, String)> {
+    let hash = HTTP_RESPONSES.with(|responses| responses.borrow().get(url_path).copied())?;
+    let certificate = ic_cdk::api::data_certificate()?;
+    let witness = tree_witness(&http_response_path(url_path, hash.as_ref()));
+    let expr_path = Cbor::Array(
+        http_expr_path(url_path)
+            .into_iter()
+            .map(|label| Cbor::Text(String::from_utf8_lossy(&label).into_owned()))
+            .collect(),
+    );
+    let b64 = |bytes: &[u8]| base64::engine::general_purpose::STANDARD.encode(bytes);
+    Some((
+        "IC-Certificate".to_string(),
+        format!(
+            "certificate=:{}:, tree=:{}:, expr_path=:{}:, version=2",
+            b64(&certificate),
+            b64(&encode_cbor(&witness.to_cbor()).ok()?),
+            b64(&encode_cbor(&expr_path).ok()?)
+        ),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path(labels: &[&str]) -> Vec<Vec<u8>> {
+        labels
+            .iter()
+            .map(|label| label.as_bytes().to_vec())
+            .collect()
+    }
+
+    fn lookup<'a>(tree: &'a HashTree, label: &[u8]) -> Option<&'a HashTree> {
+        match tree {
+            HashTree::Labeled(l, subtree) if l.as_slice() == label => Some(subtree),
+            HashTree::Fork(left, right) => lookup(left, label).or_else(|| lookup(right, label)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn root_hash_depends_only_on_paths() {
+        let paths: Vec<_> = (0..200)
+            .map(|i| path(&["http_expr", "token", &i.to_string(), "<$>"]))
+            .collect();
+        let mut forward = None;
+        for p in &paths {
+            forward = insert_path(forward, p);
+        }
+        let mut backward = insert_path(None, &path(&["sig", "a", "b"]));
+        for p in paths.iter().rev() {
+            backward = insert_path(backward, p);
+        }
+        backward = remove_path(backward, &path(&["sig", "a", "b"]));
+        assert_eq!(map_hash(&forward), map_hash(&backward));
+
+        for p in &paths[1..] {
+            forward = remove_path(forward, p);
+        }
+        let single = insert_path(None, &paths[0]);
+        assert_eq!(map_hash(&forward), map_hash(&single));
+        assert_eq!(
+            map_hash(&remove_path(forward, &paths[0])),
+            HashTree::Empty.digest()
+        );
+    }
+
+    #[test]
+    fn witness_reveals_only_the_requested_path() {
+        let mut tree = None;
+        for i in 0..50 {
+            tree = insert_path(tree, &path(&["sig", &i.to_string(), "m"]));
+        }
+        let wanted = path(&["sig", "17", "m"]);
+        let witness = witness(&tree, &wanted);
+        assert_eq!(witness.digest(), map_hash(&tree));
+
+        let sig = lookup(&witness, b"sig").expect("sig label");
+        let seed = lookup(sig, b"17").expect("seed label");
+        assert!(matches!(lookup(seed, b"m"), Some(HashTree::Leaf(value)) if value.is_empty()));
+        assert!(lookup(sig, b"18").is_none());
+    }
+}
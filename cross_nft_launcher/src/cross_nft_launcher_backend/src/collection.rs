@@ -0,0 +1,327 @@
+use candid::{CandidType, Nat, Principal};
+use ic_cdk::{query, update};
+use ic_stable_structures::StableCell;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+
+use crate::http;
+use crate::memory::{candid_storable, get_memory, Memory, COLLECTION_CONFIG_MEMORY_ID};
+use crate::{ensure_admin, Account, Value, TOKENS};
+
+// Limits applied when the config leaves a value unset
+const DEFAULT_MAX_QUERY_BATCH_SIZE: u16 = 100;
+const DEFAULT_MAX_UPDATE_BATCH_SIZE: u16 = 20;
+const DEFAULT_TAKE_VALUE: u64 = 10;
+const DEFAULT_MAX_TAKE_VALUE: u64 = 100;
+// Bridge mints carry "Bridge from EVM: <tx hash>", 83 bytes
+const DEFAULT_MAX_MEMO_SIZE: u32 = 128;
+const DEFAULT_TX_WINDOW_SECS: u64 = 24 * 3_600;
+const DEFAULT_PERMITTED_DRIFT_SECS: u64 = 120;
+
+/// Collection-level ICRC-7 settings; `tx_window` and `permitted_drift` are in seconds
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct CollectionConfig {
+    pub name: String,
+    pub symbol: String,
+    pub description: Option<String>,
+    pub logo: Option<String>,
+    pub supply_cap: Option<Nat>,
+    pub max_query_batch_size: Option<u16>,
+    pub max_update_batch_size: Option<u16>,
+    pub default_take_value: Option<Nat>,
+    pub max_take_value: Option<Nat>,
+    pub max_memo_size: Option<u32>,
+    pub tx_window: Option<u64>,
+    pub permitted_drift: Option<u64>,
+    // Allowed to mint in addition to the controllers and the canister itself
+    pub minting_account: Option<Account>,
+}
+
+impl Default for CollectionConfig {
+    fn default() -> Self {
+        CollectionConfig {
+            name: "Cross NFT Launcher".to_string(),
+            symbol: "XNFT".to_string(),
+            description: None,
+            logo: None,
+            supply_cap: None,
+            max_query_batch_size: None,
+            max_update_batch_size: None,
+            default_take_value: None,
+            max_take_value: None,
+            max_memo_size: None,
+            tx_window: None,
+            permitted_drift: None,
+            minting_account: None,
+        }
+    }
+}
+
+candid_storable!(CollectionConfig);
+
+thread_local! {
+    static COLLECTION_CONFIG: RefCell<StableCell<CollectionConfig, Memory>> = RefCell::new(
+        StableCell::init(get_memory(COLLECTION_CONFIG_MEMORY_ID), CollectionConfig::default())
+            .expect("failed to init collection config")
+    );
+}
+
+fn nat_to_u64(n: &Nat) -> u64 {
+    u64::try_from(&n.0).unwrap_or(u64::MAX)
+}
+
+pub fn config() -> CollectionConfig {
+    COLLECTION_CONFIG.with(|c| c.borrow().get().clone())
+}
+
+fn validate(config: &CollectionConfig) -> Result<(), String> {
+    if config.name.trim().is_empty() || config.symbol.trim().is_empty() {
+        return Err("Collection name and symbol must not be empty".to_string());
+    }
+    let default_take = config
+        .default_take_value
+        .as_ref()
+        .map_or(DEFAULT_TAKE_VALUE, nat_to_u64);
+    let max_take = config
+        .max_take_value
+        .as_ref()
+        .map_or(DEFAULT_MAX_TAKE_VALUE, nat_to_u64);
+    if default_take == 0 || default_take > max_take {
+        return Err(format!(
+            "default_take_value {} must be between 1 and max_take_value {}",
+            default_take, max_take
+        ));
+    }
+    if config.max_query_batch_size == Some(0) || config.max_update_batch_size == Some(0) {
+        return Err("Batch sizes must be at least 1".to_string());
+    }
+    if let Some(cap) = &config.supply_cap {
+        let supply = total_supply();
+        if *cap < supply {
+            return Err(format!(
+                "supply_cap {} is below the current supply {}",
+                cap, supply
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Install a config, e.g. from the init or upgrade argument
+pub fn set_config(config: CollectionConfig) -> Result<(), String> {
+    validate(&config)?;
+    COLLECTION_CONFIG
+        .with(|c| c.borrow_mut().set(config))
+        .map_err(|e| format!("Failed to store collection config: {:?}", e))?;
+    Ok(())
+}
+
+pub fn total_supply() -> Nat {
+    Nat::from(TOKENS.with(|tokens| tokens.borrow().len()))
+}
+
+pub fn max_query_batch_size() -> usize {
+    config()
+        .max_query_batch_size
+        .unwrap_or(DEFAULT_MAX_QUERY_BATCH_SIZE) as usize
+}
+
+pub fn max_update_batch_size() -> usize {
+    config()
+        .max_update_batch_size
+        .unwrap_or(DEFAULT_MAX_UPDATE_BATCH_SIZE) as usize
+}
+
+fn max_take_value() -> u64 {
+    config()
+        .max_take_value
+        .as_ref()
+        .map_or(DEFAULT_MAX_TAKE_VALUE, nat_to_u64)
+}
+
+fn default_take_value() -> u64 {
+    config()
+        .default_take_value
+        .as_ref()
+        .map_or(DEFAULT_TAKE_VALUE, nat_to_u64)
+}
+
+/// Page size for paginated queries: the default when unset, capped at max_take_value
+pub fn take(take: Option<Nat>) -> usize {
+    take.as_ref()
+        .map_or_else(default_take_value, nat_to_u64)
+        .min(max_take_value()) as usize
+}
+
+fn max_memo_size() -> u32 {
+    config().max_memo_size.unwrap_or(DEFAULT_MAX_MEMO_SIZE)
+}
+
+pub fn check_memo(memo: &Option<Vec<u8>>) -> Result<(), String> {
+    match memo {
+        Some(memo) if memo.len() > max_memo_size() as usize => Err(format!(
+            "Memo is {} bytes (max {})",
+            memo.len(),
+            max_memo_size()
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// Trap on query batches above max_query_batch_size, as ICRC-7 allows for queries
+pub fn check_query_batch(len: usize) {
+    let max = max_query_batch_size();
+    if len > max {
+        ic_cdk::trap(&format!(
+            "Batch of {} exceeds max_query_batch_size {}",
+            len, max
+        ));
+    }
+}
+
+pub fn tx_window_nanos() -> u64 {
+    config()
+        .tx_window
+        .unwrap_or(DEFAULT_TX_WINDOW_SECS)
+        .saturating_mul(1_000_000_000)
+}
+
+pub fn permitted_drift_nanos() -> u64 {
+    config()
+        .permitted_drift
+        .unwrap_or(DEFAULT_PERMITTED_DRIFT_SECS)
+        .saturating_mul(1_000_000_000)
+}
+
+pub fn supply_cap() -> Option<Nat> {
+    config().supply_cap
+}
+
+pub fn is_minting_account(owner: &Principal) -> bool {
+    config()
+        .minting_account
+        .map_or(false, |account| account.owner == *owner)
+}
+
+#[update]
+pub fn set_collection_config(config: CollectionConfig) -> Result<(), String> {
+    ensure_admin()?;
+    set_config(config)?;
+    http::certify_path("/collection");
+    Ok(())
+}
+
+#[query]
+pub fn get_collection_config() -> CollectionConfig {
+    config()
+}
+
+#[query]
+pub fn icrc7_name() -> String {
+    config().name
+}
+
+#[query]
+pub fn icrc7_symbol() -> String {
+    config().symbol
+}
+
+#[query]
+pub fn icrc7_description() -> Option<String> {
+    config().description
+}
+
+#[query]
+pub fn icrc7_logo() -> Option<String> {
+    config().logo
+}
+
+#[query]
+pub fn icrc7_total_supply() -> Nat {
+    total_supply()
+}
+
+#[query]
+pub fn icrc7_supply_cap() -> Option<Nat> {
+    supply_cap()
+}
+
+#[query]
+pub fn icrc7_max_query_batch_size() -> Option<Nat> {
+    Some(Nat::from(max_query_batch_size()))
+}
+
+#[query]
+pub fn icrc7_max_update_batch_size() -> Option<Nat> {
+    Some(Nat::from(max_update_batch_size()))
+}
+
+#[query]
+pub fn icrc7_default_take_value() -> Option<Nat> {
+    Some(Nat::from(default_take_value()))
+}
+
+#[query]
+pub fn icrc7_max_take_value() -> Option<Nat> {
+    Some(Nat::from(max_take_value()))
+}
+
+#[query]
+pub fn icrc7_max_memo_size() -> Option<Nat> {
+    Some(Nat::from(max_memo_size()))
+}
+
+#[query]
+pub fn icrc7_tx_window() -> Option<Nat> {
+    Some(Nat::from(tx_window_nanos() / 1_000_000_000))
+}
+
+#[query]
+pub fn icrc7_permitted_drift() -> Option<Nat> {
+    Some(Nat::from(permitted_drift_nanos() / 1_000_000_000))
+}
+
+#[query]
+pub fn icrc7_collection_metadata() -> Vec<(String, Value)> {
+    let config = config();
+    let mut metadata = vec![
+        ("icrc7:name".to_string(), Value::Text(config.name)),
+        ("icrc7:symbol".to_string(), Value::Text(config.symbol)),
+        ("icrc7:total_supply".to_string(), Value::Nat(total_supply())),
+    ];
+    if let Some(description) = config.description {
+        metadata.push(("icrc7:description".to_string(), Value::Text(description)));
+    }
+    if let Some(logo) = config.logo {
+        metadata.push(("icrc7:logo".to_string(), Value::Text(logo)));
+    }
+    if let Some(cap) = config.supply_cap {
+        metadata.push(("icrc7:supply_cap".to_string(), Value::Nat(cap)));
+    }
+    metadata.extend(
+        [
+            (
+                "icrc7:max_query_batch_size",
+                Nat::from(max_query_batch_size()),
+            ),
+            (
+                "icrc7:max_update_batch_size",
+                Nat::from(max_update_batch_size()),
+            ),
+            ("icrc7:default_take_value", Nat::from(default_take_value())),
+            ("icrc7:max_take_value", Nat::from(max_take_value())),
+            ("icrc7:max_memo_size", Nat::from(max_memo_size())),
+            (
+                "icrc7:tx_window",
+                Nat::from(tx_window_nanos() / 1_000_000_000),
+            ),
+            (
+                "icrc7:permitted_drift",
+                Nat::from(permitted_drift_nanos() / 1_000_000_000),
+            ),
+        ]
+        .into_iter()
+        .map(|(key, value)| (key.to_string(), Value::Nat(value))),
+    );
+    metadata
+}
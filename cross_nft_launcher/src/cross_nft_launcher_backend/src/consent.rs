@@ -0,0 +1,518 @@
+use candid::{CandidType, Nat};
+use ic_cdk::update;
+use serde::{Deserialize, Serialize};
+
+use crate::evm_nft_indexer::{HOLESKY_CHAIN_ID, SEPOLIA_CHAIN_ID};
+use crate::fees::{self, BridgeIntent, BridgeToEvmArgs, PendingBridgeBurn};
+use crate::BurnArg;
+
+// ICRC-21 types
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct ConsentMessageMetadata {
+    pub language: String,
+    pub utc_offset_minutes: Option<i16>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub enum DisplayMessageType {
+    GenericDisplay,
+    LineDisplay {
+        characters_per_line: u16,
+        lines_per_page: u16,
+    },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct ConsentMessageSpec {
+    pub metadata: ConsentMessageMetadata,
+    pub device_spec: Option<DisplayMessageType>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct ConsentMessageRequest {
+    pub method: String,
+    #[serde(with = "serde_bytes")]
+    pub arg: Vec<u8>,
+    pub user_preferences: ConsentMessageSpec,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct LineDisplayPage {
+    pub lines: Vec<String>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub enum ConsentMessage {
+    GenericDisplayMessage(String),
+    LineDisplayMessage { pages: Vec<LineDisplayPage> },
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct ConsentInfo {
+    pub consent_message: ConsentMessage,
+    pub metadata: ConsentMessageMetadata,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct ErrorInfo {
+    pub description: String,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub enum Icrc21Error {
+    UnsupportedCanisterCall(ErrorInfo),
+    ConsentMessageUnavailable(ErrorInfo),
+    InsufficientPayment(ErrorInfo),
+    GenericError {
+        error_code: Nat,
+        description: String,
+    },
+}
+
+/// A consent message before rendering: a title and plain-text paragraphs
+struct Consent {
+    title: String,
+    paragraphs: Vec<String>,
+}
+
+fn chain_name(chain_id: u64) -> String {
+    match chain_id {
+        SEPOLIA_CHAIN_ID => format!("Sepolia ({})", chain_id),
+        HOLESKY_CHAIN_ID => format!("Holesky ({})", chain_id),
+        _ => format!("chain {}", chain_id),
+    }
+}
+
+fn format_icp(e8s: u64) -> String {
+    format!("{}.{:08} ICP", e8s / 100_000_000, e8s % 100_000_000)
+}
+
+fn fee_paragraph(chain_id: u64) -> String {
+    match fees::quote_fee(chain_id) {
+        Ok(quote) => format!(
+            "Bridge fee: {} (flat {} + gas {}), taken from your deposit balance.",
+            format_icp(quote.total_e8s),
+            format_icp(quote.flat_fee_e8s),
+            format_icp(quote.gas_fee_e8s)
+        ),
+        Err(e) => format!("Bridge fee: unavailable ({}).", e),
+    }
+}
+
+fn bridge_paragraphs(intent: &BridgeIntent) -> Vec<String> {
+    vec![
+        format!(
+            "Destination chain: {}",
+            chain_name(intent.destination_chain_id)
+        ),
+        format!("Destination address: {}", intent.destination_address),
+        fee_paragraph(intent.destination_chain_id),
+    ]
+}
+
+fn burn_consent(args: Vec<BurnArg>) -> Consent {
+    let mut paragraphs = vec![format!(
+        "You are permanently destroying {} token(s) on the Internet Computer.",
+        args.len()
+    )];
+    for arg in args {
+        match &arg.bridge_intent {
+            Some(intent) => paragraphs.push(format!(
+                "Token {} will be released on {} to {} once you pay the bridge fee.",
+                arg.token_id,
+                chain_name(intent.destination_chain_id),
+                intent.destination_address
+            )),
+            None => paragraphs.push(format!("Token {}", arg.token_id)),
+        }
+    }
+    Consent {
+        title: "Burn NFTs".to_string(),
+        paragraphs,
+    }
+}
+
+fn bridge_to_evm_consent(args: BridgeToEvmArgs) -> Consent {
+    let intent = BridgeIntent {
+        destination_chain_id: args.destination_chain_id,
+        destination_address: args.destination_address,
+    };
+    let mut paragraphs = vec![format!("Token: {}", args.token_id)];
+    paragraphs.extend(bridge_paragraphs(&intent));
+    paragraphs.push(
+        "The token is burned on the Internet Computer and minted to the destination address by the bridge relayer."
+            .to_string(),
+    );
+    Consent {
+        title: format!("Bridge NFT to {}", chain_name(intent.destination_chain_id)),
+        paragraphs,
+    }
+}
+
+fn release_burned_token_consent(block_index: Nat) -> Result<Consent, Icrc21Error> {
+    let pending = fees::pending_bridge_burn(&block_index).ok_or_else(|| {
+        Icrc21Error::ConsentMessageUnavailable(ErrorInfo {
+            description: format!("No pending bridge burn at block {}", block_index),
+        })
+    })?;
+    Ok(pending_burn_consent(&block_index, pending))
+}
+
+fn pending_burn_consent(block_index: &Nat, pending: PendingBridgeBurn) -> Consent {
+    let intent = BridgeIntent {
+        destination_chain_id: pending.release.chain_id,
+        destination_address: pending.release.owner,
+    };
+    let mut paragraphs = vec![format!(
+        "Release burned token {} (burn block {}).",
+        pending.token_id, block_index
+    )];
+    paragraphs.extend(bridge_paragraphs(&intent));
+    Consent {
+        title: format!("Release NFT on {}", chain_name(intent.destination_chain_id)),
+        paragraphs,
+    }
+}
+
+fn decode_error(method: &str, e: candid::Error) -> Icrc21Error {
+    Icrc21Error::UnsupportedCanisterCall(ErrorInfo {
+        description: format!("Could not decode arguments of {}: {}", method, e),
+    })
+}
+
+fn consent_for(method: &str, arg: &[u8]) -> Result<Consent, Icrc21Error> {
+    match method {
+        "icrc7_burn" => candid::decode_one::<Vec<BurnArg>>(arg)
+            .map(burn_consent)
+            .map_err(|e| decode_error(method, e)),
+        "bridge_to_evm" => candid::decode_one::<BridgeToEvmArgs>(arg)
+            .map(bridge_to_evm_consent)
+            .map_err(|e| decode_error(method, e)),
+        "release_burned_token" => candid::decode_one::<Nat>(arg)
+            .map_err(|e| decode_error(method, e))
+            .and_then(release_burned_token_consent),
+        // Tokens only move by burning and bridging; there are no transfer or approval endpoints
+        "icrc7_transfer"
+        | "icrc37_approve_tokens"
+        | "icrc37_approve_collection"
+        | "icrc37_transfer_from" => Err(Icrc21Error::UnsupportedCanisterCall(ErrorInfo {
+            description: format!("{} is not implemented by this canister", method),
+        })),
+        _ => Err(Icrc21Error::UnsupportedCanisterCall(ErrorInfo {
+            description: format!("No consent message for method {}", method),
+        })),
+    }
+}
+
+/// Split text into lines of at most `width` characters, breaking on spaces where possible
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    let mut lines = Vec::new();
+    let mut line = String::new();
+    for word in text.split_whitespace() {
+        let mut word: Vec<char> = word.chars().collect();
+        while word.len() > width {
+            if !line.is_empty() {
+                lines.push(std::mem::take(&mut line));
+            }
+            lines.push(word.drain(..width).collect());
+        }
+        let word: String = word.into_iter().collect();
+        if line.is_empty() {
+            line = word;
+        } else if line.chars().count() + 1 + word.chars().count() <= width {
+            line.push(' ');
+            line.push_str(&word);
+        } else {
+            lines.push(std::mem::replace(&mut line, word));
+        }
+    }
+    if !line.is_empty() {
+        lines.push(line);
+    }
+    lines
+}
+
+fn render(consent: Consent, device_spec: Option<DisplayMessageType>) -> ConsentMessage {
+    match device_spec {
+        Some(DisplayMessageType::LineDisplay {
+            characters_per_line,
+            lines_per_page,
+        }) => {
+            let width = characters_per_line.max(1) as usize;
+            let lines: Vec<String> = std::iter::once(consent.title)
+                .chain(consent.paragraphs)
+                .flat_map(|paragraph| wrap(&paragraph, width))
+                .collect();
+            let pages = lines
+                .chunks(lines_per_page.max(1) as usize)
+                .map(|lines| LineDisplayPage {
+                    lines: lines.to_vec(),
+                })
+                .collect();
+            ConsentMessage::LineDisplayMessage { pages }
+        }
+        Some(DisplayMessageType::GenericDisplay) | None => ConsentMessage::GenericDisplayMessage(
+            format!("# {}\n\n{}", consent.title, consent.paragraphs.join("\n\n")),
+        ),
+    }
+}
+
+#[update]
+pub fn icrc21_canister_call_consent_message(
+    request: ConsentMessageRequest,
+) -> Result<ConsentInfo, Icrc21Error> {
+    let consent = consent_for(&request.method, &request.arg)?;
+    let preferences = request.user_preferences;
+    Ok(ConsentInfo {
+        consent_message: render(consent, preferences.device_spec),
+        // Messages are only written in English; other languages fall back to it
+        metadata: ConsentMessageMetadata {
+            language: "en".to_string(),
+            utc_offset_minutes: preferences.metadata.utc_offset_minutes,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::release_nft::ReleaseRequest;
+    use candid::Principal;
+
+    const DESTINATION: &str = "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23";
+    // Default fee config with no gas price observed yet: 0.01 ICP flat plus 500k gas at the
+    // 25 gwei fee cap, at 300 ICP per ETH
+    const DEFAULT_FEE_PARAGRAPH: &str = "Bridge fee: 3.76000000 ICP (flat 0.01000000 ICP + gas 3.75000000 ICP), taken from your deposit balance.";
+
+    fn request(
+        method: &str,
+        arg: Vec<u8>,
+        device_spec: Option<DisplayMessageType>,
+    ) -> ConsentMessageRequest {
+        ConsentMessageRequest {
+            method: method.to_string(),
+            arg,
+            user_preferences: ConsentMessageSpec {
+                metadata: ConsentMessageMetadata {
+                    language: "de".to_string(),
+                    utc_offset_minutes: Some(60),
+                },
+                device_spec,
+            },
+        }
+    }
+
+    fn generic_text(method: &str, arg: Vec<u8>) -> String {
+        let info = icrc21_canister_call_consent_message(request(
+            method,
+            arg,
+            Some(DisplayMessageType::GenericDisplay),
+        ))
+        .unwrap();
+        assert_eq!(info.metadata.language, "en");
+        assert_eq!(info.metadata.utc_offset_minutes, Some(60));
+        match info.consent_message {
+            ConsentMessage::GenericDisplayMessage(text) => text,
+            other => panic!("expected a generic message, got {:?}", other),
+        }
+    }
+
+    fn line_pages(method: &str, arg: Vec<u8>, width: u16, height: u16) -> Vec<Vec<String>> {
+        let device_spec = DisplayMessageType::LineDisplay {
+            characters_per_line: width,
+            lines_per_page: height,
+        };
+        let info =
+            icrc21_canister_call_consent_message(request(method, arg, Some(device_spec))).unwrap();
+        match info.consent_message {
+            ConsentMessage::LineDisplayMessage { pages } => {
+                pages.into_iter().map(|page| page.lines).collect()
+            }
+            other => panic!("expected a line display message, got {:?}", other),
+        }
+    }
+
+    fn unsupported_description(method: &str, arg: Vec<u8>) -> String {
+        match icrc21_canister_call_consent_message(request(method, arg, None)) {
+            Err(Icrc21Error::UnsupportedCanisterCall(info)) => info.description,
+            other => panic!("expected UnsupportedCanisterCall, got {:?}", other),
+        }
+    }
+
+    fn burn_args() -> Vec<u8> {
+        candid::encode_one(vec![
+            BurnArg {
+                from_subaccount: None,
+                spender_subaccount: None,
+                token_id: Nat::from(7u64),
+                memo: None,
+                bridge_intent: Some(BridgeIntent {
+                    destination_chain_id: SEPOLIA_CHAIN_ID,
+                    destination_address: DESTINATION.to_string(),
+                }),
+            },
+            BurnArg {
+                from_subaccount: None,
+                spender_subaccount: None,
+                token_id: Nat::from(8u64),
+                memo: None,
+                bridge_intent: None,
+            },
+        ])
+        .unwrap()
+    }
+
+    fn bridge_to_evm_args() -> Vec<u8> {
+        candid::encode_one(BridgeToEvmArgs {
+            token_id: Nat::from(42u64),
+            destination_chain_id: HOLESKY_CHAIN_ID,
+            destination_address: DESTINATION.to_string(),
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn burn_consent_lists_every_token() {
+        assert_eq!(
+            generic_text("icrc7_burn", burn_args()),
+            format!(
+                "# Burn NFTs\n\n\
+                 You are permanently destroying 2 token(s) on the Internet Computer.\n\n\
+                 Token 7 will be released on Sepolia (11155111) to {} once you pay the bridge fee.\n\n\
+                 Token 8",
+                DESTINATION
+            )
+        );
+    }
+
+    #[test]
+    fn bridge_to_evm_consent_shows_destination_and_fee() {
+        assert_eq!(
+            generic_text("bridge_to_evm", bridge_to_evm_args()),
+            format!(
+                "# Bridge NFT to Holesky (17000)\n\n\
+                 Token: 42\n\n\
+                 Destination chain: Holesky (17000)\n\n\
+                 Destination address: {}\n\n\
+                 {}\n\n\
+                 The token is burned on the Internet Computer and minted to the destination address by the bridge relayer.",
+                DESTINATION, DEFAULT_FEE_PARAGRAPH
+            )
+        );
+    }
+
+    #[test]
+    fn missing_device_spec_renders_a_generic_message() {
+        let info =
+            icrc21_canister_call_consent_message(request("icrc7_burn", burn_args(), None)).unwrap();
+        assert!(matches!(
+            info.consent_message,
+            ConsentMessage::GenericDisplayMessage(text) if text.starts_with("# Burn NFTs")
+        ));
+    }
+
+    #[test]
+    fn line_display_fits_the_device_and_keeps_every_word() {
+        for (method, arg) in [
+            ("icrc7_burn", burn_args()),
+            ("bridge_to_evm", bridge_to_evm_args()),
+        ] {
+            let pages = line_pages(method, arg.clone(), 20, 4);
+            assert!(pages.iter().all(|page| !page.is_empty() && page.len() <= 4));
+            assert!(pages
+                .iter()
+                .flatten()
+                .all(|line| line.chars().count() <= 20));
+
+            // Wrapping only moves line breaks and splits words longer than a line
+            let generic = generic_text(method, arg).replace("# ", "");
+            let wrapped: String = pages.concat().concat();
+            let expected: String = generic.split_whitespace().collect();
+            assert_eq!(wrapped.replace(' ', ""), expected);
+        }
+    }
+
+    #[test]
+    fn line_display_splits_words_longer_than_a_line() {
+        let pages = line_pages("bridge_to_evm", bridge_to_evm_args(), 16, 100);
+        let lines = &pages[0];
+        assert!(lines.contains(&"0x2c7536e3605d9c".to_string()));
+        assert!(lines.contains(&"16a7a3d7b1898e52".to_string()));
+    }
+
+    #[test]
+    fn release_burned_token_needs_a_pending_burn() {
+        let arg = candid::encode_one(Nat::from(404u64)).unwrap();
+        match icrc21_canister_call_consent_message(request("release_burned_token", arg, None)) {
+            Err(Icrc21Error::ConsentMessageUnavailable(info)) => {
+                assert_eq!(info.description, "No pending bridge burn at block 404")
+            }
+            other => panic!("expected ConsentMessageUnavailable, got {:?}", other),
+        }
+    }
+
+    #[test]
+    fn release_burned_token_consent_shows_the_pending_release() {
+        let pending = PendingBridgeBurn {
+            block_index: Nat::from(9u64),
+            owner: Principal::anonymous(),
+            token_id: Nat::from(42u64),
+            release: ReleaseRequest {
+                chain_id: SEPOLIA_CHAIN_ID,
+                source_address: Principal::anonymous().to_text(),
+                owner: DESTINATION.to_string(),
+                name: "Crab".to_string(),
+                description: "A crab".to_string(),
+                image: "ipfs://crab".to_string(),
+                price: Nat::from(0u64),
+            },
+            burned_at: 0,
+        };
+        let consent = pending_burn_consent(&Nat::from(9u64), pending);
+        assert_eq!(consent.title, "Release NFT on Sepolia (11155111)");
+        assert_eq!(
+            consent.paragraphs,
+            vec![
+                "Release burned token 42 (burn block 9).".to_string(),
+                "Destination chain: Sepolia (11155111)".to_string(),
+                format!("Destination address: {}", DESTINATION),
+                DEFAULT_FEE_PARAGRAPH.to_string(),
+            ]
+        );
+    }
+
+    #[test]
+    fn undecodable_arguments_are_unsupported() {
+        let text = candid::encode_one("not the right type").unwrap();
+        for method in ["icrc7_burn", "bridge_to_evm", "release_burned_token"] {
+            for arg in [text.clone(), vec![0xde, 0xad], vec![]] {
+                let description = unsupported_description(method, arg);
+                assert!(
+                    description.starts_with(&format!("Could not decode arguments of {}:", method)),
+                    "{}",
+                    description
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn methods_without_consent_are_unsupported() {
+        for method in [
+            "icrc7_transfer",
+            "icrc37_approve_tokens",
+            "icrc37_approve_collection",
+            "icrc37_transfer_from",
+        ] {
+            assert_eq!(
+                unsupported_description(method, vec![]),
+                format!("{} is not implemented by this canister", method)
+            );
+        }
+        assert_eq!(
+            unsupported_description("withdraw_fees", vec![]),
+            "No consent message for method withdraw_fees"
+        );
+    }
+}
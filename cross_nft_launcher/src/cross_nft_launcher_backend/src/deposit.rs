@@ -0,0 +1,170 @@
+use candid::{CandidType, Principal};
+use ic_cdk::api::{caller, id, time};
+use ic_cdk::{query, update};
+use ic_ledger_types::{
+    account_balance, AccountBalanceArgs, AccountIdentifier, Memo, Subaccount, DEFAULT_FEE,
+    DEFAULT_SUBACCOUNT,
+};
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+
+use crate::fees::{ledger_canister_id, ledger_transfer};
+use crate::log;
+use crate::memory::{
+    candid_storable, get_memory, Memory, BALANCES_MEMORY_ID, DEPOSIT_CREDITS_MEMORY_ID,
+};
+use crate::Account;
+
+const SWEEP_MEMO: Memo = Memo(0x5357_4550); // "SWEP"
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct DepositAccount {
+    pub account: Account,
+    // Hex AccountIdentifier for wallets that only speak the legacy ICP ledger API
+    pub account_identifier: String,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct DepositCredit {
+    pub principal: Principal,
+    pub amount_e8s: u64,
+    pub block_index: u64,
+    pub memo: u64,
+    pub credited_at: u64,
+}
+
+candid_storable!(DepositCredit);
+
+thread_local! {
+    static BALANCES: RefCell<StableBTreeMap<Principal, u64, Memory>> = RefCell::new(
+        StableBTreeMap::init(get_memory(BALANCES_MEMORY_ID))
+    );
+
+    // Keyed by (principal, ledger block index) so one user's credits are a contiguous range
+    static DEPOSIT_CREDITS: RefCell<StableBTreeMap<(Principal, u64), DepositCredit, Memory>> = RefCell::new(
+        StableBTreeMap::init(get_memory(DEPOSIT_CREDITS_MEMORY_ID))
+    );
+}
+
+/// Deterministic per-user subaccount of this canister: [len, principal bytes.., 0 padding]
+pub fn deposit_subaccount(principal: &Principal) -> Subaccount {
+    let bytes = principal.as_slice();
+    let mut subaccount = [0u8; 32];
+    subaccount[0] = bytes.len() as u8;
+    subaccount[1..1 + bytes.len()].copy_from_slice(bytes);
+    Subaccount(subaccount)
+}
+
+pub fn deposit_account(principal: &Principal) -> DepositAccount {
+    let subaccount = deposit_subaccount(principal);
+    DepositAccount {
+        account: Account {
+            owner: id(),
+            subaccount: Some(subaccount.0.to_vec()),
+        },
+        account_identifier: AccountIdentifier::new(&id(), &subaccount).to_hex(),
+    }
+}
+
+async fn ledger_deposit_balance(principal: &Principal) -> Result<u64, String> {
+    let account = AccountIdentifier::new(&id(), &deposit_subaccount(principal));
+    let balance = account_balance(ledger_canister_id(), AccountBalanceArgs { account })
+        .await
+        .map_err(|e| format!("Failed to read deposit balance: {:?}", e))?;
+    Ok(balance.e8s())
+}
+
+pub fn internal_balance(principal: &Principal) -> u64 {
+    BALANCES.with(|balances| balances.borrow().get(principal).unwrap_or(0))
+}
+
+pub fn credit(principal: &Principal, amount_e8s: u64) {
+    BALANCES.with(|balances| {
+        let mut balances = balances.borrow_mut();
+        let balance = balances.get(principal).unwrap_or(0);
+        balances.insert(*principal, balance.saturating_add(amount_e8s));
+    });
+}
+
+pub fn debit(principal: &Principal, amount_e8s: u64) -> Result<(), String> {
+    BALANCES.with(|balances| {
+        let mut balances = balances.borrow_mut();
+        let balance = balances.get(principal).unwrap_or(0);
+        if balance < amount_e8s {
+            return Err(format!(
+                "Insufficient balance: {} e8s available, {} e8s required",
+                balance, amount_e8s
+            ));
+        }
+        balances.insert(*principal, balance - amount_e8s);
+        Ok(())
+    })
+}
+
+/// Move everything in the user's deposit subaccount into the canister account and credit it internally
+pub async fn sweep(principal: &Principal) -> Result<Option<DepositCredit>, String> {
+    let balance = ledger_deposit_balance(principal).await?;
+    if balance <= DEFAULT_FEE.e8s() {
+        return Ok(None);
+    }
+
+    let amount_e8s = balance - DEFAULT_FEE.e8s();
+    let to = AccountIdentifier::new(&id(), &DEFAULT_SUBACCOUNT);
+    let block_index =
+        ledger_transfer(deposit_subaccount(principal), to, amount_e8s, SWEEP_MEMO).await?;
+
+    credit(principal, amount_e8s);
+    let deposit_credit = DepositCredit {
+        principal: *principal,
+        amount_e8s,
+        block_index,
+        memo: SWEEP_MEMO.0,
+        credited_at: time(),
+    };
+    DEPOSIT_CREDITS.with(|credits| {
+        credits
+            .borrow_mut()
+            .insert((*principal, block_index), deposit_credit.clone())
+    });
+    log!(
+        Info,
+        Ledger,
+        "Swept {} e8s from deposit of {} (block {})",
+        amount_e8s,
+        principal,
+        block_index
+    );
+    Ok(Some(deposit_credit))
+}
+
+#[query]
+pub fn get_deposit_account() -> DepositAccount {
+    deposit_account(&caller())
+}
+
+#[update]
+pub async fn get_deposit_balance() -> Result<u64, String> {
+    ledger_deposit_balance(&caller()).await
+}
+
+#[update]
+pub async fn sweep_deposit() -> Result<Option<DepositCredit>, String> {
+    sweep(&caller()).await
+}
+
+#[query]
+pub fn get_internal_balance() -> u64 {
+    internal_balance(&caller())
+}
+
+#[query]
+pub fn get_deposit_credits(principal: Principal) -> Vec<DepositCredit> {
+    DEPOSIT_CREDITS.with(|credits| {
+        credits
+            .borrow()
+            .range((principal, 0)..=(principal, u64::MAX))
+            .map(|(_, credit)| credit)
+            .collect()
+    })
+}
@@ -0,0 +1,223 @@
+use candid::CandidType;
+use ic_cdk::api::management_canister::ecdsa::{
+    ecdsa_public_key, sign_with_ecdsa, EcdsaCurve, EcdsaKeyId, EcdsaPublicKeyArgument,
+    SignWithEcdsaArgument,
+};
+use ic_cdk::{query, update};
+use ic_stable_structures::{StableBTreeMap, StableCell};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+
+use crate::ensure_admin;
+use crate::evm_nft_indexer::{HOLESKY_CHAIN_ID, SEPOLIA_CHAIN_ID};
+use crate::log;
+use crate::memory::{
+    candid_storable, get_memory, Memory, ECDSA_KEY_CACHE_MEMORY_ID, ECDSA_KEY_NAME_MEMORY_ID,
+};
+use crate::relayer_rotation;
+use crate::release_nft::pubkey_bytes_to_address;
+
+// Chains with a relayer, listed by get_relayer_addresses even before their first release
+const RELAYER_CHAINS: [u64; 2] = [SEPOLIA_CHAIN_ID, HOLESKY_CHAIN_ID];
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+pub enum EcdsaKeyName {
+    DfxTestKey,
+    TestKey1,
+    Key1,
+}
+
+impl EcdsaKeyName {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EcdsaKeyName::DfxTestKey => "dfx_test_key",
+            EcdsaKeyName::TestKey1 => "test_key_1",
+            EcdsaKeyName::Key1 => "key_1",
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize, PartialEq, Eq)]
+pub enum KeyPurpose {
+    // Generation 0 keeps the original empty path the deployed contracts authorize; rotations
+    // derive from ["relayer", chain_id, generation]
+    Relayer { chain_id: u64, generation: u32 },
+    HotWallet(String),
+}
+
+impl KeyPurpose {
+    fn derivation_path(&self) -> Vec<Vec<u8>> {
+        match self {
+            KeyPurpose::Relayer {
+                chain_id,
+                generation,
+            } => {
+                if *generation == 0 {
+                    return vec![];
+                }
+                vec![
+                    b"relayer".to_vec(),
+                    chain_id.to_be_bytes().to_vec(),
+                    generation.to_be_bytes().to_vec(),
+                ]
+            }
+            KeyPurpose::HotWallet(name) => vec![b"hot_wallet".to_vec(), name.as_bytes().to_vec()],
+        }
+    }
+
+    fn label(&self) -> String {
+        match self {
+            KeyPurpose::Relayer {
+                chain_id,
+                generation,
+            } => format!("relayer/{}/{}", chain_id, generation),
+            KeyPurpose::HotWallet(name) => format!("hot_wallet/{}", name),
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct DerivedKey {
+    pub purpose: KeyPurpose,
+    pub key_name: String,
+    pub derivation_path: Vec<Vec<u8>>,
+    pub public_key: Vec<u8>,
+    pub address: String,
+}
+
+candid_storable!(EcdsaKeyName, DerivedKey);
+
+thread_local! {
+    // Survives upgrades, so a canister switched to key_1 never falls back to the test key
+    static KEY_NAME: RefCell<StableCell<EcdsaKeyName, Memory>> = RefCell::new(
+        StableCell::init(get_memory(ECDSA_KEY_NAME_MEMORY_ID), EcdsaKeyName::DfxTestKey)
+            .expect("failed to init ECDSA key name")
+    );
+
+    // "<key name>/<purpose>" -> public key and address, so releases skip ecdsa_public_key
+    static KEY_CACHE: RefCell<StableBTreeMap<String, DerivedKey, Memory>> = RefCell::new(
+        StableBTreeMap::init(get_memory(ECDSA_KEY_CACHE_MEMORY_ID))
+    );
+}
+
+pub fn key_id() -> EcdsaKeyId {
+    EcdsaKeyId {
+        curve: EcdsaCurve::Secp256k1,
+        name: get_ecdsa_key_name().as_str().to_string(),
+    }
+}
+
+fn cache_key(key_name: &str, purpose: &KeyPurpose) -> String {
+    format!("{}/{}", key_name, purpose.label())
+}
+
+/// Public key and EVM address for `purpose`, derived once and then served from stable memory
+pub async fn derived_key(purpose: &KeyPurpose) -> Result<DerivedKey, String> {
+    let key_id = key_id();
+    let cache_key = cache_key(&key_id.name, purpose);
+    if let Some(key) = KEY_CACHE.with(|cache| cache.borrow().get(&cache_key)) {
+        return Ok(key);
+    }
+
+    let derivation_path = purpose.derivation_path();
+    let (response,) = ecdsa_public_key(EcdsaPublicKeyArgument {
+        canister_id: None,
+        derivation_path: derivation_path.clone(),
+        key_id: key_id.clone(),
+    })
+    .await
+    .map_err(|e| format!("ecdsa_public_key failed {:?}", e))?;
+
+    let key = DerivedKey {
+        purpose: purpose.clone(),
+        key_name: key_id.name,
+        derivation_path,
+        address: pubkey_bytes_to_address(&response.public_key),
+        public_key: response.public_key,
+    };
+    log!(
+        Info,
+        Release,
+        "Derived {} address {}",
+        cache_key,
+        key.address
+    );
+    KEY_CACHE.with(|cache| cache.borrow_mut().insert(cache_key, key.clone()));
+    Ok(key)
+}
+
+/// Key of the relayer generation currently authorized on `chain_id`
+pub fn active_relayer(chain_id: u64) -> KeyPurpose {
+    KeyPurpose::Relayer {
+        chain_id,
+        generation: relayer_rotation::active_generation(chain_id),
+    }
+}
+
+pub async fn relayer_address(chain_id: u64) -> Result<String, String> {
+    Ok(derived_key(&active_relayer(chain_id)).await?.address)
+}
+
+/// Sign `message_hash` with the key for `purpose`, returning the key alongside the signature
+pub async fn sign(
+    purpose: &KeyPurpose,
+    message_hash: Vec<u8>,
+) -> Result<(DerivedKey, Vec<u8>), String> {
+    let key = derived_key(purpose).await?;
+    let (response,) = sign_with_ecdsa(SignWithEcdsaArgument {
+        message_hash,
+        derivation_path: key.derivation_path.clone(),
+        key_id: EcdsaKeyId {
+            curve: EcdsaCurve::Secp256k1,
+            name: key.key_name.clone(),
+        },
+    })
+    .await
+    .map_err(|e| format!("Failed to generate signature {:?}", e))?;
+
+    Ok((key, response.signature))
+}
+
+#[update]
+pub fn set_ecdsa_key_name(key_name: EcdsaKeyName) -> Result<(), String> {
+    ensure_admin()?;
+    log!(
+        Info,
+        Release,
+        "Switching threshold ECDSA key to {}",
+        key_name.as_str()
+    );
+    KEY_NAME
+        .with(|name| name.borrow_mut().set(key_name))
+        .map_err(|e| format!("Failed to store ECDSA key name: {:?}", e))?;
+    Ok(())
+}
+
+#[query]
+pub fn get_ecdsa_key_name() -> EcdsaKeyName {
+    KEY_NAME.with(|name| *name.borrow().get())
+}
+
+/// Every key derived so far under the current key name
+#[query]
+pub fn get_relayer_addresses() -> Vec<DerivedKey> {
+    let key_name = key_id().name;
+    KEY_CACHE.with(|cache| {
+        cache
+            .borrow()
+            .iter()
+            .map(|(_, key)| key)
+            .filter(|key| key.key_name == key_name)
+            .collect()
+    })
+}
+
+/// Derive the active relayer of each chain, so it is listed by get_relayer_addresses
+/// before its first release
+#[update]
+pub async fn derive_relayer_addresses() -> Result<Vec<DerivedKey>, String> {
+    for chain_id in RELAYER_CHAINS {
+        derived_key(&active_relayer(chain_id)).await?;
+    }
+    Ok(get_relayer_addresses())
+}
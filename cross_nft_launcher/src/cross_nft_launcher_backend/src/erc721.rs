@@ -0,0 +1,263 @@
+use candid::Nat;
+use serde_json::{json, Map, Value as Json};
+
+use crate::Value;
+
+/// Token metadata key holding the ERC-721 JSON document as a Value tree (ICRC-97)
+pub const ICRC97_METADATA: &str = "icrc97:metadata";
+// Kept as flat Text entries so metadata policies and plain ICRC-7 clients read them directly
+const CORE_FIELDS: [&str; 3] = ["name", "description", "image"];
+// Value has no bool, float or null; they are stored as single-entry maps under these keys
+const JSON_BOOL: &str = "json:bool";
+const JSON_FLOAT: &str = "json:float";
+const JSON_NULL: &str = "json:null";
+const JSON_TAGS: [&str; 3] = [JSON_BOOL, JSON_FLOAT, JSON_NULL];
+
+pub fn nat_json(n: &Nat) -> Json {
+    match u64::try_from(&n.0) {
+        Ok(n) => json!(n),
+        // Beyond JSON's safe integer range, e.g. uint256 prices
+        Err(_) => json!(n.0.to_string()),
+    }
+}
+
+pub fn value_to_json(value: &Value) -> Json {
+    match value {
+        Value::Text(text) => json!(text),
+        Value::Nat(n) => nat_json(n),
+        Value::Int(i) => match i64::try_from(*i) {
+            Ok(i) => json!(i),
+            Err(_) => json!(i.to_string()),
+        },
+        Value::Blob(bytes) => json!(format!("0x{}", hex::encode(bytes))),
+        Value::Array(values) => Json::Array(values.iter().map(value_to_json).collect()),
+        Value::Map(entries) => tagged_json(entries).unwrap_or_else(|| {
+            Json::Object(
+                entries
+                    .iter()
+                    .map(|(key, value)| (key.clone(), value_to_json(value)))
+                    .collect(),
+            )
+        }),
+    }
+}
+
+/// JSON bool, float or null written by `json_to_value`
+fn tagged_json(entries: &[(String, Value)]) -> Option<Json> {
+    match entries {
+        [(tag, Value::Nat(flag))] if tag == JSON_BOOL => Some(Json::Bool(*flag != Nat::from(0u8))),
+        [(tag, Value::Text(float))] if tag == JSON_FLOAT => float
+            .parse::<f64>()
+            .ok()
+            .and_then(serde_json::Number::from_f64)
+            .map(Json::Number),
+        [(tag, _)] if tag == JSON_NULL => Some(Json::Null),
+        _ => None,
+    }
+}
+
+fn tagged(tag: &str, value: Value) -> Value {
+    Value::Map(vec![(tag.to_string(), value)])
+}
+
+fn object_to_value(fields: &Map<String, Json>) -> Result<Value, String> {
+    if fields.len() == 1 {
+        if let Some(tag) = fields.keys().find(|key| JSON_TAGS.contains(&key.as_str())) {
+            return Err(format!(
+                "JSON objects with the single key '{}' are reserved",
+                tag
+            ));
+        }
+    }
+    Ok(Value::Map(
+        fields
+            .iter()
+            .map(|(key, value)| Ok((key.clone(), json_to_value(value)?)))
+            .collect::<Result<_, String>>()?,
+    ))
+}
+
+/// Lossless JSON to Value; `value_to_json` turns the result back into the same JSON.
+/// Booleans, floats and nulls are tagged maps, e.g. `true` is `{"json:bool": 1}`.
+pub fn json_to_value(json: &Json) -> Result<Value, String> {
+    Ok(match json {
+        Json::Null => tagged(JSON_NULL, Value::Array(vec![])),
+        Json::Bool(flag) => tagged(JSON_BOOL, Value::Nat(Nat::from(*flag as u8))),
+        Json::Number(n) => match (n.as_u64(), n.as_i64()) {
+            (Some(n), _) => Value::Nat(Nat::from(n)),
+            (None, Some(n)) => Value::Int(n as i128),
+            _ => tagged(JSON_FLOAT, Value::Text(n.to_string())),
+        },
+        Json::String(text) => Value::Text(text.clone()),
+        Json::Array(items) => Value::Array(
+            items
+                .iter()
+                .map(json_to_value)
+                .collect::<Result<_, String>>()?,
+        ),
+        Json::Object(fields) => object_to_value(fields)?,
+    })
+}
+
+/// Token metadata for an ERC-721 JSON document: name, description and image as flat Text
+/// entries and every other field, attributes included, under `icrc97:metadata`
+pub fn to_icrc7_metadata(document: &Json) -> Result<Vec<(String, Value)>, String> {
+    let fields = document
+        .as_object()
+        .ok_or_else(|| "ERC-721 metadata must be a JSON object".to_string())?;
+    if fields.get("attributes").map_or(false, |a| !a.is_array()) {
+        return Err("ERC-721 attributes must be an array".to_string());
+    }
+
+    let mut metadata = Vec::new();
+    for key in CORE_FIELDS {
+        match fields.get(key) {
+            None => {}
+            Some(Json::String(text)) => metadata.push((key.to_string(), Value::Text(text.clone()))),
+            Some(other) => {
+                return Err(format!("ERC-721 {} must be a string, got {}", key, other));
+            }
+        }
+    }
+    let rest: Map<String, Json> = fields
+        .iter()
+        .filter(|(key, _)| !CORE_FIELDS.contains(&key.as_str()))
+        .map(|(key, value)| (key.clone(), value.clone()))
+        .collect();
+    // Always present, even when empty, so `to_erc721_json` reads the document back as is
+    metadata.push((ICRC97_METADATA.to_string(), object_to_value(&rest)?));
+    Ok(metadata)
+}
+
+/// ERC-721 JSON for token metadata; the inverse of `to_icrc7_metadata`. Tokens minted
+/// without `icrc97:metadata` use a JSON "attributes" text entry as is, or otherwise turn
+/// every non-standard entry into a trait.
+pub fn to_erc721_json(metadata: &[(String, Value)]) -> Json {
+    let text = |key: &str| {
+        metadata.iter().find_map(|(k, v)| match v {
+            Value::Text(t) if k == key => Some(t.clone()),
+            _ => None,
+        })
+    };
+    let icrc97 = metadata.iter().find_map(|(key, value)| match value {
+        Value::Map(_) if key == ICRC97_METADATA => Some(value_to_json(value)),
+        _ => None,
+    });
+
+    if let Some(Json::Object(mut document)) = icrc97 {
+        for key in CORE_FIELDS {
+            if let Some(value) = text(key) {
+                document.insert(key.to_string(), json!(value));
+            }
+        }
+        return Json::Object(document);
+    }
+
+    let attributes = text("attributes")
+        .and_then(|raw| serde_json::from_str::<Json>(&raw).ok())
+        .filter(Json::is_array)
+        .unwrap_or_else(|| {
+            Json::Array(
+                metadata
+                    .iter()
+                    .filter(|(key, _)| !CORE_FIELDS.contains(&key.as_str()) && key != "attributes")
+                    .map(|(key, value)| json!({ "trait_type": key, "value": value_to_json(value) }))
+                    .collect(),
+            )
+        });
+    let mut document = Map::new();
+    for key in CORE_FIELDS {
+        document.insert(key.to_string(), json!(text(key).unwrap_or_default()));
+    }
+    document.insert("attributes".to_string(), attributes);
+    Json::Object(document)
+}
+
+/// Value of the first attribute with `trait_type`
+pub fn attribute<'a>(document: &'a Json, trait_type: &str) -> Option<&'a Json> {
+    document
+        .get("attributes")?
+        .as_array()?
+        .iter()
+        .find(|attribute| attribute.get("trait_type").and_then(Json::as_str) == Some(trait_type))?
+        .get("value")
+}
+
+/// Attribute as a Nat, whether stored as a number or as decimal text
+pub fn nat_attribute(document: &Json, trait_type: &str) -> Option<Nat> {
+    match attribute(document, trait_type)? {
+        Json::Number(n) => n.as_u64().map(Nat::from),
+        Json::String(text) => text.parse().ok(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(document: &Json) -> Json {
+        to_erc721_json(&to_icrc7_metadata(document).expect("valid ERC-721 document"))
+    }
+
+    #[test]
+    fn document_round_trips_through_icrc7_metadata() {
+        let document = json!({
+            "name": "Genesis",
+            "description": "First of its kind",
+            "image": "ipfs://bafy/1.png",
+            "external_url": null,
+            "attributes": [
+                { "trait_type": "price", "value": 1_000_000_000_000_000_000u64 },
+                { "trait_type": "forSale", "value": true },
+                { "trait_type": "rarity", "value": 0.125 },
+                { "trait_type": "offset", "value": -7 },
+                { "trait_type": "tags", "value": ["a", null, false, 2.5] },
+            ],
+            "properties": { "nested": { "listed": false, "score": 1e300 } },
+        });
+        assert_eq!(round_trip(&document), document);
+    }
+
+    #[test]
+    fn core_only_document_round_trips() {
+        let document = json!({ "name": "Plain", "image": "https://example.com/a.png" });
+        assert_eq!(round_trip(&document), document);
+    }
+
+    #[test]
+    fn scalars_round_trip_through_value() {
+        for json in [
+            json!(true),
+            json!(false),
+            json!(null),
+            json!(3.75),
+            json!(-1),
+            json!(u64::MAX),
+            json!("text"),
+            json!({}),
+            json!([]),
+        ] {
+            assert_eq!(value_to_json(&json_to_value(&json).unwrap()), json);
+        }
+    }
+
+    #[test]
+    fn non_string_core_field_is_rejected() {
+        for document in [
+            json!({ "name": 7 }),
+            json!({ "description": null }),
+            json!({ "image": { "url": "x" } }),
+        ] {
+            assert!(to_icrc7_metadata(&document).is_err());
+        }
+    }
+
+    #[test]
+    fn reserved_tag_objects_are_rejected() {
+        assert!(json_to_value(&json!({ "json:bool": 1 })).is_err());
+        assert!(json_to_value(&json!({ "attributes": [{ "json:null": [] }] })).is_err());
+        // Tags only clash as the single key of an object
+        assert!(json_to_value(&json!({ "json:bool": 1, "other": 2 })).is_ok());
+    }
+}
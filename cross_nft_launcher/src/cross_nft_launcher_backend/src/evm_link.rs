@@ -0,0 +1,249 @@
+use candid::{CandidType, Principal};
+use ethabi::ethereum_types::H160;
+use ic_cdk::api::management_canister::main::raw_rand;
+use ic_cdk::api::{caller, time};
+use ic_cdk::{query, update};
+use ic_stable_structures::StableBTreeMap;
+use k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
+use k256::elliptic_curve::sec1::ToEncodedPoint;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::log;
+use crate::memory::{
+    get_memory, Memory, ADDRESS_BY_PRINCIPAL_MEMORY_ID, PRINCIPAL_BY_ADDRESS_MEMORY_ID,
+};
+use crate::release_nft::pubkey_bytes_to_address;
+
+const LINK_NONCE_TTL_NANOS: u64 = 5 * 60 * 1_000_000_000;
+
+/// What the caller must sign with the EVM wallet to link it
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct EvmLinkChallenge {
+    pub address: String,
+    pub nonce: String,
+    pub message: String,
+    pub expires_at: u64,
+}
+
+thread_local! {
+    // Outstanding challenge per principal; each nonce is single use
+    static LINK_CHALLENGES: RefCell<HashMap<Principal, EvmLinkChallenge>> = RefCell::new(HashMap::new());
+
+    // Lowercased EVM address <-> principal; kept across upgrades, as burns are minted to them
+    static PRINCIPAL_BY_ADDRESS: RefCell<StableBTreeMap<String, Principal, Memory>> = RefCell::new(
+        StableBTreeMap::init(get_memory(PRINCIPAL_BY_ADDRESS_MEMORY_ID))
+    );
+    static ADDRESS_BY_PRINCIPAL: RefCell<StableBTreeMap<Principal, String, Memory>> = RefCell::new(
+        StableBTreeMap::init(get_memory(ADDRESS_BY_PRINCIPAL_MEMORY_ID))
+    );
+}
+
+/// Lowercased 0x-prefixed form of a 20-byte hex address
+pub fn normalize_address(address: &str) -> Result<String, String> {
+    let parsed = H160::from_str(address.trim_start_matches("0x"))
+        .map_err(|e| format!("Invalid EVM address '{}': {}", address, e))?;
+    Ok(format!("0x{}", hex::encode(parsed.as_bytes())))
+}
+
+fn link_message(address: &str, principal: &Principal, nonce: &str) -> String {
+    format!(
+        "Link Ethereum address {} to Internet Computer principal {}.\n\nNonce: {}",
+        address, principal, nonce
+    )
+}
+
+/// keccak256 of an EIP-191 personal_sign message
+pub fn eip191_hash(message: &str) -> [u8; 32] {
+    let mut prefixed = format!("\x19Ethereum Signed Message:\n{}", message.len()).into_bytes();
+    prefixed.extend_from_slice(message.as_bytes());
+    ethers_core::utils::keccak256(prefixed)
+}
+
+/// Lowercased address that produced a 65-byte (r || s || v) personal_sign signature
+pub fn recover_eip191_signer(message: &str, signature_hex: &str) -> Result<String, String> {
+    let bytes = hex::decode(signature_hex.trim_start_matches("0x"))
+        .map_err(|e| format!("Invalid signature hex: {}", e))?;
+    if bytes.len() != 65 {
+        return Err(format!("Signature must be 65 bytes, got {}", bytes.len()));
+    }
+    let signature =
+        Signature::try_from(&bytes[..64]).map_err(|e| format!("Invalid signature: {}", e))?;
+    // Wallets send v as 27/28; some libraries use 0/1
+    let v = match bytes[64] {
+        27 | 28 => bytes[64] - 27,
+        0 | 1 => bytes[64],
+        other => return Err(format!("Invalid signature recovery byte {}", other)),
+    };
+    let recovery_id = RecoveryId::try_from(v).map_err(|e| format!("Invalid recovery id: {}", e))?;
+    let key = VerifyingKey::recover_from_prehash(&eip191_hash(message), &signature, recovery_id)
+        .map_err(|e| format!("Could not recover signer: {}", e))?;
+    normalize_address(&pubkey_bytes_to_address(
+        key.to_encoded_point(false).as_bytes(),
+    ))
+}
+
+/// Principal linked to an EVM address, if any
+pub fn linked_principal(address: &str) -> Option<Principal> {
+    let address = normalize_address(address).ok()?;
+    PRINCIPAL_BY_ADDRESS.with(|links| links.borrow().get(&address))
+}
+
+fn unlink(principal: &Principal) -> Option<String> {
+    let address = ADDRESS_BY_PRINCIPAL.with(|links| links.borrow_mut().remove(principal))?;
+    PRINCIPAL_BY_ADDRESS.with(|links| links.borrow_mut().remove(&address));
+    Some(address)
+}
+
+/// Issue a nonce for linking `address` to the caller and return the message to sign
+#[update]
+pub async fn get_evm_link_challenge(address: String) -> Result<EvmLinkChallenge, String> {
+    let principal = caller();
+    if principal == Principal::anonymous() {
+        return Err("Anonymous principals cannot link EVM addresses".to_string());
+    }
+    let address = normalize_address(&address)?;
+    let (random,) = raw_rand()
+        .await
+        .map_err(|(code, msg)| format!("raw_rand failed: {:?} {}", code, msg))?;
+    let nonce = hex::encode(&random[..16]);
+
+    let challenge = EvmLinkChallenge {
+        message: link_message(&address, &principal, &nonce),
+        address,
+        nonce,
+        expires_at: time() + LINK_NONCE_TTL_NANOS,
+    };
+    LINK_CHALLENGES.with(|c| c.borrow_mut().insert(principal, challenge.clone()));
+    Ok(challenge)
+}
+
+/// Link the caller to `address` given a personal_sign signature over the challenge message
+#[update]
+pub fn link_evm_address(address: String, signature: String, nonce: String) -> Result<(), String> {
+    let principal = caller();
+    let address = normalize_address(&address)?;
+    let challenge = LINK_CHALLENGES
+        .with(|c| c.borrow_mut().remove(&principal))
+        .ok_or_else(|| "No link challenge issued for caller".to_string())?;
+    if challenge.nonce != nonce || challenge.address != address {
+        return Err("Nonce or address does not match the issued challenge".to_string());
+    }
+    if challenge.expires_at < time() {
+        return Err("Link challenge expired".to_string());
+    }
+
+    let signer = recover_eip191_signer(&challenge.message, &signature)?;
+    if signer != address {
+        return Err(format!("Signature was made by {}, not {}", signer, address));
+    }
+
+    // A principal has one address and an address one principal; relinking replaces both
+    unlink(&principal);
+    if let Some(previous) = PRINCIPAL_BY_ADDRESS.with(|links| links.borrow().get(&address)) {
+        unlink(&previous);
+    }
+    PRINCIPAL_BY_ADDRESS.with(|links| links.borrow_mut().insert(address.clone(), principal));
+    ADDRESS_BY_PRINCIPAL.with(|links| links.borrow_mut().insert(principal, address.clone()));
+    log!(Info, Auth, "Linked {} to {}", address, principal);
+    Ok(())
+}
+
+#[update]
+pub fn unlink_evm_address() -> Result<String, String> {
+    unlink(&caller()).ok_or_else(|| "Caller has no linked EVM address".to_string())
+}
+
+#[query]
+pub fn get_linked_evm_address(principal: Principal) -> Option<String> {
+    ADDRESS_BY_PRINCIPAL.with(|links| links.borrow().get(&principal))
+}
+
+#[query]
+pub fn get_linked_principal(address: String) -> Option<Principal> {
+    linked_principal(&address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Key 0x4c0883a6...3f362318 from the web3.js accounts docs; "Some data" is their
+    // published personal_sign example
+    const SIGNER: &str = "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23";
+    const SOME_DATA_V28: &str = "0xb91467e570a6466aa9e9876cbcd013baba02900b8979d43fe208a4a4f339f5fd6007e74cd82e037b800186422fc2da167c747ef045e5d18a5f5d4300f8e1a0291c";
+    const LINK_ME_V27: &str = "0x3bcbace3ff2b5d5195a9cfb77031b452a90f742f756a9854362dcbcf4e8e5c5d5f8001b1d0aaba68a9452e085d677883d4e6ac4d503cada6c2467205f51613b71b";
+
+    /// Same signature with v as 0/1 instead of 27/28
+    fn with_zero_based_v(signature: &str) -> String {
+        let mut bytes = hex::decode(signature.trim_start_matches("0x")).unwrap();
+        bytes[64] -= 27;
+        hex::encode(bytes)
+    }
+
+    #[test]
+    fn recovers_signer_with_v_27_and_28() {
+        assert_eq!(
+            recover_eip191_signer("Some data", SOME_DATA_V28).unwrap(),
+            SIGNER
+        );
+        assert_eq!(
+            recover_eip191_signer("Link me", LINK_ME_V27).unwrap(),
+            SIGNER
+        );
+    }
+
+    #[test]
+    fn recovers_signer_with_v_0_and_1() {
+        let v1 = with_zero_based_v(SOME_DATA_V28);
+        assert!(v1.ends_with("01"));
+        assert_eq!(recover_eip191_signer("Some data", &v1).unwrap(), SIGNER);
+        let v0 = with_zero_based_v(LINK_ME_V27);
+        assert!(v0.ends_with("00"));
+        assert_eq!(recover_eip191_signer("Link me", &v0).unwrap(), SIGNER);
+    }
+
+    #[test]
+    fn a_different_message_recovers_a_different_address() {
+        let signer = recover_eip191_signer("Some other data", SOME_DATA_V28);
+        assert_ne!(signer.as_deref(), Ok(SIGNER));
+    }
+
+    #[test]
+    fn rejects_malformed_signatures() {
+        assert!(recover_eip191_signer("Some data", "0xnot-hex")
+            .unwrap_err()
+            .starts_with("Invalid signature hex"));
+        assert!(recover_eip191_signer("Some data", "0xabc")
+            .unwrap_err()
+            .starts_with("Invalid signature hex"));
+
+        let short = &SOME_DATA_V28[..SOME_DATA_V28.len() - 2];
+        assert_eq!(
+            recover_eip191_signer("Some data", short).unwrap_err(),
+            "Signature must be 65 bytes, got 64"
+        );
+        let long = format!("{}00", SOME_DATA_V28);
+        assert_eq!(
+            recover_eip191_signer("Some data", &long).unwrap_err(),
+            "Signature must be 65 bytes, got 66"
+        );
+        assert_eq!(
+            recover_eip191_signer("Some data", "").unwrap_err(),
+            "Signature must be 65 bytes, got 0"
+        );
+
+        let bad_v = format!("{}1d", &SOME_DATA_V28[..SOME_DATA_V28.len() - 2]);
+        assert_eq!(
+            recover_eip191_signer("Some data", &bad_v).unwrap_err(),
+            "Invalid signature recovery byte 29"
+        );
+        // r = s = 0 is not a valid signature
+        let zero = format!("{}1b", "00".repeat(64));
+        assert!(recover_eip191_signer("Some data", &zero)
+            .unwrap_err()
+            .starts_with("Invalid signature"));
+    }
+}
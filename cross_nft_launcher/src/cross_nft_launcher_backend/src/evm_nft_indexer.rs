@@ -1,7 +1,7 @@
 use candid::Principal; // Import for Principal
 use candid::{CandidType, Nat};
 
-use ethabi::ethereum_types::H256;
+use ethabi::ethereum_types::{H256, U256};
 use ethabi::{decode, LogParam, ParamType, Token};
 use hex::decode as hex_decode;
 use ic_cdk::api::call::call_with_payment128;
@@ -9,12 +9,18 @@ use ic_cdk::api::time;
 
 use ic_cdk::{post_upgrade, update};
 use ic_cdk_timers::{set_timer, set_timer_interval, TimerId};
+use ic_stable_structures::{StableBTreeMap, StableCell};
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use std::cell::RefCell;
 use std::collections::{HashMap, HashSet};
+use std::str::FromStr;
 
 use ethabi::{Event, EventParam, Log, RawLog};
 
+use crate::bridge_control::{self, BridgeDirection, BridgeOperation};
+use crate::erc721;
+use crate::evm_link;
 use crate::evm_rpc_bindings::{
     BlockTag,
     GetBlockByNumberResult,
@@ -27,9 +33,22 @@ use crate::evm_rpc_bindings::{
     RpcServices,
     Service as EvmRpcService, // This is your interface to the canister
 };
-
+use crate::log;
+use crate::memory::{get_memory, Memory, BLOCK_NUMBER_MEMORY_ID, PROCESSED_BURN_LOGS_MEMORY_ID};
+use crate::metadata_policy::{self, NftMetadata};
+
+use crate::rate_limit::ReleaseOutcome;
+use crate::release_nft::ReleaseRequest;
+use crate::siwe;
+use crate::token_origin;
 use crate::{icrc7_mint, Account, MetadataValue, MintArgs};
 
+pub const SEPOLIA_CHAIN_ID: u64 = 11155111;
+pub const HOLESKY_CHAIN_ID: u64 = 17000;
+
+// How far behind a scan processed burn logs are remembered; rewinds never go back that far
+const PROCESSED_LOG_RETENTION_BLOCKS: u64 = 100_000;
+
 #[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
 pub struct TransactionDetails {
     pub from: String,
@@ -55,7 +74,18 @@ thread_local! {
 }
 
 thread_local! {
-    static BLOCK_NUMBER: RefCell<u64> = RefCell::new(8845457);
+    static BLOCK_NUMBER: RefCell<StableCell<u64, Memory>> = RefCell::new(
+        StableCell::init(get_memory(BLOCK_NUMBER_MEMORY_ID), 8845457)
+            .expect("failed to init block number")
+    );
+}
+
+thread_local! {
+    // "chain:block:txhash:logIndex" of burn logs already handled, so rewound ranges are not
+    // replayed; the zero-padded block keeps each chain's keys in block order for pruning
+    static PROCESSED_BURN_LOGS: RefCell<StableBTreeMap<String, (), Memory>> = RefCell::new(
+        StableBTreeMap::init(get_memory(PROCESSED_BURN_LOGS_MEMORY_ID))
+    );
 }
 
 thread_local! {
@@ -102,8 +132,10 @@ impl ChainService {
         to_block: u64,
         address_filter: Option<String>,
     ) -> Result<Vec<String>, String> {
-        ic_cdk::println!(
-            "🚀 Starting fetch_burn_logs from block {} to {}",
+        log!(
+            Info,
+            Indexer,
+            "Starting fetch_burn_logs from block {} to {}",
             from_block,
             to_block
         );
@@ -186,6 +218,15 @@ impl ChainService {
                 .clone()
                 .map(|n| n.to_string())
                 .unwrap_or_else(|| "N/A".to_string());
+            let log_key = processed_log_key(
+                SEPOLIA_CHAIN_ID,
+                log_entry.blockNumber.as_ref(),
+                &tx_hash,
+                log_entry.logIndex.as_ref(),
+            );
+            if is_processed(&log_key) {
+                continue;
+            }
 
             // Decode the event from topics and data (full expanded event)
             if let Some((
@@ -202,56 +243,154 @@ impl ChainService {
                 timestamp,
             )) = Self::decode_nft_burn_event_from_log(&log_entry.topics, &log_entry.data)
             {
-                ic_cdk::println!("🔥 Burn Event Decoded:");
-                ic_cdk::println!("  Tx Hash: {}", tx_hash);
-                ic_cdk::println!("  Token ID: {}", token_id);
-                ic_cdk::println!("  Owner: {}", owner);
-                ic_cdk::println!("  Name: {}", name);
-                ic_cdk::println!("  Description: {}", description);
-                ic_cdk::println!("  Image: {}", image);
-                ic_cdk::println!("  Price: {}", price);
-                ic_cdk::println!("  For Sale: {}", for_sale);
-                ic_cdk::println!("  Created At: {}", created_at);
-                ic_cdk::println!("  Destination Chain: {}", destination_chain);
-                ic_cdk::println!("  Destination Address: {}", destination_address);
-                ic_cdk::println!("  Timestamp: {}", timestamp);
+                if let Err(e) = Self::ensure_route_active(SEPOLIA_CHAIN_ID, HOLESKY_CHAIN_ID, true)
+                    .and_then(|_| bridge_control::record_burn(SEPOLIA_CHAIN_ID))
+                {
+                    log!(
+                        Warn,
+                        Indexer,
+                        "Halting burn processing at Tx {}: {}",
+                        tx_hash,
+                        e
+                    );
+                    Self::rewind_block_number(&log_entry.blockNumber);
+                    break;
+                }
 
-                // Automatically mint NFT on ICP (or bridge logic)
-                ic_cdk::println!("🔥 Attempting to mint NFT from burn event...");
+                log!(
+                    Debug,
+                    Indexer,
+                    "Decoded burn: tx {} token {} owner {} name '{}' description '{}' image '{}' price {} for_sale {} created_at {} destination {} {} at {}",
+                    tx_hash,
+                    token_id,
+                    owner,
+                    name,
+                    description,
+                    image,
+                    price,
+                    for_sale,
+                    created_at,
+                    destination_chain,
+                    destination_address,
+                    timestamp
+                );
+
+                // The contract sets tokenURI to the image; keep it before the policy shortens it
+                let event_image = image.clone();
+
+                // Fit metadata to the release chain before minting, so the ICP token and
+                // the Holesky release carry the same values
+                let NftMetadata {
+                    name,
+                    description,
+                    image,
+                } = match metadata_policy::apply(
+                    HOLESKY_CHAIN_ID,
+                    NftMetadata {
+                        name,
+                        description,
+                        image,
+                    },
+                ) {
+                    Ok(metadata) => metadata,
+                    Err(e) => {
+                        log!(Error, Indexer, "Rejecting burn {}: {}", tx_hash, e);
+                        mark_processed(log_key);
+                        continue;
+                    }
+                };
 
-                // Use canister's own principal ID for minting (not destination address)
-                let canister_principal = ic_cdk::api::id();
-                ic_cdk::println!(
-                    "🏭 Using canister principal for minting: {}",
-                    canister_principal
+                // Namespace the ICP id by source chain and contract so equal EVM ids never collide
+                let icp_token_id = match token_origin::icp_token_id(
+                    SEPOLIA_CHAIN_ID,
+                    &log_entry.address,
+                    &token_id,
+                ) {
+                    Ok(id) => id,
+                    Err(e) => {
+                        log!(
+                            Error,
+                            Indexer,
+                            "Cannot derive ICP token id for {}: {}",
+                            tx_hash,
+                            e
+                        );
+                        mark_processed(log_key);
+                        continue;
+                    }
+                };
+
+                // Automatically mint NFT on ICP (or bridge logic)
+                log!(Debug, Indexer, "Attempting to mint NFT from burn event...");
+
+                // Burns addressed to ICP stay here: minted to the principal named as the
+                // destination, by default to the one the burner linked, and otherwise to the
+                // burner's Sign-In-With-Ethereum principal. Everything else is held by the
+                // canister on its way to Holesky.
+                let stays_on_icp = destination_chain.eq_ignore_ascii_case("ICP");
+                let recipient = if stays_on_icp {
+                    Principal::from_text(&destination_address)
+                        .ok()
+                        .or_else(|| evm_link::linked_principal(&owner))
+                        .or_else(|| siwe::principal_for_address(&owner).ok())
+                        .unwrap_or_else(ic_cdk::api::id)
+                } else {
+                    ic_cdk::api::id()
+                };
+                log!(
+                    Info,
+                    Indexer,
+                    "Minting bridged NFT to principal: {}",
+                    recipient
                 );
 
-                // Create the account for minting (to canister itself)
                 let to_account = Account {
-                    owner: canister_principal,
+                    owner: recipient,
                     subaccount: None,
                 };
 
-                // Create metadata for the bridged NFT, using all decoded metadata
-                let metadata = vec![
-                    ("name".to_string(), MetadataValue::Text(name.clone())),
-                    (
-                        "description".to_string(),
-                        MetadataValue::Text(description.clone()),
-                    ),
-                    ("image".to_string(), MetadataValue::Text(image.clone())),
-                    ("price".to_string(), MetadataValue::Text(price.to_string())),
-                    (
-                        "forSale".to_string(),
-                        MetadataValue::Text(for_sale.to_string()),
-                    ),
-                    (
-                        "createdAt".to_string(),
-                        MetadataValue::Text(created_at.to_string()),
-                    ),
+                // The decoded fields as an ERC-721 document, so price, forSale and createdAt
+                // keep their types as attributes under icrc97:metadata
+                let document = json!({
+                    "name": name,
+                    "description": description,
+                    "image": image,
+                    "attributes": [
+                        { "trait_type": "price", "value": erc721::nat_json(&price) },
+                        { "trait_type": "forSale", "value": for_sale },
+                        { "trait_type": "createdAt", "display_type": "date", "value": created_at },
+                    ],
+                });
+                let mut metadata: Vec<(String, MetadataValue)> =
+                    match erc721::to_icrc7_metadata(&document) {
+                        Ok(metadata) => metadata
+                            .into_iter()
+                            .map(|(key, value)| (key, value.into()))
+                            .collect(),
+                        Err(e) => {
+                            log!(Error, Indexer, "Rejecting burn {}: {}", tx_hash, e);
+                            mark_processed(log_key);
+                            continue;
+                        }
+                    };
+                // Richer off-chain JSON at the tokenURI, when snapshotting is enabled
+                metadata.extend(
+                    self.token_uri_snapshot(
+                        SEPOLIA_CHAIN_ID,
+                        &log_entry.address,
+                        &token_id,
+                        log_entry.blockNumber.as_ref(),
+                        &event_image,
+                    )
+                    .await
+                    .into_iter()
+                    .map(|(key, value)| (key, value.into())),
+                );
+                // Bridge provenance stays in flat entries beside the document
+                metadata.extend(vec![
                     (
                         "evm_token_id".to_string(),
-                        MetadataValue::Text(token_id.to_string()),
+                        MetadataValue::Nat(token_id.clone()),
                     ),
                     (
                         "evm_tx_hash".to_string(),
@@ -277,61 +416,99 @@ impl ChainService {
                         "original_recipient".to_string(),
                         MetadataValue::Text(destination_address.clone()),
                     ),
-                ];
+                ]);
 
                 // Construct MintArgs
                 let mint_args = MintArgs {
                     to: to_account,
-                    token_id: Nat::from(token_id),
+                    token_id: icp_token_id.clone(),
                     metadata,
                     memo: Some(format!("Bridge from EVM: {}", tx_hash).into_bytes()),
-                    created_at_time: Some(timestamp),
+                    // Replays are caught by PROCESSED_BURN_LOGS and the namespaced token id,
+                    // and the EVM timestamp may already be outside the ICRC-7 dedup window
+                    created_at_time: None,
                 };
 
-                ic_cdk::println!("Minting NFTs {:?}", mint_args);
+                log!(Debug, Indexer, "Minting NFTs {:?}", mint_args);
 
                 // Call icrc7_mint function directly (your mint logic)
                 match icrc7_mint(vec![mint_args]).await {
                     results => {
                         if let Some(result) = results.first() {
                             match result {
-                                Ok(token_id) => {
-                                    ic_cdk::println!(
-                                        "✅ Successfully minted bridged NFT with token ID: {}",
-                                        token_id
+                                Ok(minted_id) => {
+                                    log!(
+                                        Info,
+                                        Indexer,
+                                        "Successfully minted bridged NFT with token ID: {}",
+                                        minted_id
                                     );
-
-                                    // Call mint_nft_release on your Solidity contract passing the required data
-                                    // Assuming 'self' has the method call_mint_nft_release and you have access here
-                                    // Also assuming 'destination_address', 'name', 'description', 'image', and 'price' are in scope
-
-                                    let owner = destination_address.clone(); // from decoded event
-                                                                             // price is u64 from the decoded burn event
-
-                                    // Note: If you are inside an async context, await the call
-                                    match self
-                                        .call_mint_nft_release(
-                                            owner,
+                                    token_origin::record_origin(
+                                        minted_id,
+                                        SEPOLIA_CHAIN_ID,
+                                        &log_entry.address,
+                                        token_id.clone(),
+                                    );
+                                    if stays_on_icp {
+                                        log!(
+                                            Info,
+                                            Indexer,
+                                            "Token {} stays on ICP with {}",
+                                            minted_id,
+                                            recipient
+                                        );
+                                    } else {
+                                        // Call mint_nft_release on your Solidity contract passing the required data
+                                        // Assuming 'self' has the method call_mint_nft_release and you have access here
+                                        // Also assuming 'destination_address', 'name', 'description', 'image', and 'price' are in scope
+
+                                        let release = ReleaseRequest {
+                                            chain_id: HOLESKY_CHAIN_ID,
+                                            source_address: owner.clone(),
+                                            owner: destination_address.clone(), // from decoded event
                                             name,
                                             description,
                                             image,
                                             price,
-                                        )
-                                        .await
-                                    {
-                                        Ok(sol_token_id) => {
-                                            ic_cdk::println!("✅ Called mint_nft_release successfully, Solidity minted token ID: {}", sol_token_id);
-                                        }
-                                        Err(err) => {
-                                            ic_cdk::println!(
-                                                "❌ Failed to call mint_nft_release: {}",
-                                                err
-                                            );
+                                        };
+
+                                        // Note: If you are inside an async context, await the call
+                                        match self.submit_release(release).await {
+                                            Ok(ReleaseOutcome::Released { tx_hash }) => {
+                                                log!(
+                                                    Info,
+                                                    Indexer,
+                                                    "Called mint_nft_release successfully, tx hash: {}",
+                                                    tx_hash
+                                                );
+                                            }
+                                            Ok(ReleaseOutcome::Deferred {
+                                                queue_id,
+                                                eligible_at,
+                                                reason,
+                                            }) => {
+                                                log!(
+                                                    Info,
+                                                    Indexer,
+                                                    "Release deferred ({:?}), queued as #{} until {}",
+                                                    reason,
+                                                    queue_id,
+                                                    eligible_at
+                                                );
+                                            }
+                                            Err(err) => {
+                                                log!(
+                                                    Error,
+                                                    Indexer,
+                                                    "Failed to call mint_nft_release: {}",
+                                                    err
+                                                );
+                                            }
                                         }
                                     }
                                 }
                                 Err(e) => {
-                                    ic_cdk::println!("❌ Failed to mint bridged NFT: {}", e);
+                                    log!(Error, Indexer, "Failed to mint bridged NFT: {:?}", e);
                                 }
                             }
                         }
@@ -344,7 +521,12 @@ impl ChainService {
                 ));
             } else {
                 if !failed_tx_hashes.contains(&tx_hash) {
-                    ic_cdk::println!("❌ Failed to decode burn event for Tx: {}", tx_hash);
+                    log!(
+                        Error,
+                        Indexer,
+                        "Failed to decode burn event for Tx: {}",
+                        tx_hash
+                    );
                     failed_tx_hashes.insert(tx_hash.clone());
                 }
                 burn_log_summaries.push(format!(
@@ -352,10 +534,18 @@ impl ChainService {
                     tx_hash, block_num
                 ));
             }
+
+            mark_processed(log_key);
         }
 
-        ic_cdk::println!(
-            "✅ fetch_burn_logs completed with {} entries",
+        prune_processed_logs(
+            SEPOLIA_CHAIN_ID,
+            from_block.saturating_sub(PROCESSED_LOG_RETENTION_BLOCKS),
+        );
+        log!(
+            Info,
+            Indexer,
+            "Fetch_burn_logs completed with {} entries",
             burn_log_summaries.len()
         );
 
@@ -367,8 +557,10 @@ impl ChainService {
         from_block: u64,
         to_block: u64,
     ) -> Result<Vec<String>, String> {
-        ic_cdk::println!(
-            "🚀 Starting fetch_burn_logs_reverse (Holesky to Sepolia) from block {} to {}",
+        log!(
+            Info,
+            Indexer,
+            "Starting fetch_burn_logs_reverse (Holesky to Sepolia) from block {} to {}",
             from_block,
             to_block
         );
@@ -446,6 +638,15 @@ impl ChainService {
                 .clone()
                 .map(|n| n.to_string())
                 .unwrap_or_else(|| "N/A".to_string());
+            let log_key = processed_log_key(
+                HOLESKY_CHAIN_ID,
+                log_entry.blockNumber.as_ref(),
+                &tx_hash,
+                log_entry.logIndex.as_ref(),
+            );
+            if is_processed(&log_key) {
+                continue;
+            }
 
             // Decode the event from topics and data (full expanded event)
             if let Some((
@@ -462,37 +663,85 @@ impl ChainService {
                 timestamp,
             )) = Self::decode_nft_burn_event_from_log(&log_entry.topics, &log_entry.data)
             {
-                ic_cdk::println!("  Tx Hash: {}", tx_hash);
-                ic_cdk::println!("  Token ID: {}", token_id);
-                ic_cdk::println!("  Owner: {}", owner);
-                ic_cdk::println!("  Name: {}", name);
-                ic_cdk::println!("  Description: {}", description);
-                ic_cdk::println!("  Image: {}", image);
-                ic_cdk::println!("  Price: {}", price);
-                ic_cdk::println!("  For Sale: {}", for_sale);
-                ic_cdk::println!("  Created At: {}", created_at);
-                ic_cdk::println!("  Destination Chain: {}", destination_chain);
-                ic_cdk::println!("  Destination Address: {}", destination_address);
-                ic_cdk::println!("  Timestamp: {}", timestamp);
+                if let Err(e) = Self::ensure_route_active(HOLESKY_CHAIN_ID, SEPOLIA_CHAIN_ID, false)
+                    .and_then(|_| bridge_control::record_burn(HOLESKY_CHAIN_ID))
+                {
+                    log!(
+                        Warn,
+                        Indexer,
+                        "Halting burn processing at Tx {}: {}",
+                        tx_hash,
+                        e
+                    );
+                    Self::rewind_block_number(&log_entry.blockNumber);
+                    break;
+                }
+
+                log!(
+                    Debug,
+                    Indexer,
+                    "Decoded burn: tx {} token {} owner {} name '{}' description '{}' image '{}' price {} for_sale {} created_at {} destination {} {} at {}",
+                    tx_hash,
+                    token_id,
+                    owner,
+                    name,
+                    description,
+                    image,
+                    price,
+                    for_sale,
+                    created_at,
+                    destination_chain,
+                    destination_address,
+                    timestamp
+                );
 
                 // For reverse flow, we need to release NFT on Sepolia
-                ic_cdk::println!(
-                    "🔥 Attempting to release NFT on Sepolia from Holesky burn event..."
+                log!(
+                    Debug,
+                    Indexer,
+                    "Attempting to release NFT on Sepolia from Holesky burn event..."
                 );
 
                 // Call mint_nft_release on Sepolia contract
-                match self
-                    .call_mint_nft_release_sepolia(owner.clone(), name, description, image, price)
-                    .await
-                {
-                    Ok(sol_token_id) => {
-                        ic_cdk::println!(
-                            "✅ Called mint_nft_release on Sepolia successfully, token ID: {}",
-                            sol_token_id
+                let release = ReleaseRequest {
+                    chain_id: SEPOLIA_CHAIN_ID,
+                    source_address: owner.clone(),
+                    owner: owner.clone(),
+                    name,
+                    description,
+                    image,
+                    price,
+                };
+                match self.submit_release(release).await {
+                    Ok(ReleaseOutcome::Released { tx_hash }) => {
+                        log!(
+                            Info,
+                            Indexer,
+                            "Called mint_nft_release on Sepolia successfully, tx hash: {}",
+                            tx_hash
+                        );
+                    }
+                    Ok(ReleaseOutcome::Deferred {
+                        queue_id,
+                        eligible_at,
+                        reason,
+                    }) => {
+                        log!(
+                            Info,
+                            Indexer,
+                            "Sepolia release deferred ({:?}), queued as #{} until {}",
+                            reason,
+                            queue_id,
+                            eligible_at
                         );
                     }
                     Err(err) => {
-                        ic_cdk::println!("❌ Failed to call mint_nft_release on Sepolia: {}", err);
+                        log!(
+                            Error,
+                            Indexer,
+                            "Failed to call mint_nft_release on Sepolia: {}",
+                            err
+                        );
                     }
                 }
 
@@ -502,7 +751,12 @@ impl ChainService {
                 ));
             } else {
                 if !failed_tx_hashes.contains(&tx_hash) {
-                    ic_cdk::println!("❌ Failed to decode burn event for Tx: {}", tx_hash);
+                    log!(
+                        Error,
+                        Indexer,
+                        "Failed to decode burn event for Tx: {}",
+                        tx_hash
+                    );
                     failed_tx_hashes.insert(tx_hash.clone());
                 }
                 burn_log_summaries.push(format!(
@@ -510,10 +764,18 @@ impl ChainService {
                     tx_hash, block_num
                 ));
             }
+
+            mark_processed(log_key);
         }
 
-        ic_cdk::println!(
-            "✅ fetch_burn_logs_reverse completed with {} entries",
+        prune_processed_logs(
+            HOLESKY_CHAIN_ID,
+            from_block.saturating_sub(PROCESSED_LOG_RETENTION_BLOCKS),
+        );
+        log!(
+            Info,
+            Indexer,
+            "Fetch_burn_logs_reverse completed with {} entries",
             burn_log_summaries.len()
         );
 
@@ -525,12 +787,12 @@ impl ChainService {
         topics: &Vec<String>,
         data: &str,
     ) -> Option<(
-        u64,    // tokenId
+        Nat,    // tokenId
         String, // owner
         String, // name
         String, // description
         String, // image
-        u64,    // price
+        Nat,    // price
         bool,   // forSale
         u64,    // createdAt
         String, // destinationChain
@@ -547,11 +809,7 @@ impl ChainService {
         if token_id_bytes.len() != 32 {
             return None;
         }
-        let token_id = {
-            let mut arr = [0u8; 8];
-            arr.copy_from_slice(&token_id_bytes[24..32]); // last 8 bytes as big endian u64
-            u64::from_be_bytes(arr)
-        };
+        let token_id = u256_to_nat(U256::from_big_endian(&token_id_bytes));
 
         // Decode owner address from topic[2] (address - last 20 bytes of 32-byte topic)
         let owner_bytes = hex_decode(topics[2].trim_start_matches("0x")).ok()?;
@@ -598,7 +856,7 @@ impl ChainService {
             _ => return None,
         };
         let price = match &tokens[3] {
-            Token::Uint(n) => n.as_u64(),
+            Token::Uint(n) => u256_to_nat(*n),
             _ => return None,
         };
         let for_sale = match &tokens[4] {
@@ -606,7 +864,7 @@ impl ChainService {
             _ => return None,
         };
         let created_at = match &tokens[5] {
-            Token::Uint(n) => n.as_u64(),
+            Token::Uint(n) => u64::try_from(*n).ok()?,
             _ => return None,
         };
         let destination_chain = match &tokens[6] {
@@ -618,7 +876,7 @@ impl ChainService {
             _ => return None,
         };
         let timestamp = match &tokens[8] {
-            Token::Uint(n) => n.as_u64(),
+            Token::Uint(n) => u64::try_from(*n).ok()?,
             _ => return None,
         };
 
@@ -654,7 +912,7 @@ impl ChainService {
 
         // Just run once immediately, no interval
         ic_cdk::spawn(async move {
-            ic_cdk::println!("start_periodic_fetch_reverse");
+            log!(Debug, Indexer, "start_periodic_fetch_reverse");
             service_clone.fetch_logs_and_update_time_reverse().await;
         });
 
@@ -663,14 +921,19 @@ impl ChainService {
     }
 
     pub async fn fetch_logs_and_update_time(&self) {
-        ic_cdk::println!("start_monitoring.");
+        log!(Debug, Indexer, "start_monitoring.");
+
+        if let Err(e) = Self::ensure_route_active(SEPOLIA_CHAIN_ID, HOLESKY_CHAIN_ID, true) {
+            log!(Warn, Indexer, "Skipping fetch: {}", e);
+            return;
+        }
 
         // Read the last checked block number
-        let from_block = BLOCK_NUMBER.with(|block_num: &RefCell<u64>| *block_num.borrow());
-        ic_cdk::println!("Read BLOCK_NUMBER: {}", from_block);
+        let from_block = block_number();
+        log!(Debug, Indexer, "Read BLOCK_NUMBER: {}", from_block);
 
         // Build RPC call
-        ic_cdk::println!("About to call eth_get_block_by_number");
+        log!(Debug, Indexer, "About to call eth_get_block_by_number");
 
         let rpc_services = RpcServices::Custom {
             chainId: 11155111,
@@ -703,26 +966,33 @@ impl ChainService {
         let highest_block_number: u64 = match result {
             Ok((multi_result,)) => match multi_result {
                 MultiGetBlockByNumberResult::Consistent(GetBlockByNumberResult::Ok(block)) => {
-                    ic_cdk::println!("✅ Block result OK, extracting number");
+                    log!(Debug, Indexer, "Block result OK, extracting number");
                     Self::nat_to_u64(block.number)
                 }
                 MultiGetBlockByNumberResult::Consistent(GetBlockByNumberResult::Err(err)) => {
-                    ic_cdk::println!("❌ Error inside block result: {:?}", err);
+                    log!(Error, Indexer, "Error inside block result: {:?}", err);
                     return;
                 }
                 MultiGetBlockByNumberResult::Inconsistent(providers) => {
-                    ic_cdk::println!("⚠ Inconsistent provider response: {:?}", providers);
+                    log!(
+                        Warn,
+                        Indexer,
+                        "Inconsistent provider response: {:?}",
+                        providers
+                    );
                     return;
                 }
             },
             Err((code, msg)) => {
-                ic_cdk::println!("❌ Canister call failed: {:?} - {}", code, msg);
+                log!(Error, Indexer, "Canister call failed: {:?} - {}", code, msg);
                 return;
             }
         };
 
         // Continue logic
-        ic_cdk::println!(
+        log!(
+            Debug,
+            Indexer,
             "highest_block_number: {}, from_block: {}",
             highest_block_number,
             from_block
@@ -736,15 +1006,15 @@ impl ChainService {
                                         // };
 
         // 8841826 > 8842202
-        ic_cdk::println!(
+        log!(
+            Debug,
+            Indexer,
             "Fetching logs from_block: {}, to_block: {}",
             from_block,
             to_block
         );
 
-        BLOCK_NUMBER.with(|block_num| {
-            *block_num.borrow_mut() = to_block;
-        });
+        set_block_number(to_block);
 
         if let Err(e) = self
             .fetch_burn_logs(
@@ -754,22 +1024,35 @@ impl ChainService {
             )
             .await
         {
-            ic_cdk::println!("Error fetching logs: {}", e);
+            log!(Error, Indexer, "Error fetching logs: {}", e);
             return;
         }
 
-        ic_cdk::println!("✅ fetch_logs completed successfully");
+        log!(Info, Indexer, "fetch_logs completed successfully");
     }
 
     pub async fn fetch_logs_and_update_time_reverse(&self) {
-        ic_cdk::println!("start_monitoring_reverse (Holesky to Sepolia).");
+        log!(
+            Debug,
+            Indexer,
+            "start_monitoring_reverse (Holesky to Sepolia)."
+        );
+
+        if let Err(e) = Self::ensure_route_active(HOLESKY_CHAIN_ID, SEPOLIA_CHAIN_ID, false) {
+            log!(Warn, Indexer, "Skipping fetch: {}", e);
+            return;
+        }
 
         // Read the last checked block number
-        let from_block = BLOCK_NUMBER.with(|block_num: &RefCell<u64>| *block_num.borrow());
-        ic_cdk::println!("Read BLOCK_NUMBER: {}", from_block);
+        let from_block = block_number();
+        log!(Debug, Indexer, "Read BLOCK_NUMBER: {}", from_block);
 
         // Build RPC call for Holesky
-        ic_cdk::println!("About to call eth_get_block_by_number on Holesky");
+        log!(
+            Debug,
+            Indexer,
+            "About to call eth_get_block_by_number on Holesky"
+        );
 
         let rpc_services = RpcServices::Custom {
             chainId: 17000, // Holesky chain ID
@@ -802,26 +1085,33 @@ impl ChainService {
         let highest_block_number: u64 = match result {
             Ok((multi_result,)) => match multi_result {
                 MultiGetBlockByNumberResult::Consistent(GetBlockByNumberResult::Ok(block)) => {
-                    ic_cdk::println!("✅ Block result OK, extracting number");
+                    log!(Debug, Indexer, "Block result OK, extracting number");
                     Self::nat_to_u64(block.number)
                 }
                 MultiGetBlockByNumberResult::Consistent(GetBlockByNumberResult::Err(err)) => {
-                    ic_cdk::println!("❌ Error inside block result: {:?}", err);
+                    log!(Error, Indexer, "Error inside block result: {:?}", err);
                     return;
                 }
                 MultiGetBlockByNumberResult::Inconsistent(providers) => {
-                    ic_cdk::println!("⚠ Inconsistent provider response: {:?}", providers);
+                    log!(
+                        Warn,
+                        Indexer,
+                        "Inconsistent provider response: {:?}",
+                        providers
+                    );
                     return;
                 }
             },
             Err((code, msg)) => {
-                ic_cdk::println!("❌ Canister call failed: {:?} - {}", code, msg);
+                log!(Error, Indexer, "Canister call failed: {:?} - {}", code, msg);
                 return;
             }
         };
 
         // Continue logic
-        ic_cdk::println!(
+        log!(
+            Debug,
+            Indexer,
             "highest_block_number_HOLESKY: {}, from_block: {}",
             highest_block_number,
             from_block
@@ -834,22 +1124,55 @@ impl ChainService {
         //     highest_block_number
         // };
 
-        ic_cdk::println!(
+        log!(
+            Debug,
+            Indexer,
             "Fetching logs from_block: {}, to_block: {}",
             from_block,
             to_block
         );
 
-        BLOCK_NUMBER.with(|block_num| {
-            *block_num.borrow_mut() = to_block;
-        });
+        set_block_number(to_block);
 
         if let Err(e) = self.fetch_burn_logs_reverse(from_block, to_block).await {
-            ic_cdk::println!("Error fetching logs: {}", e);
+            log!(Error, Indexer, "Error fetching logs: {}", e);
             return;
         }
 
-        ic_cdk::println!("✅ fetch_logs_reverse completed successfully");
+        log!(Info, Indexer, "fetch_logs_reverse completed successfully");
+    }
+
+    /// Checks every pause that would affect a burn on `source_chain_id` released on `dest_chain_id`
+    fn ensure_route_active(
+        source_chain_id: u64,
+        dest_chain_id: u64,
+        mints_on_icp: bool,
+    ) -> Result<(), String> {
+        bridge_control::ensure_active(
+            source_chain_id,
+            BridgeDirection::EvmToIcp,
+            BridgeOperation::Index,
+        )?;
+        if mints_on_icp {
+            bridge_control::ensure_active(
+                source_chain_id,
+                BridgeDirection::EvmToIcp,
+                BridgeOperation::Mint,
+            )?;
+        }
+        bridge_control::ensure_active(
+            dest_chain_id,
+            BridgeDirection::IcpToEvm,
+            BridgeOperation::Release,
+        )
+    }
+
+    /// Move the scan cursor back so unprocessed logs from `block_number` onward are fetched again
+    fn rewind_block_number(block_number: &Option<Nat>) {
+        if let Some(block_number) = block_number {
+            let block_number = Self::nat_to_u64(block_number.clone());
+            set_block_number(block_number);
+        }
     }
 
     pub fn nat_to_u64(nat: Nat) -> u64 {
@@ -884,12 +1207,69 @@ impl ChainService {
     }
 }
 
+fn block_number() -> u64 {
+    BLOCK_NUMBER.with(|num| *num.borrow().get())
+}
+
+fn set_block_number(block_number: u64) {
+    // Only fails when stable memory cannot grow, in which case nothing else persists either
+    if let Err(e) = BLOCK_NUMBER.with(|num| num.borrow_mut().set(block_number)) {
+        log!(Error, Indexer, "Failed to store block number: {:?}", e);
+    }
+}
+
+fn processed_log_key(
+    chain_id: u64,
+    block_number: Option<&Nat>,
+    tx_hash: &str,
+    log_index: Option<&Nat>,
+) -> String {
+    use num_traits::cast::ToPrimitive;
+    format!(
+        "{}:{:020}:{}:{}",
+        chain_id,
+        block_number.and_then(|n| n.0.to_u64()).unwrap_or_default(),
+        tx_hash,
+        log_index.map(|n| n.to_string()).unwrap_or_default()
+    )
+}
+
+fn is_processed(log_key: &str) -> bool {
+    PROCESSED_BURN_LOGS.with(|p| p.borrow().contains_key(&log_key.to_string()))
+}
+
+fn mark_processed(log_key: String) {
+    PROCESSED_BURN_LOGS.with(|p| p.borrow_mut().insert(log_key, ()));
+}
+
+/// Forget the processed logs of `chain_id` from blocks below `below_block`
+fn prune_processed_logs(chain_id: u64, below_block: u64) {
+    PROCESSED_BURN_LOGS.with(|p| {
+        let mut processed = p.borrow_mut();
+        let start = format!("{}:", chain_id);
+        let end = format!("{}:{:020}", chain_id, below_block);
+        let stale: Vec<String> = processed.range(start..end).map(|(key, _)| key).collect();
+        for key in stale {
+            processed.remove(&key);
+        }
+    });
+}
+
+/// Lossless conversion of an EVM uint256 into a candid Nat
+pub fn u256_to_nat(value: U256) -> Nat {
+    Nat::from_str(&value.to_string()).expect("U256 always formats as a decimal number")
+}
+
+/// Inverse of `u256_to_nat`; fails for values that do not fit in uint256
+pub fn nat_to_u256(value: &Nat) -> Result<U256, String> {
+    U256::from_dec_str(&value.0.to_string())
+        .map_err(|e| format!("{} does not fit in uint256: {:?}", value, e))
+}
+
 #[update]
 pub fn update_block_number(new_block_num: u64) -> Result<String, String> {
-    ic_cdk::println!("Updating block number to {}", new_block_num);
-    BLOCK_NUMBER.with(|num| {
-        *num.borrow_mut() = new_block_num;
-    });
+    log!(Debug, Indexer, "Updating block number to {}", new_block_num);
+    set_block_number(new_block_num);
     Ok(format!("BLOCK_NUMBER updated to {}", new_block_num))
 }
 
@@ -936,3 +1316,55 @@ pub fn update_block_number(new_block_num: u64) -> Result<String, String> {
 //         }
 //     }
 // }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{MetadataValue, Value};
+    use candid::{Decode, Encode};
+
+    const TWO_POW_64: &str = "18446744073709551616";
+    const U256_MAX: &str =
+        "115792089237316195423570985008687907853269984665640564039457584007913129639935";
+
+    fn large_values() -> Vec<(U256, &'static str)> {
+        vec![
+            (U256::from(u64::MAX) + U256::one(), TWO_POW_64),
+            (U256::MAX, U256_MAX),
+        ]
+    }
+
+    #[test]
+    fn u256_round_trips_through_nat() {
+        for (value, decimal) in large_values() {
+            let nat = u256_to_nat(value);
+            assert_eq!(nat.0.to_string(), decimal);
+            assert_eq!(nat_to_u256(&nat).unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn nat_above_uint256_is_rejected() {
+        let too_big = Nat::from_str(U256_MAX).unwrap() + Nat::from(1u8);
+        assert!(nat_to_u256(&too_big).is_err());
+    }
+
+    #[test]
+    fn u256_survives_metadata_value_storage() {
+        for (value, decimal) in large_values() {
+            let stored = MetadataValue::Nat(u256_to_nat(value));
+
+            // Stored as an ICRC-7 Value on the token, candid-encoded across calls
+            let bytes = Encode!(&Value::from(stored)).unwrap();
+            let restored = MetadataValue::from(Decode!(&bytes, Value).unwrap());
+
+            match restored {
+                MetadataValue::Nat(nat) => {
+                    assert_eq!(nat.0.to_string(), decimal);
+                    assert_eq!(nat_to_u256(&nat).unwrap(), value);
+                }
+                other => panic!("expected MetadataValue::Nat, got {:?}", other),
+            }
+        }
+    }
+}
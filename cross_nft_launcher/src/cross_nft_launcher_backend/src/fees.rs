@@ -0,0 +1,518 @@
+use candid::{CandidType, Nat, Principal};
+use ethabi::ethereum_types::H160;
+use ic_cdk::api::{caller, time};
+use ic_cdk::{query, update};
+use ic_ledger_types::{
+    transfer, AccountIdentifier, Memo, Subaccount, Tokens, TransferArgs, DEFAULT_FEE,
+    DEFAULT_SUBACCOUNT, MAINNET_LEDGER_CANISTER_ID,
+};
+use ic_stable_structures::{StableBTreeMap, StableCell};
+use num_traits::ToPrimitive;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::str::FromStr;
+
+use crate::bridge_control::{self, BridgeDirection, BridgeOperation};
+use crate::deposit;
+use crate::erc721;
+use crate::evm_nft_indexer::{CHAIN_SERVICE, HOLESKY_CHAIN_ID, SEPOLIA_CHAIN_ID};
+use crate::http;
+use crate::log;
+use crate::memory::{
+    candid_storable, get_memory, Memory, COLLECTED_FEES_MEMORY_ID, FEE_CONFIG_MEMORY_ID,
+    PENDING_BRIDGE_BURNS_MEMORY_ID,
+};
+use crate::rate_limit::ReleaseOutcome;
+use crate::relayer_health;
+use crate::release_nft::{estimate_transaction_fees, ReleaseRequest, RELEASE_GAS_LIMIT};
+use crate::token_origin;
+use crate::{
+    ensure_admin, record_transaction, Account, Token, TransactionType, Value, TOKENS,
+    TOKEN_APPROVALS,
+};
+
+const WEI_PER_ETH: u128 = 1_000_000_000_000_000_000;
+const WITHDRAW_MEMO: Memo = Memo(0x5749_5448); // "WITH"
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct ChainFee {
+    pub chain_id: u64,
+    pub flat_fee_e8s: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct FeeConfig {
+    pub ledger_canister_id: Principal,
+    pub flat_fees: Vec<ChainFee>,
+    // ICP (in e8s) charged per 1 ETH of estimated release gas cost
+    pub e8s_per_eth: u64,
+}
+
+impl Default for FeeConfig {
+    fn default() -> Self {
+        FeeConfig {
+            ledger_canister_id: MAINNET_LEDGER_CANISTER_ID,
+            flat_fees: vec![
+                ChainFee {
+                    chain_id: SEPOLIA_CHAIN_ID,
+                    flat_fee_e8s: 1_000_000,
+                },
+                ChainFee {
+                    chain_id: HOLESKY_CHAIN_ID,
+                    flat_fee_e8s: 1_000_000,
+                },
+            ],
+            e8s_per_eth: 30_000_000_000,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct FeeQuote {
+    pub chain_id: u64,
+    pub flat_fee_e8s: u64,
+    pub gas_fee_e8s: u64,
+    pub total_e8s: u64,
+    // Charged by the ICP ledger when the deposit is swept into the canister account
+    pub ledger_fee_e8s: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct BridgeToEvmArgs {
+    pub token_id: Nat,
+    pub destination_chain_id: u64,
+    pub destination_address: String,
+}
+
+/// Destination a holder names when burning a token they want released on an EVM chain
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct BridgeIntent {
+    pub destination_chain_id: u64,
+    pub destination_address: String,
+}
+
+/// A token burned with a bridge intent, waiting for its owner to pay the bridge fee
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct PendingBridgeBurn {
+    pub block_index: Nat,
+    pub owner: Principal,
+    pub token_id: Nat,
+    pub release: ReleaseRequest,
+    pub burned_at: u64,
+}
+
+candid_storable!(FeeConfig, PendingBridgeBurn);
+
+thread_local! {
+    static FEE_CONFIG: RefCell<StableCell<FeeConfig, Memory>> = RefCell::new(
+        StableCell::init(get_memory(FEE_CONFIG_MEMORY_ID), FeeConfig::default())
+            .expect("failed to init fee config")
+    );
+
+    static COLLECTED_FEES_E8S: RefCell<StableCell<u64, Memory>> = RefCell::new(
+        StableCell::init(get_memory(COLLECTED_FEES_MEMORY_ID), 0)
+            .expect("failed to init collected fees")
+    );
+
+    // Burn block index -> release owed for an icrc7_burn with a bridge intent
+    static PENDING_BRIDGE_BURNS: RefCell<StableBTreeMap<u64, PendingBridgeBurn, Memory>> = RefCell::new(
+        StableBTreeMap::init(get_memory(PENDING_BRIDGE_BURNS_MEMORY_ID))
+    );
+}
+
+fn fee_config() -> FeeConfig {
+    FEE_CONFIG.with(|c| c.borrow().get().clone())
+}
+
+pub fn ledger_canister_id() -> Principal {
+    fee_config().ledger_canister_id
+}
+
+fn set_collected_fees(amount_e8s: u64) {
+    COLLECTED_FEES_E8S.with(|fees| {
+        fees.borrow_mut()
+            .set(amount_e8s)
+            .expect("failed to persist collected fees")
+    });
+}
+
+// Burn block indices come from the u64 transaction counter
+fn burn_key(block_index: &Nat) -> Option<u64> {
+    block_index.0.to_u64()
+}
+
+pub fn quote_fee(chain_id: u64) -> Result<FeeQuote, String> {
+    let config = fee_config();
+    let flat_fee_e8s = config
+        .flat_fees
+        .iter()
+        .find(|fee| fee.chain_id == chain_id)
+        .map(|fee| fee.flat_fee_e8s)
+        .ok_or_else(|| format!("No bridge fee configured for chain {}", chain_id))?;
+
+    // Index the gas part on the network gas price from the last relayer health check; a
+    // release never pays more than the fee cap it is signed with, which is also the
+    // fallback before the first check
+    let (_, max_fee_per_gas, _) = estimate_transaction_fees();
+    let gas_price = relayer_health::gas_price_wei(chain_id)
+        .map_or(max_fee_per_gas, |price| price.min(max_fee_per_gas));
+    let gas_fee_e8s = (RELEASE_GAS_LIMIT as u128)
+        .checked_mul(gas_price)
+        .and_then(|gas_cost_wei| gas_cost_wei.checked_mul(config.e8s_per_eth as u128))
+        .and_then(|e8s_wei| u64::try_from(e8s_wei / WEI_PER_ETH).ok())
+        .ok_or_else(|| format!("Gas fee for chain {} overflows u64 e8s", chain_id))?;
+    let total_e8s = flat_fee_e8s
+        .checked_add(gas_fee_e8s)
+        .ok_or_else(|| format!("Bridge fee for chain {} overflows u64 e8s", chain_id))?;
+
+    Ok(FeeQuote {
+        chain_id,
+        flat_fee_e8s,
+        gas_fee_e8s,
+        total_e8s,
+        ledger_fee_e8s: DEFAULT_FEE.e8s(),
+    })
+}
+
+pub async fn ledger_transfer(
+    from_subaccount: Subaccount,
+    to: AccountIdentifier,
+    amount_e8s: u64,
+    memo: Memo,
+) -> Result<u64, String> {
+    transfer(
+        ledger_canister_id(),
+        TransferArgs {
+            memo,
+            amount: Tokens::from_e8s(amount_e8s),
+            fee: DEFAULT_FEE,
+            from_subaccount: Some(from_subaccount),
+            to,
+            created_at_time: None,
+        },
+    )
+    .await
+    .map_err(|e| format!("Ledger call failed: {:?}", e))?
+    .map_err(|e| format!("Ledger transfer failed: {:?}", e))
+}
+
+/// Sweep any fresh deposit, then take the fee out of the user's internal balance. The fee
+/// is held outside the collected pool until `collect_fee` or `refund_fee` settles it.
+async fn debit_fee(user: &Principal, quote: &FeeQuote) -> Result<(), String> {
+    if let Err(e) = deposit::sweep(user).await {
+        // A failed sweep is not fatal if earlier credits already cover the fee
+        log!(Warn, Ledger, "Deposit sweep for {} failed: {}", user, e);
+    }
+    deposit::debit(user, quote.total_e8s)
+}
+
+/// Move a debited fee into the withdrawable pool once its release is sent or queued
+fn collect_fee(quote: &FeeQuote) {
+    set_collected_fees(get_collected_fees().saturating_add(quote.total_e8s));
+}
+
+/// Return a debited fee to the user's internal balance after a failed release
+fn refund_fee(user: &Principal, quote: &FeeQuote) {
+    deposit::credit(user, quote.total_e8s);
+}
+
+/// Numeric metadata; tokens minted before prices were stored as Nat still carry decimal text
+fn nat_metadata(token: &Token, key: &str) -> Nat {
+    token
+        .metadata
+        .iter()
+        .find_map(|(k, v)| match v {
+            Value::Nat(n) if k == key => Some(n.clone()),
+            Value::Text(t) if k == key => t.parse().ok(),
+            _ => None,
+        })
+        .unwrap_or_default()
+}
+
+/// The EVM contracts take name, description, image and price; read them off the token's
+/// ERC-721 document so icrc97:metadata and legacy flat entries release alike
+fn release_request(token: &Token, source: &Principal, intent: &BridgeIntent) -> ReleaseRequest {
+    let document = erc721::to_erc721_json(&token.metadata);
+    let text = |key: &str| {
+        document
+            .get(key)
+            .and_then(|value| value.as_str())
+            .unwrap_or_default()
+            .to_string()
+    };
+    ReleaseRequest {
+        chain_id: intent.destination_chain_id,
+        source_address: source.to_text(),
+        owner: intent.destination_address.clone(),
+        name: text("name"),
+        description: text("description"),
+        image: text("image"),
+        // Legacy tokens with a JSON "attributes" entry keep their price as a flat entry
+        price: erc721::nat_attribute(&document, "price")
+            .unwrap_or_else(|| nat_metadata(token, "price")),
+    }
+}
+
+/// Memo recorded on the Burn block of a token leaving for an EVM chain
+pub fn bridge_intent_memo(intent: &BridgeIntent) -> Vec<u8> {
+    format!(
+        "Bridge to EVM {}: {}",
+        intent.destination_chain_id, intent.destination_address
+    )
+    .into_bytes()
+}
+
+/// Check a bridge intent before its token is burned, so a burn never strands a release
+pub fn validate_bridge_intent(intent: &BridgeIntent) -> Result<(), String> {
+    bridge_control::ensure_active(
+        intent.destination_chain_id,
+        BridgeDirection::IcpToEvm,
+        BridgeOperation::Release,
+    )?;
+    quote_fee(intent.destination_chain_id)?;
+    H160::from_str(intent.destination_address.trim_start_matches("0x")).map_err(|e| {
+        format!(
+            "Invalid destination address '{}': {}",
+            intent.destination_address, e
+        )
+    })?;
+    Ok(())
+}
+
+/// Remember the release owed for a token burned through icrc7_burn with a bridge intent
+pub fn queue_bridge_burn(block_index: Nat, token: &Token, intent: &BridgeIntent) {
+    let pending = PendingBridgeBurn {
+        block_index: block_index.clone(),
+        owner: token.owner.owner,
+        token_id: token.token_id.clone(),
+        release: release_request(token, &token.owner.owner, intent),
+        burned_at: time(),
+    };
+    log!(
+        Info,
+        Ledger,
+        "Token {} burned for chain {}; release pending as burn {}",
+        pending.token_id,
+        intent.destination_chain_id,
+        block_index
+    );
+    let key = burn_key(&block_index).expect("burn block index exceeds u64");
+    PENDING_BRIDGE_BURNS.with(|p| p.borrow_mut().insert(key, pending));
+}
+
+#[update]
+pub async fn bridge_to_evm(args: BridgeToEvmArgs) -> Result<ReleaseOutcome, String> {
+    let user = caller();
+    bridge_control::ensure_active(
+        args.destination_chain_id,
+        BridgeDirection::IcpToEvm,
+        BridgeOperation::Release,
+    )?;
+
+    let token = TOKENS
+        .with(|tokens| tokens.borrow().get(&args.token_id.to_string()).cloned())
+        .ok_or_else(|| format!("Token {} does not exist", args.token_id))?;
+    if token.owner.owner != user {
+        return Err("Unauthorized: caller does not own this token".to_string());
+    }
+
+    let service = CHAIN_SERVICE
+        .with(|cs| cs.borrow().clone())
+        .ok_or_else(|| "ChainService is not initialized".to_string())?;
+
+    let quote = quote_fee(args.destination_chain_id)?;
+    debit_fee(&user, &quote).await?;
+    log!(
+        Info,
+        Ledger,
+        "Debited bridge fee of {} e8s from {}",
+        quote.total_e8s,
+        user
+    );
+
+    // Take the token out of circulation before releasing it on the EVM side;
+    // ownership is checked again because the fee debit awaited the ledger
+    let key = args.token_id.to_string();
+    let token = match TOKENS.with(|tokens| tokens.borrow_mut().remove(&key)) {
+        Some(token) if token.owner.owner == user => token,
+        other => {
+            if let Some(token) = other {
+                TOKENS.with(|tokens| tokens.borrow_mut().insert(key.clone(), token));
+            }
+            refund_fee(&user, &quote);
+            return Err("Token changed hands while the fee was being debited".to_string());
+        }
+    };
+    let approvals = TOKEN_APPROVALS.with(|approvals| approvals.borrow_mut().remove(&key));
+    http::certify_token(&args.token_id);
+
+    let intent = BridgeIntent {
+        destination_chain_id: args.destination_chain_id,
+        destination_address: args.destination_address.clone(),
+    };
+    let release = release_request(&token, &user, &intent);
+
+    match service.submit_release(release).await {
+        Ok(outcome) => {
+            collect_fee(&quote);
+            token_origin::forget_origin(&token.token_id);
+            record_transaction(
+                TransactionType::Burn {
+                    from: token.owner.clone(),
+                    token_id: token.token_id.clone(),
+                },
+                Some(bridge_intent_memo(&intent)),
+            );
+            Ok(outcome)
+        }
+        Err(e) => {
+            TOKENS.with(|tokens| tokens.borrow_mut().insert(key.clone(), token));
+            if let Some(approval) = approvals {
+                TOKEN_APPROVALS.with(|a| a.borrow_mut().insert(key, approval));
+            }
+            http::certify_token(&args.token_id);
+            refund_fee(&user, &quote);
+            Err(e)
+        }
+    }
+}
+
+pub fn pending_bridge_burn(block_index: &Nat) -> Option<PendingBridgeBurn> {
+    let key = burn_key(block_index)?;
+    PENDING_BRIDGE_BURNS.with(|p| p.borrow().get(&key))
+}
+
+/// Pay the bridge fee for a token burned with a bridge intent and release it
+#[update]
+pub async fn release_burned_token(block_index: Nat) -> Result<ReleaseOutcome, String> {
+    let user = caller();
+    // Taken out of the queue while the fee is debited so it cannot be released twice
+    let key = burn_key(&block_index)
+        .ok_or_else(|| format!("No pending bridge burn at block {}", block_index))?;
+    let pending = match PENDING_BRIDGE_BURNS.with(|p| p.borrow_mut().remove(&key)) {
+        Some(pending) if pending.owner == user => pending,
+        Some(pending) => {
+            PENDING_BRIDGE_BURNS.with(|p| p.borrow_mut().insert(key, pending));
+            return Err("Unauthorized: caller did not burn this token".to_string());
+        }
+        None => return Err(format!("No pending bridge burn at block {}", block_index)),
+    };
+    let requeue = |pending: PendingBridgeBurn| {
+        PENDING_BRIDGE_BURNS.with(|p| p.borrow_mut().insert(key, pending));
+    };
+
+    let chain_id = pending.release.chain_id;
+    let service = match CHAIN_SERVICE.with(|cs| cs.borrow().clone()) {
+        Some(service) => service,
+        None => {
+            requeue(pending);
+            return Err("ChainService is not initialized".to_string());
+        }
+    };
+    let quote = match bridge_control::ensure_active(
+        chain_id,
+        BridgeDirection::IcpToEvm,
+        BridgeOperation::Release,
+    )
+    .and_then(|_| quote_fee(chain_id))
+    {
+        Ok(quote) => quote,
+        Err(e) => {
+            requeue(pending);
+            return Err(e);
+        }
+    };
+    if let Err(e) = debit_fee(&user, &quote).await {
+        requeue(pending);
+        return Err(e);
+    }
+
+    match service.submit_release(pending.release.clone()).await {
+        Ok(outcome) => {
+            collect_fee(&quote);
+            Ok(outcome)
+        }
+        Err(e) => {
+            refund_fee(&user, &quote);
+            requeue(pending);
+            Err(e)
+        }
+    }
+}
+
+#[query]
+pub fn get_pending_bridge_burns(owner: Principal) -> Vec<PendingBridgeBurn> {
+    PENDING_BRIDGE_BURNS.with(|p| {
+        p.borrow()
+            .iter()
+            .map(|(_, pending)| pending)
+            .filter(|pending| pending.owner == owner)
+            .collect()
+    })
+}
+
+#[query]
+pub fn get_bridge_fee_quote(chain_id: u64) -> Result<FeeQuote, String> {
+    quote_fee(chain_id)
+}
+
+#[query]
+pub fn get_fee_config() -> FeeConfig {
+    fee_config()
+}
+
+#[update]
+pub fn set_fee_config(config: FeeConfig) -> Result<(), String> {
+    ensure_admin()?;
+    FEE_CONFIG
+        .with(|c| c.borrow_mut().set(config))
+        .map_err(|e| format!("Failed to store fee config: {:?}", e))?;
+    Ok(())
+}
+
+#[query]
+pub fn get_collected_fees() -> u64 {
+    COLLECTED_FEES_E8S.with(|fees| *fees.borrow().get())
+}
+
+#[update]
+pub async fn withdraw_fees(to: Account, amount_e8s: u64) -> Result<u64, String> {
+    ensure_admin()?;
+    // The ledger fee is paid from the canister account, which also holds users' swept
+    // balances, so it comes out of the collected pool too
+    let collected = get_collected_fees();
+    let reserved_e8s = amount_e8s
+        .checked_add(DEFAULT_FEE.e8s())
+        .filter(|total| *total <= collected)
+        .ok_or_else(|| {
+            format!(
+                "Requested {} e8s plus the {} e8s ledger fee but only {} e8s of fees were collected",
+                amount_e8s,
+                DEFAULT_FEE.e8s(),
+                collected
+            )
+        })?;
+
+    let to_subaccount = match to.subaccount {
+        Some(bytes) => Subaccount(
+            bytes
+                .try_into()
+                .map_err(|_| "Subaccount must be 32 bytes".to_string())?,
+        ),
+        None => DEFAULT_SUBACCOUNT,
+    };
+    let destination = AccountIdentifier::new(&to.owner, &to_subaccount);
+
+    // Reserve the amount and ledger fee up front so concurrent withdrawals cannot overdraw
+    set_collected_fees(collected - reserved_e8s);
+    match ledger_transfer(DEFAULT_SUBACCOUNT, destination, amount_e8s, WITHDRAW_MEMO).await {
+        Ok(block_index) => {
+            log!(Info, Ledger, "Withdrew {} e8s of bridge fees", amount_e8s);
+            Ok(block_index)
+        }
+        Err(e) => {
+            set_collected_fees(get_collected_fees().saturating_add(reserved_e8s));
+            Err(e)
+        }
+    }
+}
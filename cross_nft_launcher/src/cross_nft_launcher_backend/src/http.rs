@@ -0,0 +1,240 @@
+use candid::{CandidType, Nat};
+use ic_cdk::query;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value as Json};
+
+use crate::assets::{self, Asset};
+use crate::certification;
+use crate::collection;
+use crate::ensure_admin;
+use crate::erc721;
+use crate::logs::{self, LogComponent, LogFilter, LogLevel};
+use crate::metadata_policy;
+use crate::{Token, TOKENS};
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct HttpRequest {
+    pub method: String,
+    pub url: String,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct HttpResponse {
+    pub status_code: u16,
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+/// Mark a response as covered by the shared certification expression
+fn certified(mut response: HttpResponse) -> HttpResponse {
+    response.headers.push(certification::expression_header());
+    response
+}
+
+/// Mark a response as deliberately served without certification
+fn uncertified(mut response: HttpResponse) -> HttpResponse {
+    response
+        .headers
+        .push(certification::skip_expression_header());
+    response
+}
+
+fn json_response(status_code: u16, body: &Json) -> HttpResponse {
+    HttpResponse {
+        status_code,
+        headers: vec![
+            ("Content-Type".to_string(), "application/json".to_string()),
+            ("Access-Control-Allow-Origin".to_string(), "*".to_string()),
+        ],
+        body: body.to_string().into_bytes(),
+    }
+}
+
+fn not_found(message: String) -> HttpResponse {
+    json_response(404, &json!({ "error": message }))
+}
+
+/// ERC-721 metadata JSON for an ICP token, with its id
+fn token_json(token: &Token) -> Json {
+    let mut document = erc721::to_erc721_json(&token.metadata);
+    if let Json::Object(fields) = &mut document {
+        fields.insert("token_id".to_string(), json!(token.token_id.0.to_string()));
+    }
+    document
+}
+
+fn collection_json() -> Json {
+    let config = collection::config();
+    json!({
+        "name": config.name,
+        "symbol": config.symbol,
+        "description": config.description.unwrap_or_default(),
+        "image": config.logo.unwrap_or_default(),
+        "total_supply": erc721::nat_json(&collection::total_supply()),
+        "supply_cap": config.supply_cap.as_ref().map(erc721::nat_json),
+    })
+}
+
+fn token_response(id: &str) -> HttpResponse {
+    let token_id = match id.parse::<Nat>() {
+        // Only the canonical spelling is certified, so "007" or "1_000" would go out unverified
+        Ok(token_id) if token_id.0.to_string() != id => {
+            return not_found(format!(
+                "Token ids are plain decimals, try /token/{}",
+                token_id.0
+            ))
+        }
+        Ok(token_id) => token_id,
+        Err(_) => {
+            return json_response(
+                400,
+                &json!({ "error": format!("Invalid token id '{}'", id) }),
+            )
+        }
+    };
+    match TOKENS.with(|tokens| tokens.borrow().get(&token_id.to_string()).cloned()) {
+        Some(token) => certified(json_response(200, &token_json(&token))),
+        None => not_found(format!("Token {} does not exist", id)),
+    }
+}
+
+fn overflow_response(id: &str) -> HttpResponse {
+    match metadata_policy::get_overflow_metadata(id.to_string()) {
+        Some(metadata) => certified(json_response(
+            200,
+            &json!({
+                "name": metadata.name,
+                "description": metadata.description,
+                "image": metadata.image,
+            }),
+        )),
+        None => not_found(format!("No overflow metadata '{}'", id)),
+    }
+}
+
+/// Stored bytes are immutable under their hash, so caches may keep them forever
+fn asset_response(asset: &Asset) -> HttpResponse {
+    certified(HttpResponse {
+        status_code: 200,
+        headers: vec![
+            ("Content-Type".to_string(), asset.content_type.clone()),
+            (
+                "Cache-Control".to_string(),
+                "public, max-age=31536000, immutable".to_string(),
+            ),
+            ("Access-Control-Allow-Origin".to_string(), "*".to_string()),
+        ],
+        body: asset.content.clone(),
+    })
+}
+
+fn asset_route(hash: &str) -> HttpResponse {
+    // Assets are certified under their lowercase hash only
+    if hash != hash.to_ascii_lowercase() {
+        return not_found(format!("No asset {}", hash));
+    }
+    match assets::asset(hash) {
+        Some(asset) => asset_response(&asset),
+        None => not_found(format!("No asset {}", hash)),
+    }
+}
+
+fn route(path: &str) -> HttpResponse {
+    if path == "/collection" {
+        return certified(json_response(200, &collection_json()));
+    }
+    if let Some(id) = path.strip_prefix("/token/") {
+        return token_response(id);
+    }
+    // Full metadata moved out of oversize EVM releases (OverflowMode::OffChainUri)
+    if let Some(id) = path.strip_prefix("/metadata/") {
+        return overflow_response(id);
+    }
+    if let Some(hash) = path.strip_prefix("/asset/") {
+        return asset_route(hash);
+    }
+    not_found(format!("No route for {}", path))
+}
+
+/// Log buffer page for controllers; changes with every entry, so it is registered to skip
+/// certification. Takes the LogFilter fields as query parameters, e.g. /logs?min_level=warn&component=indexer
+fn logs_response(query: &str) -> HttpResponse {
+    if let Err(e) = ensure_admin() {
+        return uncertified(json_response(403, &json!({ "error": e })));
+    }
+    let mut filter = LogFilter::default();
+    for (key, value) in query.split('&').filter_map(|pair| pair.split_once('=')) {
+        let invalid = match key {
+            "min_level" => LogLevel::parse(value)
+                .map(|l| filter.min_level = Some(l))
+                .is_none(),
+            "component" => LogComponent::parse(value)
+                .map(|c| filter.component = Some(c))
+                .is_none(),
+            "since" => value.parse().map(|s| filter.since = Some(s)).is_err(),
+            "start_id" => value.parse().map(|s| filter.start_id = Some(s)).is_err(),
+            "limit" => value.parse().map(|l| filter.limit = Some(l)).is_err(),
+            _ => false,
+        };
+        if invalid {
+            return json_response(
+                400,
+                &json!({ "error": format!("Invalid value '{}' for {}", value, key) }),
+            );
+        }
+    }
+    uncertified(json_response(200, &json!(logs::page(&filter))))
+}
+
+/// Re-certify what `path` now serves; only successful responses are certified, so a
+/// removed token or asset drops out of the tree. Must run in an update call.
+pub fn certify_path(path: &str) {
+    let response = route(path);
+    if response.status_code == 200 {
+        certification::certify_response(
+            path,
+            response.status_code,
+            &response.headers,
+            &response.body,
+        );
+    } else {
+        certification::remove_response(path);
+    }
+}
+
+/// Call whenever a token is minted, burned or has its metadata changed; the collection
+/// supply moves with it
+pub fn certify_token(token_id: &Nat) {
+    certify_path(&format!("/token/{}", token_id.0));
+    certify_path("/collection");
+}
+
+#[query]
+pub fn http_request(request: HttpRequest) -> HttpResponse {
+    if request.method != "GET" && request.method != "HEAD" {
+        return json_response(405, &json!({ "error": "Only GET is supported" }));
+    }
+    let (path, query) = request
+        .url
+        .split_once('?')
+        .unwrap_or((request.url.as_str(), ""));
+    let mut response = if path == "/logs" {
+        logs_response(query)
+    } else {
+        route(path)
+    };
+    // The certificate covers the GET body, so an empty HEAD response goes out uncertified
+    if request.method == "HEAD" {
+        response.body.clear();
+        response
+            .headers
+            .retain(|(name, _)| !name.eq_ignore_ascii_case("IC-CertificateExpression"));
+        return response;
+    }
+    if let Some(header) = certification::certificate_header(path) {
+        response.headers.push(header);
+    }
+    response
+}
@@ -1,7 +1,29 @@
+use crate::assets::{AssetInfo, CommitAssetArgs};
+use crate::bridge_control::{BridgeStatus, CircuitBreakerConfig, PauseScope};
+use crate::collection::CollectionConfig;
+use crate::consent::{ConsentInfo, ConsentMessageRequest, Icrc21Error};
+use crate::deposit::{DepositAccount, DepositCredit};
+use crate::ecdsa_keys::{DerivedKey, EcdsaKeyName};
+use crate::evm_link::EvmLinkChallenge;
 use crate::evm_nft_indexer::{ChainService, CHAIN_SERVICE};
+use crate::fees::{BridgeIntent, BridgeToEvmArgs, FeeConfig, FeeQuote, PendingBridgeBurn};
+use crate::http::{HttpRequest, HttpResponse};
+use crate::logs::{LogFilter, LogPage};
+use crate::metadata_policy::{MetadataPolicy, NftMetadata};
+use crate::rate_limit::{DeferredRelease, RateLimitConfig, ReleaseOutcome};
+use crate::relayer_health::{RelayerHealth, RelayerHealthConfig};
+use crate::relayer_rotation::RelayerGeneration;
+use crate::siwe::{LoginDetails, SignedDelegation, SiweConfig};
+use crate::standards::SupportedStandard;
+use crate::token_origin::EvmOrigin;
+use crate::token_uri::TokenUriConfig;
+use crate::trusted_origins::TrustedOriginsResponse;
 use candid::{CandidType, Deserialize, Nat, Principal};
+use ic_cdk::api::management_canister::http_request::{
+    HttpResponse as OutcallResponse, TransformArgs,
+};
 use ic_cdk::api::{caller, id, time};
-use ic_cdk::{export_candid, init, query, update};
+use ic_cdk::{export_candid, init, post_upgrade, query, update};
 use serde::Serialize;
 use std::cell::RefCell;
 use std::collections::{BTreeMap, HashMap};
@@ -9,24 +31,92 @@ use std::time::Duration;
 
 mod release_nft;
 
+mod assets;
+mod bridge_control;
+mod certification;
+mod collection;
+mod consent;
+mod deposit;
+mod ecdsa_keys;
+mod erc721;
+mod evm_link;
 mod evm_nft_indexer;
 mod evm_rpc_bindings;
+mod fees;
+mod http;
+mod logs;
+mod memory;
+mod metadata_policy;
+mod rate_limit;
+mod relayer_health;
+mod relayer_rotation;
+mod simulation;
+mod siwe;
+mod standards;
+mod token_origin;
+mod token_uri;
+mod trusted_origins;
+
+#[init]
+fn init(config: Option<CollectionConfig>) {
+    if let Some(config) = config {
+        if let Err(e) = collection::set_config(config) {
+            ic_cdk::trap(&format!("Invalid collection config: {}", e));
+        }
+    }
+    http::certify_path("/collection");
+    certification::skip_certification("/logs");
+}
 
-#[ic_cdk::update]
-pub async fn evm_monitor() {
-    ic_cdk::println!("LOOOGGGGGGGING - Initializing canister");
+// Stable state, including the collection config, outlives an upgrade but the certified
+// data and timers do not; an upgrade argument replaces the stored config
+#[post_upgrade]
+fn post_upgrade(config: Option<CollectionConfig>) {
+    if let Some(config) = config {
+        if let Err(e) = collection::set_config(config) {
+            ic_cdk::trap(&format!("Invalid collection config: {}", e));
+        }
+    }
+    http::certify_path("/collection");
+    certification::skip_certification("/logs");
+    assets::certify_all();
+    metadata_policy::certify_all();
+    init_chain_service();
+    rate_limit::resume_deferred_releases();
+    relayer_health::resume_balance_monitor();
+}
 
-    // Initialize the service
+/// Admin endpoints are restricted to the canister's controllers
+pub(crate) fn ensure_admin() -> Result<(), String> {
+    if ic_cdk::api::is_controller(&caller()) {
+        Ok(())
+    } else {
+        Err("Unauthorized: caller is not a controller".to_string())
+    }
+}
+
+// The chain service is heap state, so post_upgrade recreates it for the resumed timers
+fn init_chain_service() {
     CHAIN_SERVICE.with(|cs| {
         *cs.borrow_mut() = Some(ChainService::new("7hfb6-caaaa-aaaar-qadga-cai".to_string()));
     });
-    ic_cdk::println!(
+}
+
+#[ic_cdk::update]
+pub async fn evm_monitor() {
+    log!(Info, Indexer, "Initializing the EVM chain service");
+
+    init_chain_service();
+    log!(
+        Debug,
+        Indexer,
         "CHAIN_SERVICE: {:?}",
         CHAIN_SERVICE.with(|cs| cs.borrow().clone())
     );
     // Start automatic monitoring
+    relayer_health::ensure_balance_monitor();
 
-    ic_cdk::println!("Canister initialization complete");
+    log!(Info, Indexer, "EVM chain service initialized");
 }
 
 #[update]
@@ -36,7 +126,7 @@ pub async fn monitor_evm_nft() {
         if let Some(service) = maybe_service.borrow().as_ref() {
             service.start_periodic_fetch();
         } else {
-            ic_cdk::println!("ChainService is not initialized");
+            log!(Error, Indexer, "ChainService is not initialized");
         }
     });
 }
@@ -44,12 +134,12 @@ pub async fn monitor_evm_nft() {
 #[ic_cdk::update]
 pub async fn monitor_evm_nft_reverse() {
     evm_monitor().await;
-    ic_cdk::println!("monitor_evm_nft_reverse");
+    log!(Debug, Indexer, "monitor_evm_nft_reverse");
     CHAIN_SERVICE.with(|maybe_service| {
         if let Some(service) = maybe_service.borrow().as_ref() {
             service.start_periodic_fetch_reverse();
         } else {
-            ic_cdk::println!("ChainService is not initialized");
+            log!(Error, Indexer, "ChainService is not initialized");
         }
     });
 }
@@ -89,13 +179,15 @@ pub struct Token {
     pub created_at: u64,
 }
 
-// Legacy MetadataValue for backward compatibility
+// Legacy MetadataValue for backward compatibility; Array and Map carry icrc97:metadata
 #[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
 pub enum MetadataValue {
     Text(String),
     Blob(Vec<u8>),
     Nat(Nat),
     Int(i128),
+    Array(Vec<MetadataValue>),
+    Map(Vec<(String, MetadataValue)>),
 }
 
 impl From<MetadataValue> for Value {
@@ -105,6 +197,35 @@ impl From<MetadataValue> for Value {
             MetadataValue::Blob(b) => Value::Blob(b),
             MetadataValue::Nat(n) => Value::Nat(n),
             MetadataValue::Int(i) => Value::Int(i),
+            MetadataValue::Array(values) => {
+                Value::Array(values.into_iter().map(Value::from).collect())
+            }
+            MetadataValue::Map(entries) => Value::Map(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, value.into()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl From<Value> for MetadataValue {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::Text(t) => MetadataValue::Text(t),
+            Value::Blob(b) => MetadataValue::Blob(b),
+            Value::Nat(n) => MetadataValue::Nat(n),
+            Value::Int(i) => MetadataValue::Int(i),
+            Value::Array(values) => {
+                MetadataValue::Array(values.into_iter().map(MetadataValue::from).collect())
+            }
+            Value::Map(entries) => MetadataValue::Map(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, value.into()))
+                    .collect(),
+            ),
         }
     }
 }
@@ -189,16 +310,50 @@ thread_local! {
 
     static TRANSACTION_COUNTER: RefCell<u64> = RefCell::new(0);
 
+    // (minter, memo, created_at_time) -> index of the mint transaction, within the dedup window
+    static RECENT_MINTS: RefCell<HashMap<(Principal, Option<Vec<u8>>, u64), Nat>> = RefCell::new(HashMap::new());
+
+}
+
+/// Principals allowed to mint: controllers, the configured minting account, and the
+/// canister itself for bridged mints
+fn is_minter(principal: &Principal) -> bool {
+    *principal == id()
+        || ic_cdk::api::is_controller(principal)
+        || collection::is_minting_account(principal)
 }
 
 #[update]
-async fn icrc7_mint(args: Vec<MintArgs>) -> Vec<Result<Nat, String>> {
+async fn icrc7_mint(args: Vec<MintArgs>) -> Vec<Result<Nat, MintError>> {
     let current_time = time();
+    let minter = caller();
+    if !is_minter(&minter) {
+        return args.iter().map(|_| Err(MintError::Unauthorized)).collect();
+    }
+    let max_batch = collection::max_update_batch_size();
+    if args.len() > max_batch {
+        return vec![Err(MintError::GenericBatchError {
+            error_code: Nat::from(0u64),
+            message: format!(
+                "Batch of {} exceeds max_update_batch_size {}",
+                args.len(),
+                max_batch
+            ),
+        })];
+    }
+
+    // Forget dedup entries that have left the transaction window
+    let window = collection::tx_window_nanos().saturating_add(collection::permitted_drift_nanos());
+    RECENT_MINTS.with(|recent| {
+        recent.borrow_mut().retain(|(_, _, created_at_time), _| {
+            created_at_time.saturating_add(window) >= current_time
+        })
+    });
 
-    ic_cdk::println!("Minting NFTs {:?}", args);
+    log!(Debug, Ledger, "Minting NFTs {:?}", args);
     let mut results = Vec::new();
     for arg in args {
-        results.push(process_mint(current_time, arg).await);
+        results.push(process_mint(current_time, minter, arg).await);
     }
     results
 }
@@ -234,10 +389,89 @@ pub struct MintArgs {
     pub created_at_time: Option<u64>,
 }
 
-async fn process_mint(current_time: u64, args: MintArgs) -> Result<Nat, String> {
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub enum MintError {
+    TokenIdExists,
+    Unauthorized,
+    SupplyCapExceeded,
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    Duplicate { duplicate_of: Nat },
+    GenericError { error_code: Nat, message: String },
+    GenericBatchError { error_code: Nat, message: String },
+}
+
+/// ICRC-7 time checks for a caller-supplied created_at_time; saturating, so values near
+/// u64::MAX are rejected as from the future instead of overflowing
+fn check_created_at_time(
+    created_at_time: u64,
+    current_time: u64,
+    tx_window: u64,
+    permitted_drift: u64,
+) -> Result<(), MintError> {
+    if created_at_time > current_time.saturating_add(permitted_drift) {
+        return Err(MintError::CreatedInFuture {
+            ledger_time: current_time,
+        });
+    }
+    if created_at_time
+        .saturating_add(tx_window)
+        .saturating_add(permitted_drift)
+        < current_time
+    {
+        return Err(MintError::TooOld);
+    }
+    Ok(())
+}
+
+async fn process_mint(
+    current_time: u64,
+    minter: Principal,
+    args: MintArgs,
+) -> Result<Nat, MintError> {
     let token_id = args.token_id;
-    let metadata: Vec<(String, Value)> = args
-        .metadata
+    collection::check_memo(&args.memo).map_err(|message| MintError::GenericError {
+        error_code: Nat::from(0u64),
+        message,
+    })?;
+
+    // ICRC-7 deduplication only applies to calls that set created_at_time
+    let dedup_key = match args.created_at_time {
+        Some(created_at_time) => {
+            check_created_at_time(
+                created_at_time,
+                current_time,
+                collection::tx_window_nanos(),
+                collection::permitted_drift_nanos(),
+            )?;
+            let key = (minter, args.memo.clone(), created_at_time);
+            if let Some(duplicate_of) =
+                RECENT_MINTS.with(|recent| recent.borrow().get(&key).cloned())
+            {
+                return Err(MintError::Duplicate { duplicate_of });
+            }
+            Some(key)
+        }
+        None => None,
+    };
+
+    if TOKENS.with(|tokens| tokens.borrow().contains_key(&token_id.to_string())) {
+        return Err(MintError::TokenIdExists);
+    }
+    if let Some(cap) = collection::supply_cap() {
+        if collection::total_supply() >= cap {
+            return Err(MintError::SupplyCapExceeded);
+        }
+    }
+
+    let mut metadata = args.metadata;
+    metadata_policy::normalize_token_metadata(&mut metadata).map_err(|message| {
+        MintError::GenericError {
+            error_code: Nat::from(0u64),
+            message,
+        }
+    })?;
+    let metadata: Vec<(String, Value)> = metadata
         .into_iter()
         .map(|(key, value)| (key, Value::from(value)))
         .collect();
@@ -253,19 +487,209 @@ async fn process_mint(current_time: u64, args: MintArgs) -> Result<Nat, String>
     TOKENS.with(|tokens| {
         tokens.borrow_mut().insert(token_id.to_string(), token);
     });
+    http::certify_token(&token_id);
 
     // Record transaction
-    record_transaction(
+    let transaction_id = record_transaction(
         TransactionType::Mint {
             to: args.to,
             token_id: token_id.clone(),
         },
         args.memo,
     );
+    if let Some(key) = dedup_key {
+        RECENT_MINTS.with(|recent| recent.borrow_mut().insert(key, transaction_id));
+    }
 
     Ok(token_id)
 }
 
+#[derive(CandidType, Deserialize, Clone, Debug)]
+pub struct BurnArg {
+    // Subaccount of the token owner the token is burned from
+    pub from_subaccount: Option<Vec<u8>>,
+    // Subaccount of an approved spender burning on the owner's behalf
+    pub spender_subaccount: Option<Vec<u8>>,
+    pub token_id: Nat,
+    pub memo: Option<Vec<u8>>,
+    // Release the token on an EVM chain once the bridge fee is paid; the Burn block
+    // then carries the bridge memo instead of `memo`
+    pub bridge_intent: Option<BridgeIntent>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub enum BurnError {
+    NonExistingTokenId,
+    Unauthorized,
+    GenericError { error_code: Nat, message: String },
+    GenericBatchError { error_code: Nat, message: String },
+}
+
+/// Owners, and spenders holding an unexpired approval, may burn a token; `from_subaccount`
+/// must name the owner's subaccount either way
+fn can_burn(token: &Token, caller: &Principal, arg: &BurnArg) -> bool {
+    if token.owner.subaccount != arg.from_subaccount {
+        return false;
+    }
+    if token.owner.owner == *caller {
+        return true;
+    }
+    TOKEN_APPROVALS.with(|approvals| {
+        approvals
+            .borrow()
+            .get(&token.token_id.to_string())
+            .map_or(false, |approval| {
+                let info = &approval.approval_info;
+                info.spender.owner == *caller
+                    && info.spender.subaccount == arg.spender_subaccount
+                    && info.from_subaccount == token.owner.subaccount
+                    && info
+                        .expires_at
+                        .map_or(true, |expires_at| expires_at > time())
+            })
+    })
+}
+
+fn burn_one(caller: &Principal, arg: BurnArg) -> Result<Nat, BurnError> {
+    let generic = |message: String| BurnError::GenericError {
+        error_code: Nat::from(0u64),
+        message,
+    };
+    collection::check_memo(&arg.memo).map_err(generic)?;
+    if let Some(intent) = &arg.bridge_intent {
+        fees::validate_bridge_intent(intent).map_err(generic)?;
+    }
+
+    let key = arg.token_id.to_string();
+    let token = TOKENS
+        .with(|tokens| tokens.borrow().get(&key).cloned())
+        .ok_or(BurnError::NonExistingTokenId)?;
+    if !can_burn(&token, caller, &arg) {
+        return Err(BurnError::Unauthorized);
+    }
+
+    TOKENS.with(|tokens| tokens.borrow_mut().remove(&key));
+    TOKEN_APPROVALS.with(|approvals| approvals.borrow_mut().remove(&key));
+    token_origin::forget_origin(&arg.token_id);
+    http::certify_token(&arg.token_id);
+
+    let memo = match &arg.bridge_intent {
+        Some(intent) => Some(fees::bridge_intent_memo(intent)),
+        None => arg.memo,
+    };
+    let block_index = record_transaction(
+        TransactionType::Burn {
+            from: token.owner.clone(),
+            token_id: token.token_id.clone(),
+        },
+        memo,
+    );
+    if let Some(intent) = &arg.bridge_intent {
+        fees::queue_bridge_burn(block_index.clone(), &token, intent);
+    }
+    Ok(block_index)
+}
+
+/// Burn tokens; each result is the index of the Burn block
+#[update]
+fn icrc7_burn(args: Vec<BurnArg>) -> Vec<Result<Nat, BurnError>> {
+    let max_batch = collection::max_update_batch_size();
+    if args.len() > max_batch {
+        return vec![Err(BurnError::GenericBatchError {
+            error_code: Nat::from(0u64),
+            message: format!(
+                "Batch of {} exceeds max_update_batch_size {}",
+                args.len(),
+                max_batch
+            ),
+        })];
+    }
+    let caller = caller();
+    args.into_iter().map(|arg| burn_one(&caller, arg)).collect()
+}
+
+fn same_account(a: &Account, b: &Account) -> bool {
+    a.owner == b.owner && a.subaccount == b.subaccount
+}
+
+fn sorted_token_ids(owner: Option<&Account>) -> Vec<Nat> {
+    let mut ids: Vec<Nat> = TOKENS.with(|tokens| {
+        tokens
+            .borrow()
+            .values()
+            .filter(|token| owner.map_or(true, |owner| same_account(&token.owner, owner)))
+            .map(|token| token.token_id.clone())
+            .collect()
+    });
+    ids.sort();
+    ids
+}
+
+fn paginate(ids: Vec<Nat>, prev: Option<Nat>, take: Option<Nat>) -> Vec<Nat> {
+    let take = collection::take(take);
+    ids.into_iter()
+        .filter(|id| prev.as_ref().map_or(true, |prev| id > prev))
+        .take(take)
+        .collect()
+}
+
+#[query]
+fn icrc7_owner_of(token_ids: Vec<Nat>) -> Vec<Option<Account>> {
+    collection::check_query_batch(token_ids.len());
+    TOKENS.with(|tokens| {
+        let tokens = tokens.borrow();
+        token_ids
+            .iter()
+            .map(|id| tokens.get(&id.to_string()).map(|token| token.owner.clone()))
+            .collect()
+    })
+}
+
+#[query]
+fn icrc7_token_metadata(token_ids: Vec<Nat>) -> Vec<Option<Vec<(String, Value)>>> {
+    collection::check_query_batch(token_ids.len());
+    TOKENS.with(|tokens| {
+        let tokens = tokens.borrow();
+        token_ids
+            .iter()
+            .map(|id| {
+                tokens
+                    .get(&id.to_string())
+                    .map(|token| token.metadata.clone())
+            })
+            .collect()
+    })
+}
+
+#[query]
+fn icrc7_balance_of(accounts: Vec<Account>) -> Vec<Nat> {
+    collection::check_query_batch(accounts.len());
+    TOKENS.with(|tokens| {
+        let tokens = tokens.borrow();
+        accounts
+            .iter()
+            .map(|account| {
+                Nat::from(
+                    tokens
+                        .values()
+                        .filter(|token| same_account(&token.owner, account))
+                        .count(),
+                )
+            })
+            .collect()
+    })
+}
+
+#[query]
+fn icrc7_tokens(prev: Option<Nat>, take: Option<Nat>) -> Vec<Nat> {
+    paginate(sorted_token_ids(None), prev, take)
+}
+
+#[query]
+fn icrc7_tokens_of(account: Account, prev: Option<Nat>, take: Option<Nat>) -> Vec<Nat> {
+    paginate(sorted_token_ids(Some(&account)), prev, take)
+}
+
 fn record_transaction(transaction_type: TransactionType, memo: Option<Vec<u8>>) -> Nat {
     TRANSACTION_COUNTER.with(|counter| {
         let mut counter = counter.borrow_mut();
@@ -292,10 +716,43 @@ fn record_transaction(transaction_type: TransactionType, memo: Option<Vec<u8>>)
 // Simple greeting function (keeping your original function)
 #[query]
 fn greet(name: String) -> String {
-    format!(
-        "Hello, {}! Welcome to the Cross NFT Launcher with ICRC-7 and ICRC-37 support!",
-        name
-    )
+    format!("Hello, {}! Welcome to the Cross NFT Launcher!", name)
 }
 
 export_candid!();
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WINDOW: u64 = 86_400_000_000_000;
+    const DRIFT: u64 = 120_000_000_000;
+
+    #[test]
+    fn created_at_time_near_u64_max_is_in_the_future() {
+        let now = 1_700_000_000_000_000_000;
+        assert!(matches!(
+            check_created_at_time(u64::MAX, now, WINDOW, DRIFT),
+            Err(MintError::CreatedInFuture { ledger_time }) if ledger_time == now
+        ));
+        assert!(matches!(
+            check_created_at_time(u64::MAX - 1, u64::MAX, u64::MAX, u64::MAX),
+            Ok(())
+        ));
+    }
+
+    #[test]
+    fn created_at_time_outside_the_window_is_too_old() {
+        let now = 1_700_000_000_000_000_000;
+        assert!(check_created_at_time(now, now, WINDOW, DRIFT).is_ok());
+        assert!(check_created_at_time(now - WINDOW - DRIFT, now, WINDOW, DRIFT).is_ok());
+        assert!(matches!(
+            check_created_at_time(now - WINDOW - DRIFT - 1, now, WINDOW, DRIFT),
+            Err(MintError::TooOld)
+        ));
+        assert!(matches!(
+            check_created_at_time(now + DRIFT + 1, now, WINDOW, DRIFT),
+            Err(MintError::CreatedInFuture { .. })
+        ));
+    }
+}
@@ -0,0 +1,169 @@
+use candid::CandidType;
+use ic_cdk::api::time;
+use ic_cdk::query;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+use crate::ensure_admin;
+
+// Oldest entries are dropped beyond this, so the buffer stays bounded in heap memory
+const MAX_LOG_ENTRIES: usize = 5_000;
+const DEFAULT_PAGE_SIZE: u32 = 100;
+const MAX_PAGE_SIZE: u32 = 1_000;
+
+#[derive(
+    CandidType, Deserialize, Clone, Copy, Debug, Serialize, PartialEq, Eq, PartialOrd, Ord,
+)]
+pub enum LogLevel {
+    Debug,
+    Info,
+    Warn,
+    Error,
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+pub enum LogComponent {
+    Indexer,
+    Release,
+    Ledger,
+    Auth,
+    Metadata,
+}
+
+impl LogLevel {
+    fn as_str(&self) -> &'static str {
+        match self {
+            LogLevel::Debug => "debug",
+            LogLevel::Info => "info",
+            LogLevel::Warn => "warn",
+            LogLevel::Error => "error",
+        }
+    }
+
+    pub fn parse(level: &str) -> Option<Self> {
+        match level.to_ascii_lowercase().as_str() {
+            "debug" => Some(LogLevel::Debug),
+            "info" => Some(LogLevel::Info),
+            "warn" => Some(LogLevel::Warn),
+            "error" => Some(LogLevel::Error),
+            _ => None,
+        }
+    }
+}
+
+impl LogComponent {
+    fn as_str(&self) -> &'static str {
+        match self {
+            LogComponent::Indexer => "indexer",
+            LogComponent::Release => "release",
+            LogComponent::Ledger => "ledger",
+            LogComponent::Auth => "auth",
+            LogComponent::Metadata => "metadata",
+        }
+    }
+
+    pub fn parse(component: &str) -> Option<Self> {
+        match component.to_ascii_lowercase().as_str() {
+            "indexer" => Some(LogComponent::Indexer),
+            "release" => Some(LogComponent::Release),
+            "ledger" => Some(LogComponent::Ledger),
+            "auth" => Some(LogComponent::Auth),
+            "metadata" => Some(LogComponent::Metadata),
+            _ => None,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct LogRecord {
+    pub id: u64,
+    pub timestamp: u64,
+    pub level: LogLevel,
+    pub component: LogComponent,
+    pub message: String,
+}
+
+/// Every field is optional; entries come back oldest first from `start_id`
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize, Default)]
+pub struct LogFilter {
+    pub min_level: Option<LogLevel>,
+    pub component: Option<LogComponent>,
+    pub since: Option<u64>,
+    pub start_id: Option<u64>,
+    pub limit: Option<u32>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct LogPage {
+    pub entries: Vec<LogRecord>,
+    // Pass as start_id to fetch the next page; None once the buffer is exhausted
+    pub next_id: Option<u64>,
+}
+
+thread_local! {
+    static LOGS: RefCell<VecDeque<LogRecord>> = RefCell::new(VecDeque::new());
+    static NEXT_LOG_ID: RefCell<u64> = RefCell::new(0);
+}
+
+/// Record an entry and echo it to the replica log; use through `log!`
+pub fn append(level: LogLevel, component: LogComponent, message: String) {
+    ic_cdk::println!("[{} {}] {}", level.as_str(), component.as_str(), message);
+    let id = NEXT_LOG_ID.with(|next| {
+        let mut next = next.borrow_mut();
+        *next += 1;
+        *next
+    });
+    LOGS.with(|logs| {
+        let mut logs = logs.borrow_mut();
+        if logs.len() == MAX_LOG_ENTRIES {
+            logs.pop_front();
+        }
+        logs.push_back(LogRecord {
+            id,
+            timestamp: time(),
+            level,
+            component,
+            message,
+        });
+    });
+}
+
+/// `log!(Warn, Indexer, "Skipping block {}", n)`
+#[macro_export]
+macro_rules! log {
+    ($level:ident, $component:ident, $($arg:tt)*) => {
+        $crate::logs::append(
+            $crate::logs::LogLevel::$level,
+            $crate::logs::LogComponent::$component,
+            format!($($arg)*),
+        )
+    };
+}
+
+pub fn page(filter: &LogFilter) -> LogPage {
+    let limit = filter
+        .limit
+        .unwrap_or(DEFAULT_PAGE_SIZE)
+        .clamp(1, MAX_PAGE_SIZE) as usize;
+    LOGS.with(|logs| {
+        let mut matching = logs.borrow().iter().filter(|entry| {
+            entry.id >= filter.start_id.unwrap_or(0)
+                && filter.min_level.map_or(true, |level| entry.level >= level)
+                && filter.component.map_or(true, |c| entry.component == c)
+                && filter.since.map_or(true, |since| entry.timestamp >= since)
+        });
+        let entries: Vec<LogRecord> = matching.by_ref().take(limit).cloned().collect();
+        LogPage {
+            entries,
+            next_id: matching.next().map(|entry| entry.id),
+        }
+    })
+}
+
+/// Controllers only: entries name principals, addresses and RPC errors
+#[query]
+pub fn get_logs(filter: LogFilter) -> Result<LogPage, String> {
+    ensure_admin()?;
+    Ok(page(&filter))
+}
@@ -0,0 +1,69 @@
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::DefaultMemoryImpl;
+use std::cell::RefCell;
+
+pub type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+// Each stable structure gets its own virtual memory; never reuse or renumber an id
+pub const ECDSA_KEY_CACHE_MEMORY_ID: MemoryId = MemoryId::new(0);
+pub const RELAYER_GENERATIONS_MEMORY_ID: MemoryId = MemoryId::new(1);
+pub const ASSETS_MEMORY_ID: MemoryId = MemoryId::new(2);
+pub const RATE_LIMIT_CONFIG_MEMORY_ID: MemoryId = MemoryId::new(3);
+pub const RATE_LIMIT_USAGE_MEMORY_ID: MemoryId = MemoryId::new(4);
+pub const DEFERRED_RELEASES_MEMORY_ID: MemoryId = MemoryId::new(5);
+pub const NEXT_QUEUE_ID_MEMORY_ID: MemoryId = MemoryId::new(6);
+pub const BALANCES_MEMORY_ID: MemoryId = MemoryId::new(7);
+pub const DEPOSIT_CREDITS_MEMORY_ID: MemoryId = MemoryId::new(8);
+pub const FEE_CONFIG_MEMORY_ID: MemoryId = MemoryId::new(9);
+pub const COLLECTED_FEES_MEMORY_ID: MemoryId = MemoryId::new(10);
+pub const PENDING_BRIDGE_BURNS_MEMORY_ID: MemoryId = MemoryId::new(11);
+pub const ECDSA_KEY_NAME_MEMORY_ID: MemoryId = MemoryId::new(12);
+pub const COLLECTION_CONFIG_MEMORY_ID: MemoryId = MemoryId::new(13);
+pub const TOKEN_URI_CONFIG_MEMORY_ID: MemoryId = MemoryId::new(14);
+pub const JSON_RPC_MAX_RESPONSE_BYTES_MEMORY_ID: MemoryId = MemoryId::new(15);
+pub const BRIDGE_PAUSES_MEMORY_ID: MemoryId = MemoryId::new(16);
+pub const CIRCUIT_BREAKER_CONFIG_MEMORY_ID: MemoryId = MemoryId::new(17);
+pub const BURN_WINDOWS_MEMORY_ID: MemoryId = MemoryId::new(18);
+pub const RELEASE_FAILURES_MEMORY_ID: MemoryId = MemoryId::new(19);
+pub const BLOCK_NUMBER_MEMORY_ID: MemoryId = MemoryId::new(20);
+pub const PROCESSED_BURN_LOGS_MEMORY_ID: MemoryId = MemoryId::new(21);
+pub const TRUSTED_ORIGINS_MEMORY_ID: MemoryId = MemoryId::new(22);
+pub const PRINCIPAL_BY_ADDRESS_MEMORY_ID: MemoryId = MemoryId::new(23);
+pub const ADDRESS_BY_PRINCIPAL_MEMORY_ID: MemoryId = MemoryId::new(24);
+pub const METADATA_POLICIES_MEMORY_ID: MemoryId = MemoryId::new(25);
+pub const OVERFLOW_METADATA_MEMORY_ID: MemoryId = MemoryId::new(26);
+pub const RELAYER_HEALTH_CONFIG_MEMORY_ID: MemoryId = MemoryId::new(27);
+pub const RELAYER_HEALTH_MEMORY_ID: MemoryId = MemoryId::new(28);
+pub const EVM_ORIGINS_MEMORY_ID: MemoryId = MemoryId::new(29);
+pub const ICP_TOKENS_BY_ORIGIN_MEMORY_ID: MemoryId = MemoryId::new(30);
+
+thread_local! {
+    static MEMORY_MANAGER: RefCell<MemoryManager<DefaultMemoryImpl>> =
+        RefCell::new(MemoryManager::init(DefaultMemoryImpl::default()));
+}
+
+pub fn get_memory(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|m| m.borrow().get(id))
+}
+
+/// Candid-encoded, unbounded Storable for types kept in stable cells and maps
+macro_rules! candid_storable {
+    ($($t:ty),+ $(,)?) => {$(
+        impl ic_stable_structures::Storable for $t {
+            fn to_bytes(&self) -> std::borrow::Cow<[u8]> {
+                std::borrow::Cow::Owned(
+                    candid::Encode!(self).expect(concat!("failed to encode ", stringify!($t))),
+                )
+            }
+
+            fn from_bytes(bytes: std::borrow::Cow<[u8]>) -> Self {
+                candid::Decode!(bytes.as_ref(), Self)
+                    .expect(concat!("failed to decode ", stringify!($t)))
+            }
+
+            const BOUND: ic_stable_structures::storable::Bound =
+                ic_stable_structures::storable::Bound::Unbounded;
+        }
+    )+};
+}
+pub(crate) use candid_storable;
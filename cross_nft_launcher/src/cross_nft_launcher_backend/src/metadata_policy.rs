@@ -0,0 +1,401 @@
+use candid::CandidType;
+use ic_cdk::{query, update};
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+
+use crate::ensure_admin;
+use crate::evm_nft_indexer::{HOLESKY_CHAIN_ID, SEPOLIA_CHAIN_ID};
+use crate::http;
+use crate::log;
+use crate::memory::{
+    candid_storable, get_memory, Memory, METADATA_POLICIES_MEMORY_ID, OVERFLOW_METADATA_MEMORY_ID,
+};
+use crate::MetadataValue;
+
+// Chains running contracts/nft.sol; they get its limits until an admin sets a policy
+const NFT_CONTRACT_CHAINS: [u64; 2] = [SEPOLIA_CHAIN_ID, HOLESKY_CHAIN_ID];
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+pub enum OverflowMode {
+    // Refuse the mint or release
+    Reject,
+    // Cut each field at the last UTF-8 boundary that fits
+    Truncate,
+    // Keep the full metadata in the canister and point to it from the truncated fields
+    OffChainUri,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct MetadataPolicy {
+    pub chain_id: u64,
+    pub max_name_bytes: u32,
+    pub max_description_bytes: u32,
+    pub max_image_bytes: u32,
+    pub mode: OverflowMode,
+    // Prefix for overflow records in OffChainUri mode, e.g. "https://<canister>.icp0.io/metadata/"
+    pub overflow_uri_base: Option<String>,
+}
+
+impl MetadataPolicy {
+    /// Limits enforced by `mint_nft_release` in contracts/nft.sol
+    fn nft_contract(chain_id: u64) -> Self {
+        MetadataPolicy {
+            chain_id,
+            max_name_bytes: 32,
+            max_description_bytes: 200,
+            max_image_bytes: 200,
+            mode: OverflowMode::Truncate,
+            overflow_uri_base: None,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize, PartialEq, Eq)]
+pub struct NftMetadata {
+    pub name: String,
+    pub description: String,
+    pub image: String,
+}
+
+candid_storable!(MetadataPolicy, NftMetadata);
+
+thread_local! {
+    // Policies set by admins, by chain id
+    static POLICIES: RefCell<StableBTreeMap<u64, MetadataPolicy, Memory>> = RefCell::new(
+        StableBTreeMap::init(get_memory(METADATA_POLICIES_MEMORY_ID))
+    );
+
+    // Overflow id -> untruncated metadata for OffChainUri mode; released tokens point here
+    static OVERFLOW_METADATA: RefCell<StableBTreeMap<String, NftMetadata, Memory>> = RefCell::new(
+        StableBTreeMap::init(get_memory(OVERFLOW_METADATA_MEMORY_ID))
+    );
+}
+
+fn policy(chain_id: u64) -> Option<MetadataPolicy> {
+    POLICIES.with(|p| p.borrow().get(&chain_id)).or_else(|| {
+        NFT_CONTRACT_CHAINS
+            .contains(&chain_id)
+            .then(|| MetadataPolicy::nft_contract(chain_id))
+    })
+}
+
+/// Admin-set policies plus the nft.sol defaults of chains without one, by chain id
+fn policies() -> Vec<MetadataPolicy> {
+    let mut policies: Vec<MetadataPolicy> =
+        POLICIES.with(|p| p.borrow().iter().map(|(_, policy)| policy).collect());
+    for chain_id in NFT_CONTRACT_CHAINS {
+        if !policies.iter().any(|policy| policy.chain_id == chain_id) {
+            policies.push(MetadataPolicy::nft_contract(chain_id));
+        }
+    }
+    policies.sort_by_key(|policy| policy.chain_id);
+    policies
+}
+
+/// Longest prefix of `s` that fits in `max_bytes` without splitting a character
+pub fn truncate_utf8(s: &str, max_bytes: usize) -> &str {
+    if s.len() <= max_bytes {
+        return s;
+    }
+    let mut end = max_bytes;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+fn overflow_id(metadata: &NftMetadata) -> String {
+    let mut hasher = Sha256::new();
+    for field in [&metadata.name, &metadata.description, &metadata.image] {
+        hasher.update((field.len() as u64).to_be_bytes());
+        hasher.update(field.as_bytes());
+    }
+    hex::encode(&hasher.finalize()[..16])
+}
+
+/// `metadata` fitted to `policy`, with the overflow id the original must be stored under
+/// when OffChainUri mode had to cut it
+fn fit(
+    policy: &MetadataPolicy,
+    metadata: &NftMetadata,
+) -> Result<(NftMetadata, Option<String>), String> {
+    let max_name = policy.max_name_bytes as usize;
+    let max_description = policy.max_description_bytes as usize;
+    let max_image = policy.max_image_bytes as usize;
+
+    let oversize: Vec<String> = [
+        ("name", metadata.name.len(), max_name),
+        ("description", metadata.description.len(), max_description),
+        ("image", metadata.image.len(), max_image),
+    ]
+    .iter()
+    .filter(|(_, len, max)| len > max)
+    .map(|(field, len, max)| format!("{} is {} bytes (max {})", field, len, max))
+    .collect();
+    if oversize.is_empty() {
+        return Ok((metadata.clone(), None));
+    }
+
+    match policy.mode {
+        OverflowMode::Reject => Err(format!(
+            "Metadata exceeds chain {} limits: {}",
+            policy.chain_id,
+            oversize.join(", ")
+        )),
+        OverflowMode::Truncate => Ok((
+            NftMetadata {
+                name: truncate_utf8(&metadata.name, max_name).to_string(),
+                description: truncate_utf8(&metadata.description, max_description).to_string(),
+                image: truncate_utf8(&metadata.image, max_image).to_string(),
+            },
+            None,
+        )),
+        OverflowMode::OffChainUri => {
+            let base = policy.overflow_uri_base.as_ref().ok_or_else(|| {
+                format!(
+                    "Chain {} uses OffChainUri but has no overflow_uri_base",
+                    policy.chain_id
+                )
+            })?;
+            let id = overflow_id(metadata);
+            let uri = format!("{}{}", base, id);
+
+            let image = if metadata.image.len() <= max_image {
+                metadata.image.clone()
+            } else if uri.len() <= max_image {
+                uri.clone()
+            } else {
+                return Err(format!(
+                    "Overflow URI {} does not fit the {} byte image limit",
+                    uri, max_image
+                ));
+            };
+            let description = if metadata.description.len() <= max_description {
+                metadata.description.clone()
+            } else if uri.len() < max_description {
+                format!(
+                    "{} {}",
+                    truncate_utf8(&metadata.description, max_description - uri.len() - 1),
+                    uri
+                )
+            } else {
+                truncate_utf8(&metadata.description, max_description).to_string()
+            };
+
+            let fitted = NftMetadata {
+                name: truncate_utf8(&metadata.name, max_name).to_string(),
+                description,
+                image,
+            };
+            Ok((fitted, Some(id)))
+        }
+    }
+}
+
+fn apply_policy(policy: &MetadataPolicy, metadata: NftMetadata) -> Result<NftMetadata, String> {
+    let (fitted, overflow_id) = fit(policy, &metadata)?;
+    if let Some(id) = overflow_id {
+        log!(
+            Info,
+            Metadata,
+            "Moved oversize metadata to overflow record {}",
+            id
+        );
+        let path = format!("/metadata/{}", id);
+        OVERFLOW_METADATA.with(|m| m.borrow_mut().insert(id, metadata));
+        http::certify_path(&path);
+    }
+    Ok(fitted)
+}
+
+/// Fit metadata to the limits of `chain_id`; chains without a policy pass it through
+pub fn apply(chain_id: u64, metadata: NftMetadata) -> Result<NftMetadata, String> {
+    match policy(chain_id) {
+        Some(policy) => apply_policy(&policy, metadata),
+        None => Ok(metadata),
+    }
+}
+
+/// Fit the name/description/image entries of an ICP token to every configured chain,
+/// so the token can later be released to any of them
+pub fn normalize_token_metadata(metadata: &mut [(String, MetadataValue)]) -> Result<(), String> {
+    let text = |key: &str| {
+        metadata
+            .iter()
+            .find_map(|(k, v)| match v {
+                MetadataValue::Text(t) if k == key => Some(t.clone()),
+                _ => None,
+            })
+            .unwrap_or_default()
+    };
+    let mut normalized = NftMetadata {
+        name: text("name"),
+        description: text("description"),
+        image: text("image"),
+    };
+
+    for policy in policies().iter() {
+        normalized = apply_policy(policy, normalized)?;
+    }
+
+    for (key, value) in metadata.iter_mut() {
+        if let MetadataValue::Text(text) = value {
+            match key.as_str() {
+                "name" => *text = normalized.name.clone(),
+                "description" => *text = normalized.description.clone(),
+                "image" => *text = normalized.image.clone(),
+                _ => {}
+            }
+        }
+    }
+    Ok(())
+}
+
+#[update]
+pub fn set_metadata_policy(policy: MetadataPolicy) -> Result<(), String> {
+    ensure_admin()?;
+    if policy.mode == OverflowMode::OffChainUri && policy.overflow_uri_base.is_none() {
+        return Err("OffChainUri mode requires overflow_uri_base".to_string());
+    }
+    POLICIES.with(|p| p.borrow_mut().insert(policy.chain_id, policy));
+    Ok(())
+}
+
+#[query]
+pub fn get_metadata_policies() -> Vec<MetadataPolicy> {
+    policies()
+}
+
+#[query]
+pub fn get_overflow_metadata(id: String) -> Option<NftMetadata> {
+    OVERFLOW_METADATA.with(|m| m.borrow().get(&id))
+}
+
+/// Re-certify every overflow record; certified data does not survive an upgrade
+pub fn certify_all() {
+    let ids: Vec<String> =
+        OVERFLOW_METADATA.with(|m| m.borrow().iter().map(|(id, _)| id).collect());
+    for id in ids {
+        http::certify_path(&format!("/metadata/{}", id));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(name: &str, description: &str, image: &str) -> NftMetadata {
+        NftMetadata {
+            name: name.to_string(),
+            description: description.to_string(),
+            image: image.to_string(),
+        }
+    }
+
+    fn policy_with(mode: OverflowMode, overflow_uri_base: Option<&str>) -> MetadataPolicy {
+        MetadataPolicy {
+            chain_id: SEPOLIA_CHAIN_ID,
+            max_name_bytes: 8,
+            max_description_bytes: 60,
+            max_image_bytes: 50,
+            mode,
+            overflow_uri_base: overflow_uri_base.map(str::to_string),
+        }
+    }
+
+    #[test]
+    fn truncate_utf8_never_splits_a_character() {
+        assert_eq!(truncate_utf8("relayer", 10), "relayer");
+        assert_eq!(truncate_utf8("relayer", 7), "relayer");
+        assert_eq!(truncate_utf8("relayer", 3), "rel");
+        assert_eq!(truncate_utf8("relayer", 0), "");
+        // 'é' is 2 bytes, '🦀' is 4
+        assert_eq!(truncate_utf8("héllo", 2), "h");
+        assert_eq!(truncate_utf8("héllo", 3), "hé");
+        for max in 1..5 {
+            assert_eq!(truncate_utf8("a🦀b", max), "a");
+        }
+        assert_eq!(truncate_utf8("a🦀b", 5), "a🦀");
+        assert_eq!(truncate_utf8("🦀🦀", 7), "🦀");
+    }
+
+    #[test]
+    fn metadata_within_limits_passes_unchanged() {
+        let original = metadata("Crab", "A crab", "ipfs://crab");
+        for mode in [
+            OverflowMode::Reject,
+            OverflowMode::Truncate,
+            OverflowMode::OffChainUri,
+        ] {
+            let (fitted, overflow) =
+                fit(&policy_with(mode, Some("https://x/")), &original).unwrap();
+            assert_eq!(fitted, original);
+            assert_eq!(overflow, None);
+        }
+    }
+
+    #[test]
+    fn reject_names_every_oversize_field() {
+        let err = fit(
+            &policy_with(OverflowMode::Reject, None),
+            &metadata("Crab Crab Crab", "A crab", &"i".repeat(51)),
+        )
+        .unwrap_err();
+        assert!(err.contains("name is 14 bytes (max 8)"), "{}", err);
+        assert!(err.contains("image is 51 bytes (max 50)"), "{}", err);
+        assert!(!err.contains("description"), "{}", err);
+    }
+
+    #[test]
+    fn truncate_cuts_multi_byte_fields_at_a_boundary() {
+        // Both limits fall inside a character: byte 8 of the name is in the second crab
+        // and byte 60 of the description is in the 30th 'é'
+        let (fitted, overflow) = fit(
+            &policy_with(OverflowMode::Truncate, None),
+            &metadata("a🦀🦀🦀", &format!("a{}", "é".repeat(40)), "ipfs://crab"),
+        )
+        .unwrap();
+        assert_eq!(fitted.name, "a🦀");
+        assert_eq!(fitted.description, format!("a{}", "é".repeat(29)));
+        assert_eq!(fitted.image, "ipfs://crab");
+        assert_eq!(overflow, None);
+    }
+
+    #[test]
+    fn off_chain_uri_points_the_cut_fields_at_the_overflow_record() {
+        let original = metadata("Crab", &format!("ab{}", "é".repeat(40)), &"i".repeat(80));
+        let (fitted, overflow) = fit(
+            &policy_with(OverflowMode::OffChainUri, Some("https://c/m/")),
+            &original,
+        )
+        .unwrap();
+        let id = overflow.expect("an overflow record");
+        assert_eq!(id, overflow_id(&original));
+        let uri = format!("https://c/m/{}", id);
+        assert_eq!(fitted.name, "Crab");
+        assert_eq!(fitted.image, uri);
+        // 60 - 44 byte URI - 1 space leaves 15 bytes, which ends inside the seventh 'é'
+        assert_eq!(fitted.description, format!("ab{} {}", "é".repeat(6), uri));
+        assert!(fitted.description.len() <= 60);
+    }
+
+    #[test]
+    fn off_chain_uri_needs_a_base_that_fits() {
+        let oversize = metadata("Crab", "A crab", &"i".repeat(80));
+        assert!(fit(&policy_with(OverflowMode::OffChainUri, None), &oversize).is_err());
+
+        let long_base = format!("https://{}/", "c".repeat(40));
+        let err = fit(
+            &policy_with(OverflowMode::OffChainUri, Some(&long_base)),
+            &oversize,
+        )
+        .unwrap_err();
+        assert!(
+            err.contains("does not fit the 50 byte image limit"),
+            "{}",
+            err
+        );
+    }
+}
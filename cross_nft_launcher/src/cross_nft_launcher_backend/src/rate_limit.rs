@@ -0,0 +1,499 @@
+use candid::CandidType;
+use ic_cdk::api::time;
+use ic_cdk::{query, update};
+use ic_cdk_timers::{clear_timer, set_timer_interval, TimerId};
+use ic_stable_structures::{StableBTreeMap, StableCell};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::time::Duration;
+
+use crate::bridge_control::{self, BridgeDirection, BridgeOperation};
+use crate::ensure_admin;
+use crate::evm_nft_indexer::{ChainService, CHAIN_SERVICE};
+use crate::log;
+use crate::memory::{
+    candid_storable, get_memory, Memory, DEFERRED_RELEASES_MEMORY_ID, NEXT_QUEUE_ID_MEMORY_ID,
+    RATE_LIMIT_CONFIG_MEMORY_ID, RATE_LIMIT_USAGE_MEMORY_ID,
+};
+use crate::relayer_health;
+use crate::release_nft::{ReleaseRequest, RELEASE_GAS_LIMIT};
+
+const HOUR_NANOS: u64 = 3_600 * 1_000_000_000;
+const DAY_NANOS: u64 = 24 * HOUR_NANOS;
+const QUEUE_POLL_INTERVAL_SECS: u64 = 60;
+// A failed deferred release is retried after 1, 2, 4, ... minutes, at most hourly
+const RETRY_BACKOFF_BASE_NANOS: u64 = 60 * 1_000_000_000;
+const RETRY_BACKOFF_MAX_NANOS: u64 = HOUR_NANOS;
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize, Default)]
+pub struct Limit {
+    pub max_releases_per_hour: Option<u32>,
+    // Budget of gas limits, not gas used: quota is reserved before a release is sent, so
+    // every release is charged its full RELEASE_GAS_LIMIT
+    pub max_gas_per_day: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct RateLimitConfig {
+    pub per_source_address: Limit,
+    pub per_destination_address: Limit,
+    pub per_chain: Limit,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            per_source_address: Limit {
+                max_releases_per_hour: Some(5),
+                max_gas_per_day: Some(20 * RELEASE_GAS_LIMIT),
+            },
+            per_destination_address: Limit {
+                max_releases_per_hour: Some(5),
+                max_gas_per_day: Some(20 * RELEASE_GAS_LIMIT),
+            },
+            per_chain: Limit {
+                max_releases_per_hour: Some(100),
+                max_gas_per_day: Some(1_000 * RELEASE_GAS_LIMIT),
+            },
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub enum DeferReason {
+    RateLimited,
+    InsufficientRelayerFunds,
+    // The release was sent and failed; retried with backoff
+    ReleaseFailed,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct DeferredRelease {
+    pub id: u64,
+    pub request: ReleaseRequest,
+    pub reason: DeferReason,
+    pub enqueued_at: u64,
+    pub eligible_at: u64,
+    // Failed release attempts so far; each one pushes eligible_at back further
+    pub attempts: u32,
+    pub last_error: Option<String>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub enum ReleaseOutcome {
+    Released {
+        tx_hash: String,
+    },
+    Deferred {
+        queue_id: u64,
+        eligible_at: u64,
+        reason: DeferReason,
+    },
+}
+
+/// Answer of the rate limiter for one release
+enum Reservation {
+    // Quota taken at this timestamp; hand it back with `cancel_reservation` if the release fails
+    Reserved(u64),
+    // Over a limit until this time
+    Deferred(u64),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum UsageKey {
+    Source(String),
+    Destination(String),
+    Chain(u64),
+}
+
+// (timestamp ns, gas) of every release reserved in the last day, oldest first
+#[derive(CandidType, Deserialize, Clone, Debug, Default)]
+struct UsageWindow(Vec<(u64, u64)>);
+
+candid_storable!(RateLimitConfig, DeferredRelease, UsageKey, UsageWindow);
+
+thread_local! {
+    static RATE_LIMIT_CONFIG: RefCell<StableCell<RateLimitConfig, Memory>> = RefCell::new(
+        StableCell::init(get_memory(RATE_LIMIT_CONFIG_MEMORY_ID), RateLimitConfig::default())
+            .expect("failed to init rate limit config")
+    );
+
+    static USAGE: RefCell<StableBTreeMap<UsageKey, UsageWindow, Memory>> = RefCell::new(
+        StableBTreeMap::init(get_memory(RATE_LIMIT_USAGE_MEMORY_ID))
+    );
+
+    static DEFERRED_RELEASES: RefCell<StableBTreeMap<u64, DeferredRelease, Memory>> = RefCell::new(
+        StableBTreeMap::init(get_memory(DEFERRED_RELEASES_MEMORY_ID))
+    );
+
+    static NEXT_QUEUE_ID: RefCell<StableCell<u64, Memory>> = RefCell::new(
+        StableCell::init(get_memory(NEXT_QUEUE_ID_MEMORY_ID), 0)
+            .expect("failed to init deferred release ids")
+    );
+
+    static QUEUE_TIMER: RefCell<Option<TimerId>> = RefCell::new(None);
+}
+
+fn usage_keys(request: &ReleaseRequest, config: &RateLimitConfig) -> Vec<(UsageKey, Limit)> {
+    vec![
+        (
+            UsageKey::Source(request.source_address.to_lowercase()),
+            config.per_source_address.clone(),
+        ),
+        (
+            UsageKey::Destination(request.owner.to_lowercase()),
+            config.per_destination_address.clone(),
+        ),
+        (UsageKey::Chain(request.chain_id), config.per_chain.clone()),
+    ]
+}
+
+/// Earliest time at which one more release of `gas` fits under `limit`, given past `usage`;
+/// an error when the limit can never admit it
+fn eligible_at(usage: &[(u64, u64)], limit: &Limit, gas: u64, now: u64) -> Result<u64, String> {
+    let mut eligible_at = now;
+
+    if let Some(max) = limit.max_releases_per_hour {
+        if max == 0 {
+            return Err("max_releases_per_hour is 0".to_string());
+        }
+        let recent: Vec<u64> = usage
+            .iter()
+            .map(|(ts, _)| *ts)
+            .filter(|ts| *ts + HOUR_NANOS > now)
+            .collect();
+        if recent.len() >= max as usize {
+            // The oldest entries have to age out until only max - 1 remain
+            let expiring = recent[recent.len() - max as usize];
+            eligible_at = eligible_at.max(expiring + HOUR_NANOS);
+        }
+    }
+
+    if let Some(max_gas) = limit.max_gas_per_day {
+        if gas > max_gas {
+            return Err(format!(
+                "a release needs {} gas but max_gas_per_day is {}",
+                gas, max_gas
+            ));
+        }
+        let mut spent: u64 = usage
+            .iter()
+            .filter(|(ts, _)| *ts + DAY_NANOS > now)
+            .map(|(_, g)| *g)
+            .sum();
+        for (ts, g) in usage.iter().filter(|(ts, _)| *ts + DAY_NANOS > now) {
+            if spent + gas <= max_gas {
+                break;
+            }
+            spent -= g;
+            eligible_at = eligible_at.max(ts + DAY_NANOS);
+        }
+    }
+
+    Ok(eligible_at)
+}
+
+/// Reserve quota for `request`, or find the time at which it becomes eligible
+fn try_reserve(request: &ReleaseRequest) -> Result<Reservation, String> {
+    let config = rate_limit_config();
+    let keys = usage_keys(request, &config);
+    let now = time();
+    // Charged up front at the gas limit; see Limit::max_gas_per_day
+    let gas = RELEASE_GAS_LIMIT;
+
+    USAGE.with(|usage| {
+        let mut usage = usage.borrow_mut();
+
+        let mut windows = Vec::with_capacity(keys.len());
+        let mut next_eligible = now;
+        for (key, limit) in keys {
+            let mut window = usage.get(&key).unwrap_or_default();
+            window.0.retain(|(ts, _)| ts + DAY_NANOS > now);
+            let eligible = eligible_at(&window.0, &limit, gas, now)
+                .map_err(|e| format!("Release can never pass the rate limits: {}", e))?;
+            next_eligible = next_eligible.max(eligible);
+            windows.push((key, window));
+        }
+        if next_eligible > now {
+            return Ok(Reservation::Deferred(next_eligible));
+        }
+
+        for (key, mut window) in windows {
+            window.0.push((now, gas));
+            usage.insert(key, window);
+        }
+        Ok(Reservation::Reserved(now))
+    })
+}
+
+/// Return the quota taken at `reserved_at` for a release that was never sent
+fn cancel_reservation(request: &ReleaseRequest, reserved_at: u64) {
+    let config = rate_limit_config();
+    USAGE.with(|usage| {
+        let mut usage = usage.borrow_mut();
+        for (key, _) in usage_keys(request, &config) {
+            if let Some(mut window) = usage.get(&key) {
+                if let Some(pos) = window.0.iter().position(|(ts, _)| *ts == reserved_at) {
+                    window.0.remove(pos);
+                    usage.insert(key, window);
+                }
+            }
+        }
+    });
+}
+
+fn retry_backoff(attempts: u32) -> u64 {
+    RETRY_BACKOFF_BASE_NANOS
+        .saturating_mul(1u64 << attempts.min(16))
+        .min(RETRY_BACKOFF_MAX_NANOS)
+}
+
+fn enqueue(
+    request: ReleaseRequest,
+    reason: DeferReason,
+    eligible_at: u64,
+    attempts: u32,
+    last_error: Option<String>,
+) -> u64 {
+    let id = NEXT_QUEUE_ID.with(|next| {
+        let mut next = next.borrow_mut();
+        let id = next.get() + 1;
+        next.set(id).expect("failed to persist deferred release id");
+        id
+    });
+
+    log!(
+        Info,
+        Release,
+        "Deferring release #{} to {} on chain {} until {} ({:?})",
+        id,
+        request.owner,
+        request.chain_id,
+        eligible_at,
+        reason
+    );
+
+    DEFERRED_RELEASES.with(|queue| {
+        queue.borrow_mut().insert(
+            id,
+            DeferredRelease {
+                id,
+                request,
+                reason,
+                enqueued_at: time(),
+                eligible_at,
+                attempts,
+                last_error,
+            },
+        );
+    });
+    ensure_queue_timer();
+    id
+}
+
+fn ensure_queue_timer() {
+    QUEUE_TIMER.with(|timer| {
+        let mut timer = timer.borrow_mut();
+        if timer.is_none() {
+            *timer = Some(set_timer_interval(
+                Duration::from_secs(QUEUE_POLL_INTERVAL_SECS),
+                || ic_cdk::spawn(process_deferred_releases()),
+            ));
+        }
+    });
+}
+
+async fn process_deferred_releases() {
+    let service = match CHAIN_SERVICE.with(|cs| cs.borrow().clone()) {
+        Some(service) => service,
+        None => {
+            log!(Error, Release, "ChainService is not initialized");
+            return;
+        }
+    };
+
+    let now = time();
+    let due: Vec<DeferredRelease> = DEFERRED_RELEASES.with(|queue| {
+        queue
+            .borrow()
+            .iter()
+            .map(|(_, item)| item)
+            .filter(|item| item.eligible_at <= now)
+            .collect()
+    });
+
+    for item in due {
+        if bridge_control::ensure_active(
+            item.request.chain_id,
+            BridgeDirection::IcpToEvm,
+            BridgeOperation::Release,
+        )
+        .is_err()
+            || relayer_health::is_underfunded(item.request.chain_id)
+        {
+            continue;
+        }
+
+        // Take the item out of the queue before any await; the timer can start another run
+        // while this one waits on a release, and that run must not send the same item again
+        let mut item = match DEFERRED_RELEASES.with(|queue| queue.borrow_mut().remove(&item.id)) {
+            Some(item) => item,
+            None => continue,
+        };
+        let requeue = |item: DeferredRelease| {
+            DEFERRED_RELEASES.with(|queue| queue.borrow_mut().insert(item.id, item));
+        };
+
+        let reserved_at = match try_reserve(&item.request) {
+            Ok(Reservation::Reserved(reserved_at)) => reserved_at,
+            Ok(Reservation::Deferred(eligible_at)) => {
+                item.reason = DeferReason::RateLimited;
+                item.eligible_at = eligible_at;
+                requeue(item);
+                continue;
+            }
+            Err(e) => {
+                // Only possible after the limits were tightened; set_rate_limits re-checks the queue
+                log!(Warn, Release, "Deferred release #{} held: {}", item.id, e);
+                item.reason = DeferReason::RateLimited;
+                item.eligible_at = now + RETRY_BACKOFF_MAX_NANOS;
+                item.last_error = Some(e);
+                requeue(item);
+                continue;
+            }
+        };
+
+        match service.release_nft(item.request.clone()).await {
+            Ok(tx_hash) => log!(
+                Info,
+                Release,
+                "Deferred release #{} sent: {}",
+                item.id,
+                tx_hash
+            ),
+            Err(e) => {
+                cancel_reservation(&item.request, reserved_at);
+                item.reason = DeferReason::ReleaseFailed;
+                item.eligible_at = time() + retry_backoff(item.attempts);
+                item.attempts += 1;
+                log!(
+                    Error,
+                    Release,
+                    "Deferred release #{} failed (attempt {}), retrying at {}: {}",
+                    item.id,
+                    item.attempts,
+                    item.eligible_at,
+                    e
+                );
+                item.last_error = Some(e);
+                requeue(item);
+            }
+        }
+    }
+
+    if DEFERRED_RELEASES.with(|queue| queue.borrow().is_empty()) {
+        if let Some(timer_id) = QUEUE_TIMER.with(|timer| timer.borrow_mut().take()) {
+            clear_timer(timer_id);
+        }
+    }
+}
+
+impl ChainService {
+    /// Release immediately when within quota, otherwise queue the request for later. A release
+    /// that fails is queued for retry, since callers have already burned or indexed its
+    /// token. Requests the limits can never admit are rejected rather than queued forever.
+    pub async fn submit_release(&self, request: ReleaseRequest) -> Result<ReleaseOutcome, String> {
+        if relayer_health::is_underfunded(request.chain_id) {
+            let eligible_at = time();
+            let reason = DeferReason::InsufficientRelayerFunds;
+            let queue_id = enqueue(request, reason.clone(), eligible_at, 0, None);
+            return Ok(ReleaseOutcome::Deferred {
+                queue_id,
+                eligible_at,
+                reason,
+            });
+        }
+
+        match try_reserve(&request)? {
+            Reservation::Reserved(reserved_at) => match self.release_nft(request.clone()).await {
+                Ok(tx_hash) => Ok(ReleaseOutcome::Released { tx_hash }),
+                Err(e) => {
+                    cancel_reservation(&request, reserved_at);
+                    let eligible_at = time() + retry_backoff(0);
+                    log!(
+                        Error,
+                        Release,
+                        "Release to {} on chain {} failed, retrying at {}: {}",
+                        request.owner,
+                        request.chain_id,
+                        eligible_at,
+                        e
+                    );
+                    let reason = DeferReason::ReleaseFailed;
+                    let queue_id = enqueue(request, reason.clone(), eligible_at, 1, Some(e));
+                    Ok(ReleaseOutcome::Deferred {
+                        queue_id,
+                        eligible_at,
+                        reason,
+                    })
+                }
+            },
+            Reservation::Deferred(eligible_at) => {
+                let reason = DeferReason::RateLimited;
+                let queue_id = enqueue(request, reason.clone(), eligible_at, 0, None);
+                Ok(ReleaseOutcome::Deferred {
+                    queue_id,
+                    eligible_at,
+                    reason,
+                })
+            }
+        }
+    }
+}
+
+#[update]
+pub fn set_rate_limits(config: RateLimitConfig) -> Result<(), String> {
+    ensure_admin()?;
+    RATE_LIMIT_CONFIG
+        .with(|c| c.borrow_mut().set(config))
+        .map_err(|e| format!("Failed to store rate limits: {:?}", e))?;
+    // Re-evaluate rate-limited releases against the new limits on the next poll; failed
+    // releases keep their retry backoff
+    let now = time();
+    DEFERRED_RELEASES.with(|queue| {
+        let mut queue = queue.borrow_mut();
+        let held: Vec<DeferredRelease> = queue
+            .iter()
+            .map(|(_, item)| item)
+            .filter(|item| {
+                matches!(item.reason, DeferReason::RateLimited) && item.eligible_at > now
+            })
+            .collect();
+        for mut item in held {
+            item.eligible_at = now;
+            queue.insert(item.id, item);
+        }
+    });
+    Ok(())
+}
+
+fn rate_limit_config() -> RateLimitConfig {
+    RATE_LIMIT_CONFIG.with(|c| c.borrow().get().clone())
+}
+
+/// Timers do not survive an upgrade; restart queue polling if releases are still waiting
+pub fn resume_deferred_releases() {
+    if !DEFERRED_RELEASES.with(|queue| queue.borrow().is_empty()) {
+        ensure_queue_timer();
+    }
+}
+
+#[query]
+pub fn get_rate_limits() -> RateLimitConfig {
+    rate_limit_config()
+}
+
+#[query]
+pub fn get_deferred_releases() -> Vec<DeferredRelease> {
+    DEFERRED_RELEASES.with(|queue| queue.borrow().iter().map(|(_, item)| item).collect())
+}
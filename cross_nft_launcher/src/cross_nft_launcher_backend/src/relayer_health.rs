@@ -0,0 +1,281 @@
+use candid::{CandidType, Nat};
+use ic_cdk::api::time;
+use ic_cdk::{query, update};
+use ic_cdk_timers::{set_timer, set_timer_interval, TimerId};
+use ic_stable_structures::{StableBTreeMap, StableCell};
+use num_traits::ToPrimitive;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::time::Duration;
+
+use crate::ecdsa_keys;
+use crate::ensure_admin;
+use crate::evm_nft_indexer::{ChainService, CHAIN_SERVICE, HOLESKY_CHAIN_ID, SEPOLIA_CHAIN_ID};
+use crate::log;
+use crate::memory::{
+    candid_storable, get_memory, Memory, RELAYER_HEALTH_CONFIG_MEMORY_ID, RELAYER_HEALTH_MEMORY_ID,
+};
+use crate::release_nft::{estimate_transaction_fees, RELEASE_GAS_LIMIT};
+
+const MONITORED_CHAINS: [u64; 2] = [SEPOLIA_CHAIN_ID, HOLESKY_CHAIN_ID];
+// Each poll makes two HTTPS outcalls per chain
+const MIN_POLL_INTERVAL_SECS: u64 = 60;
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+pub enum FundingStatus {
+    Healthy,
+    // Still releasing, but fewer than `low_funds_releases` left
+    Low,
+    // Releases are held until the relayer is topped up
+    InsufficientRelayerFunds,
+    Unknown,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct RelayerHealthConfig {
+    pub poll_interval_secs: u64,
+    pub low_funds_releases: u64,
+    // Hold releases when the balance covers fewer than this many
+    pub min_releases: u64,
+}
+
+impl Default for RelayerHealthConfig {
+    fn default() -> Self {
+        RelayerHealthConfig {
+            poll_interval_secs: 600,
+            low_funds_releases: 20,
+            min_releases: 1,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct RelayerHealth {
+    pub chain_id: u64,
+    pub address: String,
+    pub balance_wei: Nat,
+    pub gas_price_wei: Nat,
+    pub cost_per_release_wei: Nat,
+    pub releases_remaining: u64,
+    pub status: FundingStatus,
+    pub checked_at: u64,
+    pub error: Option<String>,
+}
+
+candid_storable!(RelayerHealthConfig, RelayerHealth);
+
+thread_local! {
+    static HEALTH_CONFIG: RefCell<StableCell<RelayerHealthConfig, Memory>> = RefCell::new(
+        StableCell::init(get_memory(RELAYER_HEALTH_CONFIG_MEMORY_ID), RelayerHealthConfig::default())
+            .expect("failed to init relayer health config")
+    );
+
+    // Kept across upgrades so an underfunded relayer stays held until the next check
+    static RELAYER_HEALTH: RefCell<StableBTreeMap<u64, RelayerHealth, Memory>> = RefCell::new(
+        StableBTreeMap::init(get_memory(RELAYER_HEALTH_MEMORY_ID))
+    );
+
+    static BALANCE_TIMER: RefCell<Option<TimerId>> = RefCell::new(None);
+}
+
+fn health_config() -> RelayerHealthConfig {
+    HEALTH_CONFIG.with(|c| c.borrow().get().clone())
+}
+
+/// Gas price seen by the last successful balance check on `chain_id`
+pub fn gas_price_wei(chain_id: u64) -> Option<u128> {
+    RELAYER_HEALTH.with(|health| {
+        health
+            .borrow()
+            .get(&chain_id)
+            .filter(|h| h.status != FundingStatus::Unknown)
+            .and_then(|h| h.gas_price_wei.0.to_u128())
+            .filter(|price| *price > 0)
+    })
+}
+
+/// True when the last balance check showed the relayer on `chain_id` cannot pay for a release
+pub fn is_underfunded(chain_id: u64) -> bool {
+    RELAYER_HEALTH.with(|health| {
+        health.borrow().get(&chain_id).map_or(false, |h| {
+            h.status == FundingStatus::InsufficientRelayerFunds
+        })
+    })
+}
+
+fn parse_quantity(value: &serde_json::Value) -> Result<u128, String> {
+    let hex = value
+        .as_str()
+        .ok_or_else(|| format!("Expected hex quantity, got {}", value))?;
+    u128::from_str_radix(hex.trim_start_matches("0x"), 16)
+        .map_err(|e| format!("Invalid hex quantity '{}': {}", hex, e))
+}
+
+fn classify(releases_remaining: u64, config: &RelayerHealthConfig) -> FundingStatus {
+    if releases_remaining < config.min_releases {
+        FundingStatus::InsufficientRelayerFunds
+    } else if releases_remaining < config.low_funds_releases {
+        FundingStatus::Low
+    } else {
+        FundingStatus::Healthy
+    }
+}
+
+impl ChainService {
+    async fn check_relayer_health(&self, chain_id: u64) -> RelayerHealth {
+        let address = ecdsa_keys::relayer_address(chain_id).await;
+        let mut health = RelayerHealth {
+            chain_id,
+            address: address.clone().unwrap_or_default(),
+            balance_wei: Nat::from(0u64),
+            gas_price_wei: Nat::from(0u64),
+            cost_per_release_wei: Nat::from(0u64),
+            releases_remaining: 0,
+            status: FundingStatus::Unknown,
+            checked_at: time(),
+            error: None,
+        };
+
+        let address = match address {
+            Ok(address) => address,
+            Err(e) => {
+                health.error = Some(e);
+                return health;
+            }
+        };
+
+        let balance = self
+            .json_rpc(
+                chain_id,
+                "eth_getBalance",
+                serde_json::json!([address, "latest"]),
+            )
+            .await
+            .and_then(|v| parse_quantity(&v));
+        let gas_price = self
+            .json_rpc(chain_id, "eth_gasPrice", serde_json::json!([]))
+            .await
+            .and_then(|v| parse_quantity(&v));
+
+        let (balance, gas_price) = match (balance, gas_price) {
+            (Ok(balance), Ok(gas_price)) => (balance, gas_price),
+            (Err(e), _) | (_, Err(e)) => {
+                health.error = Some(e);
+                return health;
+            }
+        };
+
+        // Nodes reject a release unless the balance covers gas limit x max fee, so never
+        // budget below the fee cap we actually sign with
+        let (_, max_fee_per_gas, _) = estimate_transaction_fees();
+        let cost_per_release = RELEASE_GAS_LIMIT as u128 * gas_price.max(max_fee_per_gas);
+        let releases_remaining = (balance / cost_per_release).min(u64::MAX as u128) as u64;
+
+        health.balance_wei = Nat::from(balance);
+        health.gas_price_wei = Nat::from(gas_price);
+        health.cost_per_release_wei = Nat::from(cost_per_release);
+        health.releases_remaining = releases_remaining;
+        health.status = classify(releases_remaining, &health_config());
+        health
+    }
+}
+
+async fn refresh_relayer_health() {
+    let service = match CHAIN_SERVICE.with(|cs| cs.borrow().clone()) {
+        Some(service) => service,
+        None => {
+            log!(Error, Release, "ChainService is not initialized");
+            return;
+        }
+    };
+
+    for chain_id in MONITORED_CHAINS {
+        let health = service.check_relayer_health(chain_id).await;
+        match health.status {
+            FundingStatus::InsufficientRelayerFunds => log!(
+                Error,
+                Release,
+                "Relayer {} on chain {} cannot fund releases ({} wei); holding releases",
+                health.address,
+                chain_id,
+                health.balance_wei
+            ),
+            FundingStatus::Low => log!(
+                Warn,
+                Release,
+                "Relayer {} on chain {} is low on funds: ~{} releases left",
+                health.address,
+                chain_id,
+                health.releases_remaining
+            ),
+            FundingStatus::Unknown => log!(
+                Warn,
+                Release,
+                "Relayer balance check on chain {} failed: {}",
+                chain_id,
+                health.error.clone().unwrap_or_default()
+            ),
+            FundingStatus::Healthy => {}
+        }
+        RELAYER_HEALTH.with(|h| h.borrow_mut().insert(chain_id, health));
+    }
+}
+
+/// Start polling relayer balances; safe to call repeatedly
+pub fn ensure_balance_monitor() {
+    BALANCE_TIMER.with(|timer| {
+        let mut timer = timer.borrow_mut();
+        if timer.is_none() {
+            let interval = health_config().poll_interval_secs;
+            set_timer(Duration::ZERO, || ic_cdk::spawn(refresh_relayer_health()));
+            *timer = Some(set_timer_interval(Duration::from_secs(interval), || {
+                ic_cdk::spawn(refresh_relayer_health())
+            }));
+        }
+    });
+}
+
+/// Timers do not survive an upgrade; restart polling if balances were being monitored
+pub fn resume_balance_monitor() {
+    if !RELAYER_HEALTH.with(|health| health.borrow().is_empty()) {
+        ensure_balance_monitor();
+    }
+}
+
+#[query]
+pub fn relayer_health() -> Vec<RelayerHealth> {
+    RELAYER_HEALTH.with(|health| health.borrow().iter().map(|(_, h)| h).collect())
+}
+
+/// Re-check balances immediately, e.g. right after topping up a relayer
+#[update]
+pub async fn refresh_relayer_balances() -> Result<Vec<RelayerHealth>, String> {
+    ensure_admin()?;
+    refresh_relayer_health().await;
+    Ok(relayer_health())
+}
+
+#[update]
+pub fn set_relayer_health_config(config: RelayerHealthConfig) -> Result<(), String> {
+    ensure_admin()?;
+    if config.poll_interval_secs < MIN_POLL_INTERVAL_SECS {
+        return Err(format!(
+            "poll_interval_secs must be at least {}",
+            MIN_POLL_INTERVAL_SECS
+        ));
+    }
+    HEALTH_CONFIG
+        .with(|c| c.borrow_mut().set(config))
+        .map_err(|e| format!("Failed to store relayer health config: {:?}", e))?;
+    // Restart the poll so a new interval takes effect
+    if let Some(timer_id) = BALANCE_TIMER.with(|timer| timer.borrow_mut().take()) {
+        ic_cdk_timers::clear_timer(timer_id);
+        ensure_balance_monitor();
+    }
+    Ok(())
+}
+
+#[query]
+pub fn get_relayer_health_config() -> RelayerHealthConfig {
+    health_config()
+}
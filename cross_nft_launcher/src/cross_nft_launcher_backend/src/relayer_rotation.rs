@@ -0,0 +1,338 @@
+use candid::CandidType;
+use ethabi::ethereum_types::{H160, U256};
+use ethabi::{Function, Param, ParamType, Token};
+use ic_cdk::api::call::call_with_payment128;
+use ic_cdk::api::time;
+use ic_cdk::{query, update};
+use ic_stable_structures::StableBTreeMap;
+use num_traits::ToPrimitive;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::str::FromStr;
+
+use crate::ecdsa_keys::{self, KeyPurpose};
+use crate::ensure_admin;
+use crate::evm_nft_indexer::{ChainService, CHAIN_SERVICE};
+use crate::evm_rpc_bindings::{
+    GetTransactionReceiptResult, MultiGetTransactionReceiptResult, RpcConfig, RpcServices,
+};
+use crate::log;
+use crate::memory::{candid_storable, get_memory, Memory, RELAYER_GENERATIONS_MEMORY_ID};
+use crate::release_nft::{nft_contract_address, rpc_services};
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+const SET_RELAYER_GAS_LIMIT: u64 = 100_000;
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+pub enum GenerationStatus {
+    // Handover transaction sent, waiting for it to be mined
+    Pending,
+    Active,
+    // Rotated out; the contract still accepts it until `retired_at`
+    Grace,
+    Retired,
+    Failed,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct RelayerGeneration {
+    pub chain_id: u64,
+    pub generation: u32,
+    pub address: String,
+    pub status: GenerationStatus,
+    pub derived_at: u64,
+    pub activated_at: Option<u64>,
+    pub retired_at: Option<u64>,
+    // setRelayer transaction that handed the role to this generation
+    pub handover_tx: Option<String>,
+    // How long the previous generation stays authorized after the handover
+    pub grace_period_secs: u64,
+    pub error: Option<String>,
+}
+
+candid_storable!(RelayerGeneration);
+
+thread_local! {
+    // (chain id, generation) -> history entry; never pruned so rotations stay auditable
+    static GENERATIONS: RefCell<StableBTreeMap<(u64, u32), RelayerGeneration, Memory>> = RefCell::new(
+        StableBTreeMap::init(get_memory(RELAYER_GENERATIONS_MEMORY_ID))
+    );
+}
+
+fn generations(chain_id: u64) -> Vec<RelayerGeneration> {
+    GENERATIONS.with(|g| {
+        g.borrow()
+            .range((chain_id, 0)..=(chain_id, u32::MAX))
+            .map(|(_, generation)| generation)
+            .collect()
+    })
+}
+
+fn remove(chain_id: u64, generation: u32) {
+    GENERATIONS.with(|g| g.borrow_mut().remove(&(chain_id, generation)));
+}
+
+fn save(generation: RelayerGeneration) {
+    GENERATIONS.with(|g| {
+        g.borrow_mut()
+            .insert((generation.chain_id, generation.generation), generation)
+    });
+}
+
+/// Generation whose key currently signs releases on `chain_id`; 0 until the first rotation
+pub fn active_generation(chain_id: u64) -> u32 {
+    generations(chain_id)
+        .into_iter()
+        .rev()
+        .find(|g| g.status == GenerationStatus::Active)
+        .map_or(0, |g| g.generation)
+}
+
+fn with_expired_grace(mut generation: RelayerGeneration, now: u64) -> RelayerGeneration {
+    if generation.status == GenerationStatus::Grace
+        && generation.retired_at.map_or(false, |at| at <= now)
+    {
+        generation.status = GenerationStatus::Retired;
+    }
+    generation
+}
+
+fn set_relayer_call(new_relayer: &str, grace_period_secs: u64) -> Result<Vec<u8>, String> {
+    let function = Function {
+        name: "setRelayer".to_string(),
+        inputs: vec![
+            Param {
+                name: "newRelayer".to_string(),
+                kind: ParamType::Address,
+                internal_type: None,
+            },
+            Param {
+                name: "gracePeriod".to_string(),
+                kind: ParamType::Uint(256),
+                internal_type: None,
+            },
+        ],
+        outputs: vec![],
+        constant: None,
+        state_mutability: ethabi::StateMutability::NonPayable,
+    };
+
+    let new_relayer = H160::from_str(new_relayer)
+        .map_err(|e| format!("Invalid relayer address '{}': {:?}", new_relayer, e))?;
+    function
+        .encode_input(&[
+            Token::Address(new_relayer),
+            Token::Uint(U256::from(grace_period_secs)),
+        ])
+        .map_err(|e| format!("Failed to encode setRelayer: {}", e))
+}
+
+/// Derive the next relayer key for `chain_id` and have the current key hand the contract role over to it.
+/// The new key only starts signing once `finalize_relayer_rotation` sees the handover mined.
+#[update]
+pub async fn rotate_relayer_key(
+    chain_id: u64,
+    grace_period_secs: u64,
+) -> Result<RelayerGeneration, String> {
+    ensure_admin()?;
+    let contract = nft_contract_address(chain_id)?;
+    let service = CHAIN_SERVICE
+        .with(|cs| cs.borrow().clone())
+        .ok_or_else(|| "ChainService is not initialized".to_string())?;
+
+    let history = generations(chain_id);
+    if history
+        .iter()
+        .any(|g| g.status == GenerationStatus::Pending)
+    {
+        return Err(format!(
+            "A relayer rotation on chain {} is already pending",
+            chain_id
+        ));
+    }
+
+    let current = ecdsa_keys::active_relayer(chain_id);
+    let next_generation = history.last().map_or(1, |g| g.generation + 1);
+    // Saved before the first await, so a concurrent call sees the rotation as pending
+    let mut record = RelayerGeneration {
+        chain_id,
+        generation: next_generation,
+        address: String::new(),
+        status: GenerationStatus::Pending,
+        derived_at: time(),
+        activated_at: None,
+        retired_at: None,
+        handover_tx: None,
+        grace_period_secs,
+        error: None,
+    };
+    save(record.clone());
+
+    match send_handover(
+        &service,
+        contract,
+        &current,
+        history.is_empty(),
+        &mut record,
+    )
+    .await
+    {
+        Ok(()) => Ok(record),
+        Err(e) => {
+            // Nothing was handed over, so the generation is free for the next attempt
+            remove(chain_id, next_generation);
+            log!(
+                Error,
+                Release,
+                "Relayer rotation on chain {} failed: {}",
+                chain_id,
+                e
+            );
+            Err(e)
+        }
+    }
+}
+
+/// Derive the key of the pending `record` and send the setRelayer transaction to it from `current`
+async fn send_handover(
+    service: &ChainService,
+    contract: &str,
+    current: &KeyPurpose,
+    first_rotation: bool,
+    record: &mut RelayerGeneration,
+) -> Result<(), String> {
+    let chain_id = record.chain_id;
+    if first_rotation {
+        // Record the original key so the history starts at generation 0
+        let key = ecdsa_keys::derived_key(current).await?;
+        save(RelayerGeneration {
+            chain_id,
+            generation: 0,
+            address: key.address,
+            status: GenerationStatus::Active,
+            derived_at: time(),
+            activated_at: None,
+            retired_at: None,
+            handover_tx: None,
+            grace_period_secs: 0,
+            error: None,
+        });
+    }
+
+    let next_key = ecdsa_keys::derived_key(&KeyPurpose::Relayer {
+        chain_id,
+        generation: record.generation,
+    })
+    .await?;
+    record.address = next_key.address;
+    save(record.clone());
+
+    let call_data = set_relayer_call(&record.address, record.grace_period_secs)?;
+    let tx_hash = service
+        .sign_and_send(
+            chain_id,
+            current,
+            contract,
+            call_data,
+            SET_RELAYER_GAS_LIMIT,
+        )
+        .await?;
+    log!(
+        Info,
+        Release,
+        "Relayer handover on chain {} to generation {} ({}): {}",
+        chain_id,
+        record.generation,
+        record.address,
+        tx_hash
+    );
+    record.handover_tx = Some(tx_hash);
+    save(record.clone());
+    Ok(())
+}
+
+/// Switch signing to the pending generation once its handover transaction has succeeded on chain
+#[update]
+pub async fn finalize_relayer_rotation(chain_id: u64) -> Result<RelayerGeneration, String> {
+    ensure_admin()?;
+    let service = CHAIN_SERVICE
+        .with(|cs| cs.borrow().clone())
+        .ok_or_else(|| "ChainService is not initialized".to_string())?;
+
+    let mut pending = generations(chain_id)
+        .into_iter()
+        .find(|g| g.status == GenerationStatus::Pending)
+        .ok_or_else(|| format!("No pending relayer rotation on chain {}", chain_id))?;
+    let tx_hash = pending.handover_tx.clone().ok_or_else(|| {
+        format!(
+            "Relayer rotation on chain {} has not sent its handover yet",
+            chain_id
+        )
+    })?;
+
+    let (receipt_result,) = call_with_payment128::<
+        (RpcServices, Option<RpcConfig>, String),
+        (MultiGetTransactionReceiptResult,),
+    >(
+        service.evm_rpc.0,
+        "eth_getTransactionReceipt",
+        (rpc_services(chain_id)?, None, tx_hash.clone()),
+        200_000_000_000u128,
+    )
+    .await
+    .map_err(|e| format!("Failed to get transaction receipt: {:?}", e))?;
+
+    let receipt = match receipt_result {
+        MultiGetTransactionReceiptResult::Consistent(GetTransactionReceiptResult::Ok(receipt)) => {
+            receipt.ok_or_else(|| format!("Handover transaction {} is not mined yet", tx_hash))?
+        }
+        MultiGetTransactionReceiptResult::Consistent(GetTransactionReceiptResult::Err(e)) => {
+            return Err(format!(
+                "RPC error fetching receipt for {}: {:?}",
+                tx_hash, e
+            ));
+        }
+        MultiGetTransactionReceiptResult::Inconsistent(_) => {
+            return Err(format!("Inconsistent receipts for {}", tx_hash));
+        }
+    };
+
+    let now = time();
+    if receipt.status.and_then(|s| s.0.to_u64()) != Some(1) {
+        pending.status = GenerationStatus::Failed;
+        pending.error = Some(format!("Handover transaction {} reverted", tx_hash));
+        save(pending.clone());
+        return Err(pending.error.unwrap_or_default());
+    }
+
+    for mut previous in generations(chain_id)
+        .into_iter()
+        .filter(|g| g.status == GenerationStatus::Active)
+    {
+        previous.status = GenerationStatus::Grace;
+        previous.retired_at = Some(now + pending.grace_period_secs * NANOS_PER_SEC);
+        save(previous);
+    }
+
+    pending.status = GenerationStatus::Active;
+    pending.activated_at = Some(now);
+    save(pending.clone());
+    log!(
+        Info,
+        Release,
+        "Relayer generation {} is now active on chain {}: {}",
+        pending.generation,
+        chain_id,
+        pending.address
+    );
+    Ok(pending)
+}
+
+#[query]
+pub fn get_relayer_generations(chain_id: u64) -> Vec<RelayerGeneration> {
+    let now = time();
+    generations(chain_id)
+        .into_iter()
+        .map(|g| with_expired_grace(g, now))
+        .collect()
+}
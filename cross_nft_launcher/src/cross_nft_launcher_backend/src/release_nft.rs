@@ -1,31 +1,34 @@
-use candid::Nat;
+use candid::{CandidType, Nat};
 use ethabi::ethereum_types::{H160, U256};
 use ethabi::{Address, Function, Param, ParamType, Token};
 use ethers_core::types::{Bytes, Eip1559TransactionRequest, U64};
 use hex;
 use ic_cdk::api::call::call_with_payment128;
-use ic_cdk::update;
+use ic_cdk::{query, update};
+use ic_stable_structures::StableCell;
 use k256::PublicKey;
 use sha2::Digest;
+use std::cell::RefCell;
 use std::str::FromStr;
 
-use crate::evm_nft_indexer::ChainService;
+use crate::bridge_control::{self, BridgeDirection, BridgeOperation};
+use crate::ecdsa_keys::{self, KeyPurpose};
+use crate::evm_nft_indexer::{nat_to_u256, ChainService, HOLESKY_CHAIN_ID, SEPOLIA_CHAIN_ID};
 use crate::evm_rpc_bindings::GetTransactionCountResult;
 use crate::evm_rpc_bindings::MultiGetTransactionCountResult;
 use crate::evm_rpc_bindings::SendRawTransactionResult;
-use crate::evm_rpc_bindings::{BlockTag, RpcServices};
+use crate::evm_rpc_bindings::{BlockTag, RequestResult, RpcService, RpcServices};
 use crate::evm_rpc_bindings::{
     EthSepoliaService, GetTransactionCountArgs, MultiSendRawTransactionResult, RpcApi,
     SendRawTransactionStatus,
 };
-use crate::{evm_rpc_bindings, Account, MetadataValue};
-use ic_cdk::api::management_canister::ecdsa::sign_with_ecdsa;
-use ic_cdk::api::management_canister::ecdsa::EcdsaPublicKeyResponse;
+use crate::log;
+use crate::memory::{get_memory, Memory, JSON_RPC_MAX_RESPONSE_BYTES_MEMORY_ID};
+use crate::metadata_policy::{self, NftMetadata};
+use crate::{ensure_admin, evm_rpc_bindings, Account, MetadataValue};
 use ic_cdk::api::management_canister::ecdsa::SignWithEcdsaResponse;
-use ic_cdk::api::management_canister::ecdsa::{
-    ecdsa_public_key, EcdsaCurve, EcdsaKeyId, EcdsaPublicKeyArgument, SignWithEcdsaArgument,
-};
 use num_traits::ToPrimitive;
+use serde::{Deserialize, Serialize};
 
 use once_cell::sync::Lazy;
 use std::sync::RwLock;
@@ -38,30 +41,91 @@ static SEPOLIA_TX_HASH: Lazy<RwLock<Option<String>>> = Lazy::new(|| RwLock::new(
 const NFT_CONTRACT_ADDRESS_HEX: &str = "0x027315bad2c06b0ab2a4f31c6b4b162f798a3b31";
 const EIP1559_TX_ID: u8 = 2;
 const NFT_SEPOLIA_ADDRESS_HEX: &str = "0x800e11fb1f4c9b33eab0dd7aae19c2ae741be30c";
+/// Gas budgeted for a single mint_nft_release call
+pub const RELEASE_GAS_LIMIT: u64 = 500_000;
+// Room for eth_call results such as long tokenURI strings; admins can raise it further
+const DEFAULT_JSON_RPC_MAX_RESPONSE_BYTES: u64 = 64 * 1024;
+const MAX_JSON_RPC_RESPONSE_BYTES: u64 = 2_000_000;
+const JSON_RPC_CYCLES: u128 = 10_000_000_000;
+// Response bytes are charged on each of the EVM RPC canister's 34 nodes; unused cycles are refunded
+const JSON_RPC_CYCLES_PER_RESPONSE_BYTE: u128 = 800 * 34;
+
+thread_local! {
+    static JSON_RPC_MAX_RESPONSE_BYTES: RefCell<StableCell<u64, Memory>> = RefCell::new(
+        StableCell::init(
+            get_memory(JSON_RPC_MAX_RESPONSE_BYTES_MEMORY_ID),
+            DEFAULT_JSON_RPC_MAX_RESPONSE_BYTES,
+        )
+        .expect("failed to init JSON-RPC response limit")
+    );
+}
+
+/// A pending mint_nft_release on a destination EVM chain
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct ReleaseRequest {
+    pub chain_id: u64,
+    pub source_address: String,
+    pub owner: String,
+    pub name: String,
+    pub description: String,
+    pub image: String,
+    pub price: Nat,
+}
 
 impl ChainService {
+    /// Send a release to the contract on `request.chain_id` and feed the outcome to the circuit breaker
+    pub async fn release_nft(&self, request: ReleaseRequest) -> Result<String, String> {
+        let chain_id = request.chain_id;
+        let NftMetadata {
+            name,
+            description,
+            image,
+        } = metadata_policy::apply(
+            chain_id,
+            NftMetadata {
+                name: request.name,
+                description: request.description,
+                image: request.image,
+            },
+        )?;
+        let result = match chain_id {
+            HOLESKY_CHAIN_ID => {
+                self.call_mint_nft_release(request.owner, name, description, image, request.price)
+                    .await
+            }
+            SEPOLIA_CHAIN_ID => {
+                self.call_mint_nft_release_sepolia(
+                    request.owner,
+                    name,
+                    description,
+                    image,
+                    request.price,
+                )
+                .await
+            }
+            other => return Err(format!("Unsupported release chain {}", other)),
+        };
+
+        bridge_control::record_release_result(chain_id, result.is_ok());
+        result
+    }
+
     async fn pubkey_and_signature(
         &self,
+        chain_id: u64,
         tx_hash: Vec<u8>,
     ) -> Result<(Vec<u8>, SignWithEcdsaResponse), String> {
-        let public_key_response = get_ecdsa_public_key().await?; // now a Result
+        let (key, signature) =
+            ecdsa_keys::sign(&ecdsa_keys::active_relayer(chain_id), tx_hash).await?;
 
-        let (signature_response,) = sign_with_ecdsa(SignWithEcdsaArgument {
-            message_hash: tx_hash,
-            key_id: key_id(),
-            ..Default::default()
-        })
-        .await
-        .map_err(|e| format!("Failed to generate signature {:?}", e))?;
-
-        Ok((public_key_response.public_key, signature_response))
+        Ok((key.public_key, SignWithEcdsaResponse { signature }))
     }
 
     /// Fetch transaction count (nonce) for your IC Ethereum address (from secp256k1 pubkey)
     pub async fn fetch_tx_nonce(&self) -> Result<Nat, String> {
         let block_tag = BlockTag::Latest;
-        let (canister_address, _ecdsa_key) = get_network_config();
-        ic_cdk::println!("canister_address {}", canister_address);
+        let canister_address = ecdsa_keys::relayer_address(HOLESKY_CHAIN_ID).await?;
+        log!(Debug, Release, "canister_address {}", canister_address);
         let get_transaction_count_args = GetTransactionCountArgs {
             address: canister_address.to_string(),
             block: block_tag,
@@ -138,8 +202,14 @@ impl ChainService {
         name: String,
         description: String,
         image: String,
-        price: u64,
+        price: Nat,
     ) -> Result<String, String> {
+        bridge_control::ensure_active(
+            HOLESKY_CHAIN_ID,
+            BridgeDirection::IcpToEvm,
+            BridgeOperation::Release,
+        )?;
+
         // 1. Prepare the function ABI for mint_nft_release(address,string,string,string,uint256)
         let function = Function {
             name: "mint_nft_release".to_string(),
@@ -193,7 +263,9 @@ impl ChainService {
         }
 
         // 3. FIXED: Create tokens with proper validation
-        ic_cdk::println!(
+        log!(
+            Debug,
+            Release,
             "Encoding parameters - Name: '{}', Desc: '{}', Image: '{}', Price: {}",
             name,
             description,
@@ -201,12 +273,13 @@ impl ChainService {
             price
         );
 
+        let price_u256 = nat_to_u256(&price)?;
         let tokens = vec![
             Token::Address(ethabi::Address::from_slice(&owner_bytes)),
             Token::String(name.clone()),
             Token::String(description.clone()),
             Token::String(image.clone()),
-            Token::Uint(U256::from(price)),
+            Token::Uint(price_u256),
         ];
 
         // 4. FIXED: Add debug logging for ABI encoding
@@ -214,26 +287,44 @@ impl ChainService {
             .encode_input(&tokens)
             .map_err(|e| format!("Failed to encode input: {}", e))?;
 
-        ic_cdk::println!("Encoded call data length: {} bytes", call_data.len());
-        ic_cdk::println!(
+        log!(
+            Debug,
+            Release,
+            "Encoded call data length: {} bytes",
+            call_data.len()
+        );
+        log!(
+            Debug,
+            Release,
             "Call data (first 100 bytes): 0x{}",
             hex::encode(&call_data[..std::cmp::min(100, call_data.len())])
         );
 
         // 5. Get public key and derive address
-        let public_key_hex = generate_key_pair_evm().await?;
-        ic_cdk::println!("Using public key: {}", public_key_hex);
+        let relayer_address = ecdsa_keys::relayer_address(HOLESKY_CHAIN_ID).await?;
+        log!(Debug, Release, "Using relayer address: {}", relayer_address);
+
+        // Dry-run the exact call so predictable reverts fail here instead of burning gas
+        self.simulate_call(
+            HOLESKY_CHAIN_ID,
+            &relayer_address,
+            NFT_CONTRACT_ADDRESS_HEX,
+            &call_data,
+        )
+        .await?;
 
         // 6. Get nonce
         let nonce = self.fetch_tx_nonce().await?;
-        ic_cdk::println!("Using nonce: {}", nonce);
+        log!(Debug, Release, "Using nonce: {}", nonce);
 
         // 7. FIXED: Use more reasonable gas settings
-        let gas_limit = 500_000u64; // Increased gas limit
+        let gas_limit = RELEASE_GAS_LIMIT;
         let max_fee_per_gas = 20_000_000_000u64; // 20 Gwei
         let max_priority_fee_per_gas = 2_000_000_000u64; // 2 Gwei
 
-        ic_cdk::println!(
+        log!(
+            Debug,
+            Release,
             "Gas settings - Limit: {}, MaxFee: {}, MaxPriority: {}",
             gas_limit,
             max_fee_per_gas,
@@ -260,7 +351,9 @@ impl ChainService {
         unsigned_tx.insert(0, EIP1559_TX_ID);
 
         let tx_hash = ethers_core::utils::keccak256(&unsigned_tx);
-        let (public_key_bytes, signature) = self.pubkey_and_signature(tx_hash.to_vec()).await?;
+        let (public_key_bytes, signature) = self
+            .pubkey_and_signature(HOLESKY_CHAIN_ID, tx_hash.to_vec())
+            .await?;
         let y_parity = y_parity(&tx_hash, &signature.signature, &public_key_bytes);
 
         let sig = ethers_core::types::Signature {
@@ -273,7 +366,7 @@ impl ChainService {
         signed_tx.insert(0, EIP1559_TX_ID);
 
         let raw_tx_hex = format!("0x{}", hex::encode(&signed_tx));
-        ic_cdk::println!("Raw signed transaction hex: {}", raw_tx_hex);
+        log!(Debug, Release, "Raw signed transaction hex: {}", raw_tx_hex);
 
         let cycles_to_pay: u128 = 600_000_000_000;
 
@@ -319,8 +412,10 @@ impl ChainService {
                             let mut hash = HOLESKY_TX_HASH.write().unwrap();
                             *hash = Some(tx_hash.clone());
                         }
-                        ic_cdk::println!(
-                            "✅ Transaction sent successfully, tx hash: {:?}",
+                        log!(
+                            Info,
+                            Release,
+                            "Transaction sent successfully, tx hash: {:?}",
                             tx_hash
                         );
                         Ok(tx_hash)
@@ -354,9 +449,19 @@ impl ChainService {
         name: String,
         description: String,
         image: String,
-        price: u64,
+        price: Nat,
     ) -> Result<String, String> {
-        ic_cdk::println!("call_mint_nft_release_sepolia - Starting transaction preparation");
+        log!(
+            Debug,
+            Release,
+            "call_mint_nft_release_sepolia - Starting transaction preparation"
+        );
+
+        bridge_control::ensure_active(
+            SEPOLIA_CHAIN_ID,
+            BridgeDirection::IcpToEvm,
+            BridgeOperation::Release,
+        )?;
 
         // ENHANCED: Input validation against contract requirements
         if name.len() > 32 {
@@ -378,13 +483,17 @@ impl ChainService {
             ));
         }
 
-        ic_cdk::println!(
-            "✅ Input validation passed - Name: {} bytes, Desc: {} bytes, Image: {} bytes",
+        log!(
+            Debug,
+            Release,
+            "Input validation passed - Name: {} bytes, Desc: {} bytes, Image: {} bytes",
             name.len(),
             description.len(),
             image.len()
         );
-        ic_cdk::println!(
+        log!(
+            Debug,
+            Release,
             "Parameters - Name: '{}', Description: '{}', Price: {}",
             name,
             description,
@@ -444,18 +553,21 @@ impl ChainService {
             ));
         }
 
-        ic_cdk::println!("Owner address validated: {}", owner_address);
+        log!(Debug, Release, "Owner address validated: {}", owner_address);
 
         // ENHANCED: Create tokens with detailed logging
+        let price_u256 = nat_to_u256(&price)?;
         let tokens = vec![
             ethabi::Token::Address(ethabi::Address::from_slice(&owner_bytes)),
             ethabi::Token::String(name.clone()),
             ethabi::Token::String(description.clone()),
             ethabi::Token::String(image.clone()),
-            ethabi::Token::Uint(U256::from(price)),
+            ethabi::Token::Uint(price_u256),
         ];
 
-        ic_cdk::println!(
+        log!(
+            Debug,
+            Release,
             "ABI tokens prepared - Address: {}, Strings: [{}, {}, {}], Price: {}",
             owner_address,
             name,
@@ -473,15 +585,28 @@ impl ChainService {
             .encode_input(&tokens)
             .map_err(|e| format!("Failed to encode input data: {}. Tokens: {:?}", e, tokens))?;
 
-        ic_cdk::println!(
-            "✅ ABI encoding successful - Call data length: {} bytes",
+        log!(
+            Debug,
+            Release,
+            "ABI encoding successful - Call data length: {} bytes",
             call_data.len()
         );
-        ic_cdk::println!(
+        log!(
+            Debug,
+            Release,
             "Call data preview (first 64 bytes): 0x{}",
             hex::encode(&call_data[..std::cmp::min(64, call_data.len())])
         );
 
+        let relayer_address = ecdsa_keys::relayer_address(SEPOLIA_CHAIN_ID).await?;
+        self.simulate_call(
+            SEPOLIA_CHAIN_ID,
+            &relayer_address,
+            NFT_SEPOLIA_ADDRESS_HEX,
+            &call_data,
+        )
+        .await?;
+
         // ENHANCED: Better nonce handling with validation
         let nonce_u128 = self
             .fetch_tx_nonce_sepolia()
@@ -493,16 +618,18 @@ impl ChainService {
             .to_u64()
             .ok_or_else(|| format!("Nonce too large for u64: {}", nonce_u128.0))?;
 
-        ic_cdk::println!("✅ Sepolia nonce retrieved: {}", nonce_u64);
+        log!(Debug, Release, "Sepolia nonce retrieved: {}", nonce_u64);
 
         // ENHANCED: Improved gas estimation with safety margins
         let (base_gas_limit, max_fee_per_gas, max_priority_fee_per_gas) =
-            estimate_transaction_fees().await;
+            estimate_transaction_fees();
 
         // Add safety margin to gas limit for complex NFT minting
-        let gas_limit = std::cmp::max(base_gas_limit, 500_000u128); // Minimum 500k gas
+        let gas_limit = std::cmp::max(base_gas_limit, RELEASE_GAS_LIMIT as u128); // Minimum 500k gas
 
-        ic_cdk::println!(
+        log!(
+            Debug,
+            Release,
             "Gas settings - Limit: {} (base: {}), MaxFee: {} Gwei, MaxPriority: {} Gwei",
             gas_limit,
             base_gas_limit,
@@ -518,7 +645,12 @@ impl ChainService {
             )
         })?;
 
-        ic_cdk::println!("Target contract: {}", NFT_SEPOLIA_ADDRESS_HEX);
+        log!(
+            Debug,
+            Release,
+            "Target contract: {}",
+            NFT_SEPOLIA_ADDRESS_HEX
+        );
 
         // Build EIP-1559 request
         let tx = Eip1559TransactionRequest {
@@ -538,15 +670,25 @@ impl ChainService {
         let mut unsigned_rlp = tx.rlp().to_vec();
         unsigned_rlp.insert(0, EIP1559_TX_ID);
 
-        ic_cdk::println!("Unsigned typed tx prepared (len={})", unsigned_rlp.len());
+        log!(
+            Debug,
+            Release,
+            "Unsigned typed tx prepared (len={})",
+            unsigned_rlp.len()
+        );
 
         // ENHANCED: Log transaction hash for debugging
         let signing_hash = ethers_core::utils::keccak256(&unsigned_rlp);
-        ic_cdk::println!("Transaction signing hash: 0x{}", hex::encode(&signing_hash));
+        log!(
+            Debug,
+            Release,
+            "Transaction signing hash: 0x{}",
+            hex::encode(&signing_hash)
+        );
 
         // ENHANCED: Better signature handling with validation
         let (public_key_bytes, signature) = self
-            .pubkey_and_signature(signing_hash.to_vec())
+            .pubkey_and_signature(SEPOLIA_CHAIN_ID, signing_hash.to_vec())
             .await
             .map_err(|e| format!("Failed to generate pubkey/signature: {:?}", e))?;
 
@@ -557,7 +699,7 @@ impl ChainService {
             ));
         }
 
-        ic_cdk::println!("✅ Signature generated successfully");
+        log!(Debug, Release, "Signature generated successfully");
 
         // Compute recovery id with validation
         let recovery_id = y_parity(&signing_hash, &signature.signature, &public_key_bytes);
@@ -568,7 +710,7 @@ impl ChainService {
             ));
         }
 
-        ic_cdk::println!("Recovery id (y parity): {}", recovery_id);
+        log!(Debug, Release, "Recovery id (y parity): {}", recovery_id);
 
         // Build ethers Signature
         let sig = ethers_core::types::Signature {
@@ -583,9 +725,16 @@ impl ChainService {
 
         let raw_tx_hex = format!("0x{}", hex::encode(&signed_rlp));
 
-        ic_cdk::println!("✅ Transaction signed successfully");
-        ic_cdk::println!("Signed tx length: {} bytes", signed_rlp.len());
-        ic_cdk::println!(
+        log!(Debug, Release, "Transaction signed successfully");
+        log!(
+            Debug,
+            Release,
+            "Signed tx length: {} bytes",
+            signed_rlp.len()
+        );
+        log!(
+            Debug,
+            Release,
             "Raw transaction (first 100 chars): {}...",
             if raw_tx_hex.len() > 100 {
                 &raw_tx_hex[..100]
@@ -597,7 +746,7 @@ impl ChainService {
         // ENHANCED: Send with better error context
         let cycles_to_pay: u128 = 600_000_000_000u128;
 
-        ic_cdk::println!("Sending transaction to Sepolia network...");
+        log!(Debug, Release, "Sending transaction to Sepolia network...");
 
         let (send_result,) = call_with_payment128::<
             (
@@ -632,7 +781,7 @@ impl ChainService {
         .await
         .map_err(|e| format!("RPC call failed: {:?}", e))?;
 
-        ic_cdk::println!("✅ RPC call completed, processing response...");
+        log!(Debug, Release, "RPC call completed, processing response...");
 
         // ENHANCED: Better response handling with detailed error messages
         match send_result {
@@ -646,9 +795,11 @@ impl ChainService {
                                 *sepolia_tx_hash = Some(tx_hash.clone());
                             }
 
-                            ic_cdk::println!("🎉 Sepolia transaction sent successfully!");
-                            ic_cdk::println!("Transaction hash: {}", tx_hash);
-                            ic_cdk::println!(
+                            log!(Info, Release, "Sepolia transaction sent successfully!");
+                            log!(Debug, Release, "Transaction hash: {}", tx_hash);
+                            log!(
+                                Debug,
+                                Release,
                                 "Sepolia explorer: https://sepolia.etherscan.io/tx/{}",
                                 tx_hash
                             );
@@ -657,20 +808,20 @@ impl ChainService {
                         } else {
                             let error_msg =
                                 "Transaction was sent but no hash was returned".to_string();
-                            ic_cdk::println!("❌ Error: {}", error_msg);
+                            log!(Error, Release, "Error: {}", error_msg);
                             Err(error_msg)
                         }
                     }
                     SendRawTransactionStatus::NonceTooLow => {
                         let error_msg =
                             format!("Nonce too low (used: {}). Try increasing nonce.", nonce_u64);
-                        ic_cdk::println!("❌ Error: {}", error_msg);
+                        log!(Error, Release, "Error: {}", error_msg);
                         Err(error_msg)
                     }
                     SendRawTransactionStatus::NonceTooHigh => {
                         let error_msg =
                             format!("Nonce too high (used: {}). Check current nonce.", nonce_u64);
-                        ic_cdk::println!("❌ Error: {}", error_msg);
+                        log!(Error, Release, "Error: {}", error_msg);
                         Err(error_msg)
                     }
                     SendRawTransactionStatus::InsufficientFunds => {
@@ -680,20 +831,20 @@ impl ChainService {
                             estimated_cost,
                             estimated_cost as f64 / 1e18
                         );
-                        ic_cdk::println!("❌ Error: {}", error_msg);
+                        log!(Error, Release, "Error: {}", error_msg);
                         Err(error_msg)
                     }
                 },
                 SendRawTransactionResult::Err(rpc_error) => {
                     let error_msg = format!("RPC error: {:?}", rpc_error);
-                    ic_cdk::println!("❌ Error: {}", error_msg);
+                    log!(Error, Release, "Error: {}", error_msg);
                     Err(error_msg)
                 }
             },
             MultiSendRawTransactionResult::Inconsistent(responses) => {
                 let error_msg =
                     format!("Inconsistent responses from RPC providers: {:?}", responses);
-                ic_cdk::println!("❌ Error: {}", error_msg);
+                log!(Error, Release, "Error: {}", error_msg);
                 Err(error_msg)
             }
         }
@@ -702,8 +853,13 @@ impl ChainService {
     /// Fetch transaction count (nonce) for Sepolia network
     pub async fn fetch_tx_nonce_sepolia(&self) -> Result<Nat, String> {
         let block_tag = BlockTag::Latest;
-        let (canister_address, _ecdsa_key) = get_network_config();
-        ic_cdk::println!("Sepolia canister_address {}", canister_address);
+        let canister_address = ecdsa_keys::relayer_address(SEPOLIA_CHAIN_ID).await?;
+        log!(
+            Debug,
+            Release,
+            "Sepolia canister_address {}",
+            canister_address
+        );
         let get_transaction_count_args = GetTransactionCountArgs {
             address: canister_address.to_string(),
             block: block_tag,
@@ -768,37 +924,286 @@ impl ChainService {
 
         Ok(transaction_count)
     }
-}
 
-#[update]
-pub async fn generate_key_pair_evm() -> Result<String, String> {
-    let (_, ecdsa_key) = get_network_config();
-
-    let request = EcdsaPublicKeyArgument {
-        key_id: EcdsaKeyId {
-            curve: EcdsaCurve::Secp256k1,
-            name: ecdsa_key.to_string(),
-        },
-        ..Default::default()
-    };
-
-    let (response,) = ecdsa_public_key(request)
+    /// Pending nonce of an arbitrary `address` on `chain_id`
+    pub async fn fetch_nonce(&self, chain_id: u64, address: &str) -> Result<u64, String> {
+        let args = GetTransactionCountArgs {
+            address: address.to_string(),
+            block: BlockTag::Latest,
+        };
+
+        let (transaction_result,) = call_with_payment128::<
+            (
+                RpcServices,
+                Option<crate::evm_rpc_bindings::RpcConfig>,
+                GetTransactionCountArgs,
+            ),
+            (MultiGetTransactionCountResult,),
+        >(
+            self.evm_rpc.0,
+            "eth_getTransactionCount",
+            (rpc_services(chain_id)?, None, args.clone()),
+            200_000_000_000u128,
+        )
+        .await
+        .map_err(|e| format!("Failed to get transaction count: {:?}", e))?;
+
+        let count = match transaction_result {
+            MultiGetTransactionCountResult::Consistent(GetTransactionCountResult::Ok(count)) => {
+                count
+            }
+            MultiGetTransactionCountResult::Consistent(GetTransactionCountResult::Err(error)) => {
+                return Err(format!(
+                    "failed to get transaction count for {:?}, error: {:?}",
+                    args, error
+                ));
+            }
+            MultiGetTransactionCountResult::Inconsistent(results) => {
+                return Err(format!(
+                    "inconsistent results when retrieving transaction count for {:?}: {:?}",
+                    args, results
+                ));
+            }
+        };
+
+        count
+            .0
+            .to_u64()
+            .ok_or_else(|| format!("Nonce too large for u64: {}", count.0))
+    }
+
+    /// Sign a contract call to `to` with the key for `purpose` and broadcast it on `chain_id`
+    pub async fn sign_and_send(
+        &self,
+        chain_id: u64,
+        purpose: &KeyPurpose,
+        to: &str,
+        call_data: Vec<u8>,
+        gas_limit: u64,
+    ) -> Result<String, String> {
+        let signer = ecdsa_keys::derived_key(purpose).await?;
+        self.simulate_call(chain_id, &signer.address, to, &call_data)
+            .await?;
+        let nonce = self.fetch_nonce(chain_id, &signer.address).await?;
+        let (_, max_fee_per_gas, max_priority_fee_per_gas) = estimate_transaction_fees();
+        let to = H160::from_str(to).map_err(|e| format!("Invalid address '{}': {:?}", to, e))?;
+
+        let tx = Eip1559TransactionRequest {
+            from: None,
+            to: Some(to.into()),
+            nonce: Some(U256::from(nonce)),
+            gas: Some(U256::from(gas_limit)),
+            max_fee_per_gas: Some(U256::from(max_fee_per_gas)),
+            max_priority_fee_per_gas: Some(U256::from(max_priority_fee_per_gas)),
+            value: Some(U256::zero()),
+            data: Some(Bytes::from(call_data)),
+            access_list: vec![].into(),
+            chain_id: Some(U64::from(chain_id)),
+        };
+
+        let mut unsigned_tx = tx.rlp().to_vec();
+        unsigned_tx.insert(0, EIP1559_TX_ID);
+        let signing_hash = ethers_core::utils::keccak256(&unsigned_tx);
+
+        let (key, signature) = ecdsa_keys::sign(purpose, signing_hash.to_vec()).await?;
+        let sig = ethers_core::types::Signature {
+            r: U256::from_big_endian(&signature[0..32]),
+            s: U256::from_big_endian(&signature[32..64]),
+            v: y_parity(&signing_hash, &signature, &key.public_key),
+        };
+
+        let mut signed_tx = tx.rlp_signed(&sig).to_vec();
+        signed_tx.insert(0, EIP1559_TX_ID);
+        self.send_raw_transaction(chain_id, format!("0x{}", hex::encode(&signed_tx)))
+            .await
+    }
+
+    /// Raw JSON-RPC call through the EVM RPC canister's generic `request`, returning `result`
+    pub async fn json_rpc(
+        &self,
+        chain_id: u64,
+        method: &str,
+        params: serde_json::Value,
+    ) -> Result<serde_json::Value, String> {
+        match self.json_rpc_response(chain_id, method, params).await? {
+            Ok(result) => Ok(result),
+            Err(error) => Err(format!("{} returned error: {}", method, error)),
+        }
+    }
+
+    /// Like `json_rpc`, but hands back the node's `error` object (e.g. revert data) untouched
+    pub async fn json_rpc_response(
+        &self,
+        chain_id: u64,
+        method: &str,
+        params: serde_json::Value,
+    ) -> Result<Result<serde_json::Value, serde_json::Value>, String> {
+        let payload = serde_json::json!({
+            "jsonrpc": "2.0",
+            "id": 1,
+            "method": method,
+            "params": params,
+        })
+        .to_string();
+
+        let max_response_bytes = get_json_rpc_max_response_bytes();
+        let (result,) = call_with_payment128::<(RpcService, String, u64), (RequestResult,)>(
+            self.evm_rpc.0,
+            "request",
+            (rpc_service(chain_id)?, payload, max_response_bytes),
+            JSON_RPC_CYCLES + JSON_RPC_CYCLES_PER_RESPONSE_BYTE * max_response_bytes as u128,
+        )
         .await
-        .map_err(|e| format!("ecdsa_public_key failed {:?}", e))?;
+        .map_err(|e| format!("{} call failed: {:?}", method, e))?;
 
-    ic_cdk::println!("ECDSA public key response: {:?}", response);
+        let body = match result {
+            RequestResult::Ok(body) => body,
+            RequestResult::Err(e) => return Err(format!("{} RPC error: {:?}", method, e)),
+        };
+        let mut response: serde_json::Value = serde_json::from_str(&body)
+            .map_err(|e| format!("Invalid {} response '{}': {}", method, body, e))?;
+        if let Some(error) = response.get_mut("error") {
+            return Ok(Err(error.take()));
+        }
+        response
+            .get_mut("result")
+            .map(|result| Ok(result.take()))
+            .ok_or_else(|| format!("{} response has no result: {}", method, body))
+    }
 
-    let public_key_hex = hex::encode(&response.public_key);
+    async fn send_raw_transaction(
+        &self,
+        chain_id: u64,
+        raw_tx_hex: String,
+    ) -> Result<String, String> {
+        let (send_result,) = call_with_payment128::<
+            (
+                RpcServices,
+                Option<crate::evm_rpc_bindings::RpcConfig>,
+                String,
+            ),
+            (MultiSendRawTransactionResult,),
+        >(
+            self.evm_rpc.0,
+            "eth_sendRawTransaction",
+            (rpc_services(chain_id)?, None, raw_tx_hex),
+            600_000_000_000u128,
+        )
+        .await
+        .map_err(|e| format!("Failed to send raw transaction: {:?}", e))?;
 
-    ic_cdk::println!("Derived public key hex: {}", public_key_hex);
+        match send_result {
+            MultiSendRawTransactionResult::Consistent(SendRawTransactionResult::Ok(status)) => {
+                match status {
+                    SendRawTransactionStatus::Ok(Some(tx_hash)) => Ok(tx_hash),
+                    SendRawTransactionStatus::Ok(None) => {
+                        Err("Error: transaction hash not found in the response".to_string())
+                    }
+                    SendRawTransactionStatus::NonceTooLow => {
+                        Err("Error: nonce too low".to_string())
+                    }
+                    SendRawTransactionStatus::NonceTooHigh => {
+                        Err("Error: nonce too high".to_string())
+                    }
+                    SendRawTransactionStatus::InsufficientFunds => {
+                        Err("Error: insufficient funds".to_string())
+                    }
+                }
+            }
+            MultiSendRawTransactionResult::Consistent(SendRawTransactionResult::Err(rpc_error)) => {
+                Err(format!("RPC error sending transaction: {:?}", rpc_error))
+            }
+            MultiSendRawTransactionResult::Inconsistent(_) => {
+                Err("Inconsistent send raw transaction results".to_string())
+            }
+        }
+    }
+}
+
+fn rpc_urls(chain_id: u64) -> Result<&'static [&'static str], String> {
+    match chain_id {
+        HOLESKY_CHAIN_ID => Ok(&[
+            "https://eth-holesky.g.alchemy.com/v2/OLoCeG14N_MLxJ5tFvD-k67DHU4Xc-ig",
+            "https://holesky.drpc.org",
+        ]),
+        SEPOLIA_CHAIN_ID => Ok(&[
+            "https://ethereum-sepolia-rpc.publicnode.com",
+            "https://sepolia.infura.io/v3/5149c676c7f9427eb71d094efdb9788b",
+        ]),
+        other => Err(format!("No RPC providers configured for chain {}", other)),
+    }
+}
 
-    let ethereum_address = pubkey_bytes_to_address(&response.public_key);
+/// RPC providers used for `chain_id`
+pub fn rpc_services(chain_id: u64) -> Result<RpcServices, String> {
+    Ok(RpcServices::Custom {
+        chainId: chain_id,
+        services: rpc_urls(chain_id)?
+            .iter()
+            .map(|url| RpcApi {
+                url: url.to_string(),
+                headers: None,
+            })
+            .collect(),
+    })
+}
 
-    Ok(ethereum_address)
+/// Primary provider for `chain_id`, used for raw JSON-RPC requests
+pub fn rpc_service(chain_id: u64) -> Result<RpcService, String> {
+    Ok(RpcService::Custom(RpcApi {
+        url: rpc_urls(chain_id)?[0].to_string(),
+        headers: None,
+    }))
+}
+
+/// Response size limit for raw JSON-RPC calls (eth_call, eth_gasPrice, ...)
+#[update]
+pub fn set_json_rpc_max_response_bytes(max_response_bytes: u64) -> Result<(), String> {
+    ensure_admin()?;
+    if max_response_bytes == 0 || max_response_bytes > MAX_JSON_RPC_RESPONSE_BYTES {
+        return Err(format!(
+            "max_response_bytes must be between 1 and {}",
+            MAX_JSON_RPC_RESPONSE_BYTES
+        ));
+    }
+    JSON_RPC_MAX_RESPONSE_BYTES
+        .with(|limit| limit.borrow_mut().set(max_response_bytes))
+        .map_err(|e| format!("Failed to store JSON-RPC response limit: {:?}", e))?;
+    Ok(())
+}
+
+#[query]
+pub fn get_json_rpc_max_response_bytes() -> u64 {
+    JSON_RPC_MAX_RESPONSE_BYTES.with(|limit| *limit.borrow().get())
+}
+
+/// NFT contract deployed on `chain_id`
+pub fn nft_contract_address(chain_id: u64) -> Result<&'static str, String> {
+    match chain_id {
+        HOLESKY_CHAIN_ID => Ok(NFT_CONTRACT_ADDRESS_HEX),
+        SEPOLIA_CHAIN_ID => Ok(NFT_SEPOLIA_ADDRESS_HEX),
+        other => Err(format!("No NFT contract configured for chain {}", other)),
+    }
+}
+
+/// Derive (or read from the cache) the active relayer key for `chain_id` and return its EVM address
+#[update]
+pub async fn generate_key_pair_evm(chain_id: u64) -> Result<String, String> {
+    ensure_admin()?;
+    let key = ecdsa_keys::derived_key(&ecdsa_keys::active_relayer(chain_id)).await?;
+
+    log!(
+        Debug,
+        Release,
+        "Derived public key hex: {}",
+        hex::encode(&key.public_key)
+    );
+
+    Ok(key.address)
 }
 /// Derive Ethereum address from uncompressed secp256k1 public key bytes (65 bytes, 0x04 prefix)
 
-fn pubkey_bytes_to_address(pubkey_bytes: &[u8]) -> String {
+pub fn pubkey_bytes_to_address(pubkey_bytes: &[u8]) -> String {
     use k256::elliptic_curve::sec1::ToEncodedPoint;
     use sha3::Keccak256;
 
@@ -814,7 +1219,7 @@ fn pubkey_bytes_to_address(pubkey_bytes: &[u8]) -> String {
     ethers_core::utils::to_checksum(&address.into(), None)
 }
 
-pub async fn estimate_transaction_fees() -> (u128, u128, u128) {
+pub fn estimate_transaction_fees() -> (u128, u128, u128) {
     const GAS_LIMIT: u128 = 300_000; // 300,000 gas units
     const MAX_FEE_PER_GAS: u128 = 25_000_000_000; // 25 Gwei
     const MAX_PRIORITY_FEE_PER_GAS: u128 = 2_000_000_000; // 2 Gwei
@@ -843,43 +1248,10 @@ fn y_parity(prehash: &[u8], sig: &[u8], pubkey: &[u8]) -> u64 {
     )
 }
 
-const NETWORK: &str = "local";
-pub fn get_network_config() -> (&'static str, &'static str) {
-    match NETWORK {
-        "local" => (
-            "0x6aa3a45c64B45BF5eb031A4Ae18059e36e674E9c", // address_local
-            "dfx_test_key",                               // ecdsa_key_local
-        ),
-        "mainnet" => (
-            "0xCe18A8128Bd68395d8C33E1B0279e54757448FAD", // address_main
-            "test_key_1",                                 // ecdsa_key_main
-        ),
-        _ => panic!("Unknown network!"),
-    }
-}
-
-pub async fn get_ecdsa_public_key() -> Result<EcdsaPublicKeyResponse, String> {
-    let res = ecdsa_public_key(EcdsaPublicKeyArgument {
-        key_id: key_id(),
-        ..Default::default()
-    })
-    .await
-    .map_err(|e| format!("Failed to get public key: {:?}", e))?;
-
-    Ok(res.0)
-}
-
-fn key_id() -> EcdsaKeyId {
-    EcdsaKeyId {
-        curve: EcdsaCurve::Secp256k1,
-        name: "dfx_test_key".to_string(), // use EcdsaKeyId::default() for mainnet use test_key_1 for testnet and test_key_1 for local deployment
-    }
-}
-
 pub async fn holesky_txn() -> Result<String, String> {
     let hash = HOLESKY_TX_HASH.read().unwrap();
     if let Some(ref txn) = *hash {
-        ic_cdk::println!("Returning latest Holesky tx hash: {}", txn);
+        log!(Debug, Release, "Returning latest Holesky tx hash: {}", txn);
         Ok(txn.clone())
     } else {
         Err("No transaction hash stored.".to_string())
@@ -890,7 +1262,7 @@ pub async fn holesky_txn() -> Result<String, String> {
 pub async fn sepolia_txn() -> Result<String, String> {
     let hash = SEPOLIA_TX_HASH.read().unwrap();
     if let Some(ref txn) = *hash {
-        ic_cdk::println!("Returning latest Sepolia tx hash: {}", txn);
+        log!(Debug, Release, "Returning latest Sepolia tx hash: {}", txn);
         Ok(txn.clone())
     } else {
         Err("No transaction hash stored.".to_string())
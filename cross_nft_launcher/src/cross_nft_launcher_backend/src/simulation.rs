@@ -0,0 +1,286 @@
+use ethabi::{ParamType, Token};
+use sha3::{Digest, Keccak256};
+
+use crate::evm_nft_indexer::ChainService;
+use crate::log;
+
+const ERROR_STRING_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0]; // Error(string)
+const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71]; // Panic(uint256)
+
+// JSON-RPC error code nodes use for a reverted eth_call
+const EXECUTION_REVERTED: i64 = 3;
+
+// Custom errors the NFT contract and its OpenZeppelin bases can raise
+const KNOWN_CUSTOM_ERRORS: &[(&str, &[ParamType])] = &[
+    ("OwnableUnauthorizedAccount", &[ParamType::Address]),
+    ("OwnableInvalidOwner", &[ParamType::Address]),
+    ("ERC721InvalidOwner", &[ParamType::Address]),
+    ("ERC721NonexistentToken", &[ParamType::Uint(256)]),
+    (
+        "ERC721IncorrectOwner",
+        &[ParamType::Address, ParamType::Uint(256), ParamType::Address],
+    ),
+    ("ERC721InvalidSender", &[ParamType::Address]),
+    ("ERC721InvalidReceiver", &[ParamType::Address]),
+];
+
+fn selector(name: &str, params: &[ParamType]) -> [u8; 4] {
+    let signature = format!(
+        "{}({})",
+        name,
+        params
+            .iter()
+            .map(|p| p.to_string())
+            .collect::<Vec<_>>()
+            .join(",")
+    );
+    let hash = Keccak256::digest(signature.as_bytes());
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+fn panic_reason(code: u64) -> &'static str {
+    match code {
+        0x01 => "assertion failed",
+        0x11 => "arithmetic overflow or underflow",
+        0x12 => "division or modulo by zero",
+        0x21 => "invalid enum value",
+        0x22 => "invalid storage byte array",
+        0x31 => "pop on empty array",
+        0x32 => "array index out of bounds",
+        0x41 => "out of memory",
+        0x51 => "call to uninitialized function",
+        _ => "unknown panic code",
+    }
+}
+
+/// Human-readable reason for ABI-encoded revert data
+pub fn decode_revert(data: &[u8]) -> String {
+    if data.len() < 4 {
+        return "execution reverted without a reason".to_string();
+    }
+    let (sel, args) = data.split_at(4);
+
+    if sel == ERROR_STRING_SELECTOR {
+        if let Ok(tokens) = ethabi::decode(&[ParamType::String], args) {
+            if let Some(Token::String(reason)) = tokens.into_iter().next() {
+                return reason;
+            }
+        }
+    }
+
+    if sel == PANIC_SELECTOR {
+        if let Ok(tokens) = ethabi::decode(&[ParamType::Uint(256)], args) {
+            if let Some(Token::Uint(code)) = tokens.into_iter().next() {
+                let code = code.low_u64();
+                return format!("panic 0x{:02x}: {}", code, panic_reason(code));
+            }
+        }
+    }
+
+    for (name, params) in KNOWN_CUSTOM_ERRORS {
+        if sel == selector(name, params) {
+            return match ethabi::decode(params, args) {
+                Ok(tokens) => format!(
+                    "{}({})",
+                    name,
+                    tokens
+                        .iter()
+                        .map(|t| t.to_string())
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                ),
+                Err(_) => name.to_string(),
+            };
+        }
+    }
+
+    format!(
+        "custom error 0x{} (data 0x{})",
+        hex::encode(sel),
+        hex::encode(args)
+    )
+}
+
+/// Revert data from a JSON-RPC error; providers put it at `data` or `data.data`
+fn revert_data(error: &serde_json::Value) -> Option<Vec<u8>> {
+    let data = error.get("data")?;
+    let hex_data = data
+        .as_str()
+        .or_else(|| data.get("data").and_then(|d| d.as_str()))?;
+    hex::decode(hex_data.trim_start_matches("0x")).ok()
+}
+
+impl ChainService {
+    /// Run `call_data` through eth_call from `from` and fail with the decoded reason if it would revert.
+    /// Transport failures and non-revert node errors are only logged, so a flaky provider does
+    /// not block releases.
+    pub async fn simulate_call(
+        &self,
+        chain_id: u64,
+        from: &str,
+        to: &str,
+        call_data: &[u8],
+    ) -> Result<(), String> {
+        let params = serde_json::json!([
+            {
+                "from": from,
+                "to": to,
+                "data": format!("0x{}", hex::encode(call_data)),
+            },
+            "latest"
+        ]);
+
+        let error = match self.json_rpc_response(chain_id, "eth_call", params).await {
+            Ok(Ok(_)) => return Ok(()),
+            Ok(Err(error)) => error,
+            Err(e) => {
+                log!(
+                    Warn,
+                    Release,
+                    "Skipping eth_call simulation on chain {}: {}",
+                    chain_id,
+                    e
+                );
+                return Ok(());
+            }
+        };
+
+        // Only code 3 or an error carrying revert data is a revert; anything else (rate
+        // limits, unknown methods, node errors) is treated like a transport failure
+        let data = revert_data(&error);
+        if data.is_none() && error.get("code").and_then(|c| c.as_i64()) != Some(EXECUTION_REVERTED)
+        {
+            log!(
+                Warn,
+                Release,
+                "Skipping eth_call simulation on chain {}: {}",
+                chain_id,
+                error
+            );
+            return Ok(());
+        }
+        let reason = match data {
+            Some(data) => decode_revert(&data),
+            None => error
+                .get("message")
+                .and_then(|m| m.as_str())
+                .unwrap_or("execution reverted")
+                .to_string(),
+        };
+        log!(
+            Warn,
+            Release,
+            "eth_call on chain {} reverted: {}",
+            chain_id,
+            reason
+        );
+        Err(format!("Transaction would revert: {}", reason))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethabi::ethereum_types::{H160, U256};
+
+    fn revert(selector: [u8; 4], args: &[Token]) -> Vec<u8> {
+        [selector.to_vec(), ethabi::encode(args)].concat()
+    }
+
+    #[test]
+    fn decodes_error_string() {
+        let data = revert(
+            ERROR_STRING_SELECTOR,
+            &[Token::String("Only the relayer can mint".to_string())],
+        );
+        assert_eq!(decode_revert(&data), "Only the relayer can mint");
+    }
+
+    #[test]
+    fn decodes_panic_codes() {
+        let data = revert(PANIC_SELECTOR, &[Token::Uint(U256::from(0x11))]);
+        assert_eq!(
+            decode_revert(&data),
+            "panic 0x11: arithmetic overflow or underflow"
+        );
+        let data = revert(PANIC_SELECTOR, &[Token::Uint(U256::from(0x99))]);
+        assert_eq!(decode_revert(&data), "panic 0x99: unknown panic code");
+    }
+
+    #[test]
+    fn decodes_known_custom_errors() {
+        // Selectors as published in the OpenZeppelin 5 error interfaces
+        let data = revert([0x7e, 0x27, 0x32, 0x89], &[Token::Uint(U256::from(42))]);
+        assert_eq!(decode_revert(&data), "ERC721NonexistentToken(2a)");
+
+        let account = H160::repeat_byte(0xab);
+        let data = revert([0x11, 0x8c, 0xda, 0xa7], &[Token::Address(account)]);
+        assert_eq!(
+            decode_revert(&data),
+            format!("OwnableUnauthorizedAccount({:x})", account)
+        );
+
+        for (name, params) in KNOWN_CUSTOM_ERRORS {
+            let args: Vec<Token> = params
+                .iter()
+                .map(|param| match param {
+                    ParamType::Address => Token::Address(account),
+                    _ => Token::Uint(U256::from(7)),
+                })
+                .collect();
+            let decoded = decode_revert(&revert(selector(name, params), &args));
+            assert!(decoded.starts_with(&format!("{}(", name)), "{}", decoded);
+        }
+    }
+
+    #[test]
+    fn truncated_data_falls_back_without_panicking() {
+        let data = revert(
+            ERROR_STRING_SELECTOR,
+            &[Token::String("reason".to_string())],
+        );
+        assert_eq!(
+            decode_revert(&data[..40]),
+            format!(
+                "custom error 0x08c379a0 (data 0x{})",
+                hex::encode(&data[4..40])
+            )
+        );
+
+        let data = revert(PANIC_SELECTOR, &[Token::Uint(U256::from(1))]);
+        assert!(decode_revert(&data[..20]).starts_with("custom error 0x4e487b71"));
+
+        // A known custom error with cut-off arguments is still named
+        assert_eq!(
+            decode_revert(&[0x7e, 0x27, 0x32, 0x89, 0x00, 0x01]),
+            "ERC721NonexistentToken"
+        );
+    }
+
+    #[test]
+    fn empty_and_short_data_has_no_reason() {
+        assert_eq!(decode_revert(&[]), "execution reverted without a reason");
+        assert_eq!(
+            decode_revert(&[0x08, 0xc3, 0x79]),
+            "execution reverted without a reason"
+        );
+    }
+
+    #[test]
+    fn unknown_selectors_are_shown_raw() {
+        assert_eq!(
+            decode_revert(&[0xde, 0xad, 0xbe, 0xef, 0x01]),
+            "custom error 0xdeadbeef (data 0x01)"
+        );
+    }
+
+    #[test]
+    fn revert_data_is_read_from_either_location() {
+        let flat = serde_json::json!({ "code": 3, "data": "0xdeadbeef" });
+        assert_eq!(revert_data(&flat), Some(vec![0xde, 0xad, 0xbe, 0xef]));
+        let nested = serde_json::json!({ "code": -32000, "data": { "data": "0x01" } });
+        assert_eq!(revert_data(&nested), Some(vec![0x01]));
+        let none = serde_json::json!({ "code": -32005, "message": "rate limited" });
+        assert_eq!(revert_data(&none), None);
+    }
+}
@@ -0,0 +1,318 @@
+use candid::{CandidType, Principal};
+use ethers_core::types::Address;
+use ethers_core::utils::to_checksum;
+use ic_cdk::api::management_canister::main::raw_rand;
+use ic_cdk::api::{id, time};
+use ic_cdk::{query, update};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use crate::certification::{self, leb128, sha256};
+use crate::ensure_admin;
+use crate::evm_link::{normalize_address, recover_eip191_signer};
+use crate::evm_nft_indexer::SEPOLIA_CHAIN_ID;
+use crate::log;
+
+const NANOS_PER_SEC: u64 = 1_000_000_000;
+// Unexpired prepared logins kept at once; bounds the memory anonymous callers can claim
+const MAX_PREPARED_LOGINS: usize = 10_000;
+// DER Ed25519 and ECDSA session keys are well under this
+const MAX_SESSION_KEY_BYTES: usize = 256;
+// DER prefix of a canister signature public key: SEQUENCE { SEQUENCE { OID 1.3.6.1.4.1.56387.1.2 } }
+const CANISTER_SIG_OID: [u8; 14] = [
+    0x30, 0x0c, 0x06, 0x0a, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x83, 0xb8, 0x43, 0x01, 0x02,
+];
+const DELEGATION_DOMAIN: &[u8] = b"\x1Aic-request-auth-delegation";
+
+/// EIP-4361 fields of the login message; `domain` and `uri` must match the frontend
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct SiweConfig {
+    pub domain: String,
+    pub uri: String,
+    pub statement: String,
+    pub chain_id: u64,
+    pub message_ttl_secs: u64,
+    pub session_ttl_secs: u64,
+}
+
+impl Default for SiweConfig {
+    fn default() -> Self {
+        SiweConfig {
+            domain: "q53ev-2iaaa-aaaan-qz6jq-cai.icp0.io".to_string(),
+            uri: "https://q53ev-2iaaa-aaaan-qz6jq-cai.icp0.io".to_string(),
+            statement: "Sign in to Cross NFT Launcher with your Ethereum account.".to_string(),
+            chain_id: SEPOLIA_CHAIN_ID,
+            message_ttl_secs: 5 * 60,
+            session_ttl_secs: 8 * 3_600,
+        }
+    }
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct LoginDetails {
+    pub expiration: u64,
+    pub user_canister_pubkey: Vec<u8>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct Delegation {
+    pub pubkey: Vec<u8>,
+    pub expiration: u64,
+    pub targets: Option<Vec<Principal>>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct SignedDelegation {
+    pub delegation: Delegation,
+    pub signature: Vec<u8>,
+}
+
+struct PreparedLogin {
+    message: String,
+    nonce: String,
+    session_key: Vec<u8>,
+    expires_at: u64,
+}
+
+thread_local! {
+    static SIWE_CONFIG: RefCell<SiweConfig> = RefCell::new(SiweConfig::default());
+
+    // Lowercased address -> last message issued by siwe_prepare_login
+    static PREPARED_LOGINS: RefCell<HashMap<String, PreparedLogin>> = RefCell::new(HashMap::new());
+}
+
+/// RFC 3339 UTC timestamp, as EIP-4361 requires
+fn rfc3339(nanos: u64) -> String {
+    let secs = nanos / NANOS_PER_SEC;
+    let (days, rem) = ((secs / 86_400) as i64, secs % 86_400);
+    // Civil date from days since 1970-01-01 (Howard Hinnant's algorithm)
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3_600,
+        rem % 3_600 / 60,
+        rem % 60
+    )
+}
+
+/// EIP-4361 message; the session key is listed as a resource so the signature binds the
+/// delegation to it
+fn siwe_message(
+    config: &SiweConfig,
+    address: &str,
+    session_key: &[u8],
+    nonce: &str,
+    now: u64,
+    expires_at: u64,
+) -> Result<String, String> {
+    let checksummed = to_checksum(
+        &Address::from_str(address.trim_start_matches("0x"))
+            .map_err(|e| format!("Invalid EVM address: {}", e))?,
+        None,
+    );
+    Ok(format!(
+        "{domain} wants you to sign in with your Ethereum account:\n{address}\n\n{statement}\n\nURI: {uri}\nVersion: 1\nChain ID: {chain_id}\nNonce: {nonce}\nIssued At: {issued_at}\nExpiration Time: {expiration}\nResources:\n- urn:ic:session-key:{session_key}",
+        domain = config.domain,
+        address = checksummed,
+        statement = config.statement,
+        uri = config.uri,
+        chain_id = config.chain_id,
+        nonce = nonce,
+        issued_at = rfc3339(now),
+        expiration = rfc3339(expires_at),
+        session_key = hex::encode(session_key),
+    ))
+}
+
+/// Canister signature seed for an address; stable, so the derived principal is too
+fn seed(address: &str) -> Result<[u8; 32], String> {
+    let address = normalize_address(address)?;
+    let bytes = hex::decode(&address[2..]).map_err(|e| e.to_string())?;
+    let mut input = b"\x07ic-siwe".to_vec();
+    input.extend_from_slice(&bytes);
+    Ok(sha256(&input))
+}
+
+/// DER-encoded canister signature public key for `seed`; every length fits DER's short form
+fn canister_sig_public_key(seed: &[u8; 32]) -> Vec<u8> {
+    let canister_id = id();
+    let canister_id = canister_id.as_slice();
+    let mut bit_string = vec![0x00, canister_id.len() as u8];
+    bit_string.extend_from_slice(canister_id);
+    bit_string.extend_from_slice(seed);
+
+    let mut body = CANISTER_SIG_OID.to_vec();
+    body.push(0x03);
+    body.push(bit_string.len() as u8);
+    body.extend_from_slice(&bit_string);
+
+    let mut der = vec![0x30, body.len() as u8];
+    der.extend_from_slice(&body);
+    der
+}
+
+/// Principal a MetaMask user gets after signing in with `address`
+pub fn principal_for_address(address: &str) -> Result<Principal, String> {
+    Ok(Principal::self_authenticating(canister_sig_public_key(
+        &seed(address)?,
+    )))
+}
+
+/// Representation-independent hash of a delegation without targets, prefixed with its domain
+fn delegation_message(session_key: &[u8], expiration: u64) -> Vec<u8> {
+    let mut fields: Vec<Vec<u8>> = [
+        (&b"expiration"[..], leb128(expiration)),
+        (&b"pubkey"[..], session_key.to_vec()),
+    ]
+    .iter()
+    .map(|(key, value)| [sha256(key), sha256(value)].concat())
+    .collect();
+    fields.sort();
+
+    let mut message = DELEGATION_DOMAIN.to_vec();
+    message.extend_from_slice(&sha256(&fields.concat()));
+    message
+}
+
+/// Issue the EIP-4361 message `address` must sign to log in with `session_key`
+#[update]
+pub async fn siwe_prepare_login(address: String, session_key: Vec<u8>) -> Result<String, String> {
+    let address = normalize_address(&address)?;
+    if session_key.is_empty() || session_key.len() > MAX_SESSION_KEY_BYTES {
+        return Err(format!(
+            "Session key must be a DER public key of 1 to {} bytes",
+            MAX_SESSION_KEY_BYTES
+        ));
+    }
+    let (random,) = raw_rand()
+        .await
+        .map_err(|(code, msg)| format!("raw_rand failed: {:?} {}", code, msg))?;
+    let nonce = hex::encode(&random[..16]);
+
+    let config = SIWE_CONFIG.with(|c| c.borrow().clone());
+    let now = time();
+    let expires_at = now + config.message_ttl_secs * NANOS_PER_SEC;
+    let message = siwe_message(&config, &address, &session_key, &nonce, now, expires_at)?;
+
+    PREPARED_LOGINS.with(|logins| {
+        let mut logins = logins.borrow_mut();
+        logins.retain(|_, login| login.expires_at > now);
+        if logins.len() >= MAX_PREPARED_LOGINS && !logins.contains_key(&address) {
+            return Err("Too many pending logins, try again later".to_string());
+        }
+        logins.insert(
+            address,
+            PreparedLogin {
+                message: message.clone(),
+                nonce,
+                session_key,
+                expires_at,
+            },
+        );
+        Ok(())
+    })?;
+    Ok(message)
+}
+
+/// Verify the signed message and certify a delegation to `session_key`
+#[update]
+pub fn siwe_login(
+    signature: String,
+    address: String,
+    session_key: Vec<u8>,
+    nonce: String,
+) -> Result<LoginDetails, String> {
+    let address = normalize_address(&address)?;
+    let now = time();
+    // Only consumed once the signature checks out, so a wrong guess cannot cancel a
+    // login someone else prepared for this address
+    let (message, prepared_session_key) = PREPARED_LOGINS.with(|logins| {
+        let logins = logins.borrow();
+        let login = logins
+            .get(&address)
+            .ok_or_else(|| format!("No login prepared for {}", address))?;
+        if login.nonce != nonce {
+            return Err("Nonce does not match the prepared login".to_string());
+        }
+        if login.expires_at < now {
+            return Err("Login message expired".to_string());
+        }
+        Ok((login.message.clone(), login.session_key.clone()))
+    })?;
+    if prepared_session_key != session_key {
+        return Err("Session key does not match the signed message".to_string());
+    }
+    let signer = recover_eip191_signer(&message, &signature)?;
+    if signer != address {
+        return Err(format!("Signature was made by {}, not {}", signer, address));
+    }
+    PREPARED_LOGINS.with(|logins| logins.borrow_mut().remove(&address));
+
+    let seed = seed(&address)?;
+    let session_ttl = SIWE_CONFIG.with(|c| c.borrow().session_ttl_secs);
+    let expiration = now + session_ttl * NANOS_PER_SEC;
+    certification::add_signature(
+        &seed,
+        &delegation_message(&session_key, expiration),
+        expiration,
+        now,
+    );
+    log!(Info, Auth, "{} signed in with Ethereum", address);
+
+    Ok(LoginDetails {
+        expiration,
+        user_canister_pubkey: canister_sig_public_key(&seed),
+    })
+}
+
+/// Fetch the delegation certified by siwe_login; a query so it can carry a certificate
+#[query]
+pub fn siwe_get_delegation(
+    address: String,
+    session_key: Vec<u8>,
+    expiration: u64,
+) -> Result<SignedDelegation, String> {
+    if expiration < time() {
+        return Err("Delegation expired".to_string());
+    }
+    let seed = seed(&address)?;
+    let signature = certification::signature(&seed, &delegation_message(&session_key, expiration))?;
+    Ok(SignedDelegation {
+        delegation: Delegation {
+            pubkey: session_key,
+            expiration,
+            targets: None,
+        },
+        signature,
+    })
+}
+
+#[query]
+pub fn siwe_get_principal(address: String) -> Result<Principal, String> {
+    principal_for_address(&address)
+}
+
+#[update]
+pub fn set_siwe_config(config: SiweConfig) -> Result<(), String> {
+    ensure_admin()?;
+    SIWE_CONFIG.with(|c| *c.borrow_mut() = config);
+    Ok(())
+}
+
+#[query]
+pub fn get_siwe_config() -> SiweConfig {
+    SIWE_CONFIG.with(|c| c.borrow().clone())
+}
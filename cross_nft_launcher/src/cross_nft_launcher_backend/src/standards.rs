@@ -0,0 +1,64 @@
+use candid::CandidType;
+use ic_cdk::query;
+use serde::{Deserialize, Serialize};
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct SupportedStandard {
+    pub name: String,
+    pub url: String,
+}
+
+// Only standards whose full interface is served by this canister. ICRC-7 and ICRC-37
+// belong here once icrc7_transfer and the ICRC-37 approval endpoints exist.
+const SUPPORTED_STANDARDS: [(&str, &str); 3] = [
+    (
+        "ICRC-10",
+        "https://github.com/dfinity/ICRC/tree/main/ICRCs/ICRC-10",
+    ),
+    (
+        "ICRC-21",
+        "https://github.com/dfinity/wg-identity-authentication/blob/main/topics/ICRC-21/icrc_21_consent_msg.md",
+    ),
+    (
+        "ICRC-28",
+        "https://github.com/dfinity/wg-identity-authentication/blob/main/topics/icrc_28_trusted_origins.md",
+    ),
+];
+
+fn supported_standards() -> Vec<SupportedStandard> {
+    SUPPORTED_STANDARDS
+        .iter()
+        .map(|(name, url)| SupportedStandard {
+            name: name.to_string(),
+            url: url.to_string(),
+        })
+        .collect()
+}
+
+#[query]
+pub fn icrc10_supported_standards() -> Vec<SupportedStandard> {
+    supported_standards()
+}
+
+/// Pre-ICRC-10 clients ask through the ICRC-7 method
+#[query]
+pub fn icrc7_supported_standards() -> Vec<SupportedStandard> {
+    supported_standards()
+}
+
+#[cfg(test)]
+mod tests {
+    use candid_parser::utils::{service_equal, CandidSource};
+    use std::path::Path;
+
+    // The checked-in .did is maintained by hand; fail when the endpoints drift from it
+    #[test]
+    fn candid_interface_matches_did_file() {
+        let generated = crate::__export_service();
+        service_equal(
+            CandidSource::Text(&generated),
+            CandidSource::File(Path::new("cross_nft_launcher_backend.did")),
+        )
+        .unwrap_or_else(|e| panic!("cross_nft_launcher_backend.did is out of date: {:?}", e));
+    }
+}
@@ -0,0 +1,89 @@
+use candid::{CandidType, Nat};
+use ic_cdk::query;
+use ic_stable_structures::StableBTreeMap;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+
+use crate::evm_nft_indexer::nat_to_u256;
+use crate::memory::{
+    candid_storable, get_memory, Memory, EVM_ORIGINS_MEMORY_ID, ICP_TOKENS_BY_ORIGIN_MEMORY_ID,
+};
+
+/// Where a bridged ICP token came from
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize, PartialEq, Eq, PartialOrd, Ord)]
+pub struct EvmOrigin {
+    pub chain_id: u64,
+    pub contract: String,
+    pub evm_token_id: Nat,
+}
+
+candid_storable!(EvmOrigin);
+
+thread_local! {
+    // ICP token id -> EVM origin
+    static EVM_ORIGINS: RefCell<StableBTreeMap<String, EvmOrigin, Memory>> = RefCell::new(
+        StableBTreeMap::init(get_memory(EVM_ORIGINS_MEMORY_ID))
+    );
+
+    // EVM origin -> ICP token id, as decimal text like the TOKENS keys
+    static ICP_TOKENS: RefCell<StableBTreeMap<EvmOrigin, String, Memory>> = RefCell::new(
+        StableBTreeMap::init(get_memory(ICP_TOKENS_BY_ORIGIN_MEMORY_ID))
+    );
+}
+
+fn normalized(chain_id: u64, contract: &str, evm_token_id: Nat) -> EvmOrigin {
+    EvmOrigin {
+        chain_id,
+        contract: contract.to_lowercase(),
+        evm_token_id,
+    }
+}
+
+/// Deterministic ICP token id for an EVM token: the first 16 bytes of
+/// sha256("evm-nft" || chain id || contract || uint256 token id), so equal EVM ids
+/// on different chains or contracts never share an ICP id
+pub fn icp_token_id(chain_id: u64, contract: &str, evm_token_id: &Nat) -> Result<Nat, String> {
+    let contract_bytes = hex::decode(contract.trim_start_matches("0x"))
+        .map_err(|e| format!("Invalid contract address '{}': {}", contract, e))?;
+    let mut evm_id_bytes = [0u8; 32];
+    nat_to_u256(evm_token_id)?.to_big_endian(&mut evm_id_bytes);
+
+    let mut hasher = Sha256::new();
+    hasher.update(b"evm-nft");
+    hasher.update(chain_id.to_be_bytes());
+    hasher.update(&contract_bytes);
+    hasher.update(evm_id_bytes);
+    let digest = hasher.finalize();
+
+    let mut prefix = [0u8; 16];
+    prefix.copy_from_slice(&digest[..16]);
+    Ok(Nat::from(u128::from_be_bytes(prefix)))
+}
+
+/// Remember the EVM origin of a freshly minted ICP token
+pub fn record_origin(token_id: &Nat, chain_id: u64, contract: &str, evm_token_id: Nat) {
+    let origin = normalized(chain_id, contract, evm_token_id);
+    EVM_ORIGINS.with(|o| o.borrow_mut().insert(token_id.to_string(), origin.clone()));
+    ICP_TOKENS.with(|t| t.borrow_mut().insert(origin, token_id.to_string()));
+}
+
+/// Drop both mappings of an ICP token that was burned or bridged back out
+pub fn forget_origin(token_id: &Nat) {
+    if let Some(origin) = EVM_ORIGINS.with(|o| o.borrow_mut().remove(&token_id.to_string())) {
+        ICP_TOKENS.with(|t| t.borrow_mut().remove(&origin));
+    }
+}
+
+#[query]
+pub fn get_icp_token_for_evm(chain_id: u64, contract: String, evm_token_id: Nat) -> Option<Nat> {
+    let origin = normalized(chain_id, &contract, evm_token_id);
+    ICP_TOKENS
+        .with(|t| t.borrow().get(&origin))
+        .and_then(|token_id| token_id.parse().ok())
+}
+
+#[query]
+pub fn get_evm_origin(token_id: Nat) -> Option<EvmOrigin> {
+    EVM_ORIGINS.with(|o| o.borrow().get(&token_id.to_string()))
+}
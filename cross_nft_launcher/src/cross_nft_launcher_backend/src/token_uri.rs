@@ -0,0 +1,417 @@
+use candid::{CandidType, Nat};
+use ethabi::{ParamType, Token};
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod,
+    HttpResponse as OutcallResponse, TransformArgs, TransformContext,
+};
+use ic_cdk::{query, update};
+use ic_stable_structures::StableCell;
+use serde::{Deserialize, Serialize};
+use serde_json::Value as Json;
+use std::cell::RefCell;
+
+use crate::certification::sha256;
+use crate::ensure_admin;
+use crate::erc721;
+use crate::evm_nft_indexer::ChainService;
+use crate::log;
+use crate::memory::{candid_storable, get_memory, Memory, TOKEN_URI_CONFIG_MEMORY_ID};
+use crate::Value;
+
+const TOKEN_URI_SELECTOR: [u8; 4] = [0xc8, 0x7b, 0x56, 0xdd]; // tokenURI(uint256)
+
+// Allowance for the URL and headers in the outcall cost estimate
+const REQUEST_BYTES_ESTIMATE: u128 = 1_024;
+
+/// Snapshotting of the off-chain JSON behind a bridged token's tokenURI
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct TokenUriConfig {
+    pub enabled: bool,
+    // ipfs:// URIs are fetched through this gateway, e.g. "https://ipfs.io/ipfs/"
+    pub ipfs_gateway: String,
+    pub max_response_bytes: u64,
+    // Read tokenURI at the block before the burn. Needs an archive RPC node; otherwise the
+    // event image is used, which is what the bridge contract sets as tokenURI
+    pub archive_reads: bool,
+}
+
+impl Default for TokenUriConfig {
+    fn default() -> Self {
+        TokenUriConfig {
+            enabled: false,
+            ipfs_gateway: "https://ipfs.io/ipfs/".to_string(),
+            max_response_bytes: 64 * 1024,
+            archive_reads: false,
+        }
+    }
+}
+
+candid_storable!(TokenUriConfig);
+
+thread_local! {
+    static TOKEN_URI_CONFIG: RefCell<StableCell<TokenUriConfig, Memory>> = RefCell::new(
+        StableCell::init(get_memory(TOKEN_URI_CONFIG_MEMORY_ID), TokenUriConfig::default())
+            .expect("failed to init tokenURI config")
+    );
+}
+
+/// HTTP GET used for snapshots; tests swap the outcall for a local mock responder
+trait HttpGet {
+    async fn get(&self, url: &str, max_response_bytes: u64) -> Result<(u16, Vec<u8>), String>;
+}
+
+/// HTTPS outcall from the canister
+struct Outcall;
+
+impl HttpGet for Outcall {
+    async fn get(&self, url: &str, max_response_bytes: u64) -> Result<(u16, Vec<u8>), String> {
+        let response = fetch(url, max_response_bytes).await?;
+        let status = u16::try_from(&response.status.0).unwrap_or(0);
+        Ok((status, response.body))
+    }
+}
+
+/// HTTPS URL to fetch for a tokenURI; other schemes would leave the subnet's replicas disagreeing or unable to connect
+fn resolve_url(uri: &str, ipfs_gateway: &str) -> Result<String, String> {
+    if uri.starts_with("https://") {
+        Ok(uri.to_string())
+    } else if let Some(path) = uri.strip_prefix("ipfs://") {
+        Ok(format!(
+            "{}/{}",
+            ipfs_gateway.trim_end_matches('/'),
+            path.trim_start_matches("ipfs/")
+        ))
+    } else {
+        Err(format!("Unsupported tokenURI scheme: {}", uri))
+    }
+}
+
+/// Cost of an outcall on a 13-node subnet
+fn outcall_cycles(max_response_bytes: u64) -> u128 {
+    let nodes: u128 = 13;
+    (3_000_000 + 60_000 * nodes) * nodes
+        + 400 * nodes * REQUEST_BYTES_ESTIMATE
+        + 800 * nodes * max_response_bytes as u128
+}
+
+/// The parts of an ERC-721 metadata document the bridge relies on; other fields pass through
+fn validate_document(body: &[u8]) -> Result<Json, String> {
+    let document: Json =
+        serde_json::from_slice(body).map_err(|e| format!("tokenURI is not JSON: {}", e))?;
+    let fields = document
+        .as_object()
+        .ok_or_else(|| "tokenURI JSON must be an object".to_string())?;
+    for key in [
+        "name",
+        "description",
+        "image",
+        "external_url",
+        "animation_url",
+    ] {
+        if fields.get(key).map_or(false, |value| !value.is_string()) {
+            return Err(format!("tokenURI field '{}' must be a string", key));
+        }
+    }
+    if let Some(attributes) = fields.get("attributes") {
+        let valid = attributes.as_array().map_or(false, |attributes| {
+            attributes
+                .iter()
+                .all(|attribute| attribute.is_object() && attribute.get("value").is_some())
+        });
+        if !valid {
+            return Err("tokenURI attributes must be objects with a value".to_string());
+        }
+    }
+    Ok(document)
+}
+
+/// Strip everything that differs between replicas so the outcall reaches consensus
+#[query]
+pub fn transform_token_uri(args: TransformArgs) -> OutcallResponse {
+    OutcallResponse {
+        status: args.response.status,
+        headers: vec![],
+        body: args.response.body,
+    }
+}
+
+async fn fetch(url: &str, max_response_bytes: u64) -> Result<OutcallResponse, String> {
+    let request = CanisterHttpRequestArgument {
+        url: url.to_string(),
+        max_response_bytes: Some(max_response_bytes),
+        method: HttpMethod::GET,
+        headers: vec![HttpHeader {
+            name: "Accept".to_string(),
+            value: "application/json".to_string(),
+        }],
+        body: None,
+        transform: Some(TransformContext::from_name(
+            "transform_token_uri".to_string(),
+            vec![],
+        )),
+    };
+    let (response,) = http_request(request, outcall_cycles(max_response_bytes))
+        .await
+        .map_err(|(code, msg)| format!("Fetching {} failed: {:?} {}", url, code, msg))?;
+    Ok(response)
+}
+
+/// Fetch and validate the document at `uri`, returning the token metadata entries for it
+async fn snapshot_entries(
+    http: &impl HttpGet,
+    uri: String,
+    config: &TokenUriConfig,
+) -> Result<Vec<(String, Value)>, String> {
+    let url = resolve_url(&uri, &config.ipfs_gateway)?;
+    let (status, body) = http.get(&url, config.max_response_bytes).await?;
+    if status != 200 {
+        return Err(format!("Fetching {} returned HTTP {}", url, status));
+    }
+    let document = validate_document(&body)?;
+    let snapshot = erc721::json_to_value(&document)?;
+    Ok(vec![
+        ("token_uri".to_string(), Value::Text(uri)),
+        (
+            "token_uri_sha256".to_string(),
+            Value::Blob(sha256(&body).to_vec()),
+        ),
+        ("token_uri_snapshot".to_string(), snapshot),
+    ])
+}
+
+impl ChainService {
+    /// tokenURI of a burned token, read at the block before its burn while it still existed;
+    /// needs an RPC node that keeps historical state
+    async fn token_uri(
+        &self,
+        chain_id: u64,
+        contract: &str,
+        token_id: &Nat,
+        burn_block: &Nat,
+    ) -> Result<String, String> {
+        let token_id = ethabi::ethereum_types::U256::from_dec_str(&token_id.0.to_string())
+            .map_err(|e| format!("Invalid token id {}: {}", token_id, e))?;
+        let mut call_data = TOKEN_URI_SELECTOR.to_vec();
+        call_data.extend(ethabi::encode(&[Token::Uint(token_id)]));
+        let block = u64::try_from(&burn_block.0)
+            .map_err(|_| format!("Invalid block number {}", burn_block))?
+            .saturating_sub(1);
+        let params = serde_json::json!([
+            {
+                "to": contract,
+                "data": format!("0x{}", hex::encode(call_data)),
+            },
+            format!("0x{:x}", block)
+        ]);
+
+        let result = self
+            .json_rpc_response(chain_id, "eth_call", params)
+            .await?
+            .map_err(|error| format!("tokenURI reverted: {}", error))?;
+        let bytes = hex::decode(result.as_str().unwrap_or_default().trim_start_matches("0x"))
+            .map_err(|e| format!("Invalid tokenURI result: {}", e))?;
+        match ethabi::decode(&[ParamType::String], &bytes)
+            .map_err(|e| format!("Invalid tokenURI result: {}", e))?
+            .pop()
+        {
+            Some(Token::String(uri)) => Ok(uri),
+            _ => Err("tokenURI did not return a string".to_string()),
+        }
+    }
+
+    /// Token metadata entries snapshotting the JSON at a bridged token's tokenURI. The URI comes
+    /// from the contract, or from `fallback_uri` (the NFT contract sets tokenURI to the image)
+    /// when the call fails. Empty when disabled or when the document cannot be fetched or validated.
+    pub async fn token_uri_snapshot(
+        &self,
+        chain_id: u64,
+        contract: &str,
+        token_id: &Nat,
+        burn_block: Option<&Nat>,
+        fallback_uri: &str,
+    ) -> Vec<(String, Value)> {
+        let config = get_token_uri_config();
+        if !config.enabled {
+            return vec![];
+        }
+        // After the burn tokenURI reverts, so only an archive read can still see it
+        let uri = match burn_block.filter(|_| config.archive_reads) {
+            Some(block) => match self.token_uri(chain_id, contract, token_id, block).await {
+                Ok(uri) if !uri.is_empty() => uri,
+                Ok(_) => fallback_uri.to_string(),
+                Err(e) => {
+                    log!(Warn, Indexer, "Using the event image as tokenURI: {}", e);
+                    fallback_uri.to_string()
+                }
+            },
+            None => fallback_uri.to_string(),
+        };
+
+        match snapshot_entries(&Outcall, uri.clone(), &config).await {
+            Ok(entries) => {
+                log!(Info, Indexer, "Snapshotted tokenURI {}", uri);
+                entries
+            }
+            Err(e) => {
+                log!(
+                    Warn,
+                    Indexer,
+                    "No tokenURI snapshot for token {}: {}",
+                    token_id,
+                    e
+                );
+                vec![]
+            }
+        }
+    }
+}
+
+#[update]
+pub fn set_token_uri_config(config: TokenUriConfig) -> Result<(), String> {
+    ensure_admin()?;
+    if !config.ipfs_gateway.starts_with("https://") {
+        return Err("ipfs_gateway must be an https URL".to_string());
+    }
+    if config.max_response_bytes == 0 || config.max_response_bytes > 2_000_000 {
+        return Err("max_response_bytes must be between 1 and 2000000".to_string());
+    }
+    TOKEN_URI_CONFIG
+        .with(|c| c.borrow_mut().set(config))
+        .map_err(|e| format!("Failed to store tokenURI config: {:?}", e))?;
+    Ok(())
+}
+
+#[query]
+pub fn get_token_uri_config() -> TokenUriConfig {
+    TOKEN_URI_CONFIG.with(|c| c.borrow().get().clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use std::future::Future;
+    use std::task::{Context, Poll, Waker};
+
+    /// Local mock responder: canned (status, body) per URL, enforcing the response limit
+    /// the way the outcall does
+    struct MockResponder(HashMap<String, (u16, Vec<u8>)>);
+
+    impl MockResponder {
+        fn new(routes: &[(&str, u16, &[u8])]) -> Self {
+            MockResponder(
+                routes
+                    .iter()
+                    .map(|(url, status, body)| (url.to_string(), (*status, body.to_vec())))
+                    .collect(),
+            )
+        }
+    }
+
+    impl HttpGet for MockResponder {
+        async fn get(&self, url: &str, max_response_bytes: u64) -> Result<(u16, Vec<u8>), String> {
+            let (status, body) = self
+                .0
+                .get(url)
+                .cloned()
+                .ok_or_else(|| format!("connection to {} refused", url))?;
+            if body.len() as u64 > max_response_bytes {
+                return Err(format!("response over {} bytes", max_response_bytes));
+            }
+            Ok((status, body))
+        }
+    }
+
+    // The mock never suspends, so one poll drives a snapshot to completion
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = std::pin::pin!(future);
+        match future
+            .as_mut()
+            .poll(&mut Context::from_waker(Waker::noop()))
+        {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("mock responder suspended"),
+        }
+    }
+
+    fn config() -> TokenUriConfig {
+        TokenUriConfig {
+            enabled: true,
+            ..TokenUriConfig::default()
+        }
+    }
+
+    fn entry<'a>(entries: &'a [(String, Value)], key: &str) -> &'a Value {
+        &entries.iter().find(|(k, _)| k == key).unwrap().1
+    }
+
+    #[test]
+    fn snapshots_https_document() {
+        let document = json!({
+            "name": "Genesis",
+            "image": "ipfs://bafy/1.png",
+            "attributes": [{ "trait_type": "forSale", "value": true }],
+        });
+        let body = document.to_string().into_bytes();
+        let http = MockResponder::new(&[("https://meta.example/1", 200, &body)]);
+
+        let entries = block_on(snapshot_entries(
+            &http,
+            "https://meta.example/1".to_string(),
+            &config(),
+        ))
+        .unwrap();
+
+        assert!(
+            matches!(entry(&entries, "token_uri"), Value::Text(uri) if uri == "https://meta.example/1")
+        );
+        assert!(
+            matches!(entry(&entries, "token_uri_sha256"), Value::Blob(hash) if hash[..] == sha256(&body))
+        );
+        assert_eq!(
+            erc721::value_to_json(entry(&entries, "token_uri_snapshot")),
+            document
+        );
+    }
+
+    #[test]
+    fn fetches_ipfs_through_gateway() {
+        let body = br#"{"name":"Pinned"}"#;
+        let http = MockResponder::new(&[("https://ipfs.io/ipfs/bafy/2.json", 200, body)]);
+
+        let entries = block_on(snapshot_entries(
+            &http,
+            "ipfs://ipfs/bafy/2.json".to_string(),
+            &config(),
+        ))
+        .unwrap();
+
+        assert!(
+            matches!(entry(&entries, "token_uri"), Value::Text(uri) if uri == "ipfs://ipfs/bafy/2.json")
+        );
+    }
+
+    #[test]
+    fn rejects_failed_or_invalid_responses() {
+        let oversize = vec![b' '; 64 * 1024 + 1];
+        let http = MockResponder::new(&[
+            ("https://meta.example/missing", 404, b"{}"),
+            ("https://meta.example/html", 200, b"<html></html>"),
+            ("https://meta.example/bad", 200, br#"{"name":7}"#),
+            ("https://meta.example/big", 200, &oversize),
+        ]);
+
+        for uri in [
+            "https://meta.example/missing",
+            "https://meta.example/html",
+            "https://meta.example/bad",
+            "https://meta.example/big",
+            "https://meta.example/unrouted",
+            "http://meta.example/plain",
+        ] {
+            let result = block_on(snapshot_entries(&http, uri.to_string(), &config()));
+            assert!(result.is_err(), "{} should not snapshot", uri);
+        }
+    }
+}
@@ -0,0 +1,90 @@
+use candid::{CandidType, Principal};
+use ic_cdk::api::id;
+use ic_cdk::update;
+use ic_stable_structures::StableCell;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+
+use crate::ensure_admin;
+use crate::memory::{candid_storable, get_memory, Memory, TRUSTED_ORIGINS_MEMORY_ID};
+
+// Production frontend (cross_nft_launcher_frontend on mainnet)
+const DEFAULT_TRUSTED_ORIGINS: [&str; 2] = [
+    "https://q53ev-2iaaa-aaaan-qz6jq-cai.icp0.io",
+    "https://q53ev-2iaaa-aaaan-qz6jq-cai.ic0.app",
+];
+// The dfx replica, trusted by default everywhere except the mainnet backend
+const LOCAL_TRUSTED_ORIGINS: [&str; 2] = ["http://localhost:4943", "http://127.0.0.1:4943"];
+const MAINNET_BACKEND_CANISTER_ID: &str = "q22cb-xqaaa-aaaan-qz6ja-cai";
+
+#[derive(CandidType, Deserialize, Clone, Debug, Serialize)]
+pub struct TrustedOriginsResponse {
+    pub trusted_origins: Vec<String>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug)]
+struct TrustedOrigins(Vec<String>);
+
+candid_storable!(TrustedOrigins);
+
+thread_local! {
+    static TRUSTED_ORIGINS: RefCell<StableCell<TrustedOrigins, Memory>> = RefCell::new(
+        StableCell::init(
+            get_memory(TRUSTED_ORIGINS_MEMORY_ID),
+            default_trusted_origins(),
+        )
+        .expect("failed to init trusted origins")
+    );
+}
+
+fn default_trusted_origins() -> TrustedOrigins {
+    let mut origins = DEFAULT_TRUSTED_ORIGINS.to_vec();
+    if Principal::from_text(MAINNET_BACKEND_CANISTER_ID).ok() != Some(id()) {
+        origins.extend_from_slice(&LOCAL_TRUSTED_ORIGINS);
+    }
+    TrustedOrigins(
+        origins
+            .into_iter()
+            .map(|origin| origin.to_string())
+            .collect(),
+    )
+}
+
+/// An origin is scheme://host[:port] with nothing after it
+fn validate_origin(origin: &str) -> Result<(), String> {
+    let rest = origin
+        .strip_prefix("https://")
+        .or_else(|| origin.strip_prefix("http://"))
+        .ok_or_else(|| format!("Origin '{}' must start with http:// or https://", origin))?;
+    if rest.is_empty() || rest.contains('/') {
+        return Err(format!(
+            "Origin '{}' must be scheme://host[:port] without a path",
+            origin
+        ));
+    }
+    Ok(())
+}
+
+/// Origins that signers may create delegations for; update call so the answer is certified
+#[update]
+pub fn icrc28_trusted_origins() -> TrustedOriginsResponse {
+    TrustedOriginsResponse {
+        trusted_origins: TRUSTED_ORIGINS.with(|origins| origins.borrow().get().0.clone()),
+    }
+}
+
+#[update]
+pub fn set_trusted_origins(origins: Vec<String>) -> Result<(), String> {
+    ensure_admin()?;
+    let mut unique: Vec<String> = Vec::new();
+    for origin in origins {
+        validate_origin(&origin)?;
+        if !unique.contains(&origin) {
+            unique.push(origin);
+        }
+    }
+    TRUSTED_ORIGINS
+        .with(|o| o.borrow_mut().set(TrustedOrigins(unique)))
+        .map_err(|e| format!("Failed to store trusted origins: {:?}", e))?;
+    Ok(())
+}